@@ -0,0 +1,165 @@
+use std::fmt::{self, Display};
+
+use crate::consts::F64_ELECTRON_MASS_IN_DALTONS;
+use crate::*;
+
+/// An ion formed from a molecule in the source of a mass spectrometer, as `[M+H]+` or `[2M-H]-`
+#[derive(Debug, Clone, PartialEq)]
+pub struct Adduct {
+    name: String,
+    /// Molecules in the ion, the 2 of `[2M+Na]+`
+    pub molecules: usize,
+    /// The formulas added and lost (with a negative count), the `+Na` and `-H2O` of the name
+    pub terms: Vec<(isize, EmpiricalFormula)>,
+    pub charge: i8,
+}
+
+impl Adduct {
+    /// It panics if the charge is 0, the ion must be seen by the spectrometer
+    pub fn new(name: &str, molecules: usize, terms: Vec<(isize, EmpiricalFormula)>, charge: i8) -> Adduct {
+        assert!(charge != 0, "the adduct {} has no charge", name);
+        Adduct { name: name.to_string(), molecules, terms, charge }
+    }
+
+    /// Read the usual notation, as `[M+H]+`, `[M+2H]2+`, `[2M+Na]+` or `[M-H2O+H]+`
+    pub fn from_string(string: &str) -> Result<Adduct, ParserError> {
+        let whole = string.as_bytes();
+        let error = |rest: &[u8], reason| -> ParserError { ParseError::new(whole, rest, reason).into() };
+        if whole.first() != Some(&b'[') {
+            return Err(error(whole, if whole.is_empty() { ParseErrorReason::UnexpectedEnd } else { ParseErrorReason::UnexpectedCharacter }));
+        }
+        let mut string = &whole[1..];
+        let mut molecules = 1;
+        if string.first().is_some_and(|c| c.is_ascii_digit()) {
+            let (n, s) = checked_parse_number(string);
+            molecules = match n {
+                Some(n) if n > 0 => n,
+                _ => return Err(error(string, ParseErrorReason::UnexpectedCharacter))
+            };
+            string = s;
+        }
+        match string.first() {
+            Some(b'M') => string = &string[1..],
+            Some(_) => return Err(error(string, ParseErrorReason::UnexpectedCharacter)),
+            None => return Err(error(string, ParseErrorReason::UnexpectedEnd))
+        }
+        let mut terms = Vec::new();
+        loop {
+            let sign = match string.first() {
+                Some(b']') => break,
+                Some(b'+') => 1,
+                Some(b'-') => -1,
+                Some(_) => return Err(error(string, ParseErrorReason::UnexpectedCharacter)),
+                None => return Err(error(string, ParseErrorReason::UnexpectedEnd))
+            };
+            string = &string[1..];
+            let mut count = 1;
+            if string.first().is_some_and(|c| c.is_ascii_digit()) {
+                let (n, s) = checked_parse_number(string);
+                count = match n {
+                    Some(n) if n > 0 && n <= isize::MAX as usize => n,
+                    _ => return Err(error(string, ParseErrorReason::UnexpectedCharacter))
+                };
+                string = s;
+            }
+            let end = string.iter().position(|c| !c.is_ascii_alphanumeric()).unwrap_or(string.len());
+            if end == 0 {
+                return Err(error(string, if string.is_empty() { ParseErrorReason::UnexpectedEnd } else { ParseErrorReason::UnexpectedCharacter }));
+            }
+            let offset = whole.len() - string.len();
+            let formula = match EmpiricalFormula::from_string(std::str::from_utf8(&string[..end]).unwrap()) {
+                Ok(formula) => formula,
+                Err(ParserError::Parse(e)) => return Err(e.shifted(offset).into()),
+                Err(e) => return Err(e)
+            };
+            terms.push((sign * count as isize, formula));
+            string = &string[end..];
+        }
+        string = &string[1..];
+        let mut charge = 1;
+        if string.first().is_some_and(|c| c.is_ascii_digit()) {
+            let (n, s) = checked_parse_number(string);
+            charge = match n {
+                Some(n) if n > 0 && n <= i8::MAX as usize => n as i8,
+                _ => return Err(error(string, ParseErrorReason::BadCharge))
+            };
+            string = s;
+        }
+        match string {
+            b"+" => {},
+            b"-" => charge = -charge,
+            [] => return Err(error(string, ParseErrorReason::UnexpectedEnd)),
+            _ => return Err(error(string, ParseErrorReason::BadCharge))
+        }
+        Ok(Adduct { name: std::str::from_utf8(whole).unwrap().to_string(), molecules, terms, charge })
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// m/z of the ion from the mass of the molecule with all its electrons and its net charge,
+    /// as `[M+H]+` of a quaternary ammonium is a 2+ ion, `None` if the ion would be neutral
+    pub fn mz_from_mass(&self, mass: f64, charge: isize) -> Option<f64> {
+        let charge = self.charge as isize + self.molecules as isize * charge;
+        if charge == 0 {
+            return None;
+        }
+        let added: f64 = self.terms.iter().map(|(count, formula)| *count as f64 * formula.get_monoisotopic_mass()).sum();
+        let mass = self.molecules as f64 * mass + added - charge as f64 * F64_ELECTRON_MASS_IN_DALTONS;
+        Some(mass / (charge as f64).abs())
+    }
+
+    /// m/z of the ion of a molecule, a compound or a formula, from its exact mass and its charge
+    pub fn mz<M: BasicMolecule>(&self, molecule: &M) -> Option<f64> {
+        self.mz_from_mass(molecule.get_exact_mass(), molecule.get_charge())
+    }
+}
+
+impl Display for Adduct {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.name)
+    }
+}
+
+/// The adducts to look for, the default table has the common ones of ESI and MALDI and more can be added
+#[derive(Debug, Clone, PartialEq)]
+pub struct AdductTable {
+    adducts: Vec<Adduct>,
+}
+
+const COMMON_ADDUCTS: [&str; 22] = [
+    "[M]+", "[M+H]+", "[M+Na]+", "[M+K]+", "[M+Li]+", "[M+NH4]+", "[M+H-H2O]+", "[M+2H]2+", "[M+3H]3+", "[M+H+Na]2+",
+    "[2M+H]+", "[2M+Na]+", "[M]-", "[M-H]-", "[M+Cl]-", "[M+Br]-", "[M+HCOO]-", "[M+CH3COO]-", "[M-H-H2O]-",
+    "[M-2H]2-", "[M+Na-2H]-", "[2M-H]-",
+];
+
+impl AdductTable {
+    pub fn new() -> AdductTable {
+        AdductTable { adducts: Vec::new() }
+    }
+
+    pub fn add(&mut self, adduct: Adduct) {
+        self.adducts.push(adduct);
+    }
+
+    pub fn get(&self, name: &str) -> Option<&Adduct> {
+        self.adducts.iter().find(|a| a.name == name)
+    }
+
+    pub fn iter(&self) -> std::slice::Iter<'_, Adduct> {
+        self.adducts.iter()
+    }
+
+    /// m/z of every adduct of the molecule that is an ion
+    pub fn mz_values<M: BasicMolecule>(&self, molecule: &M) -> Vec<(&Adduct, f64)> {
+        let (mass, charge) = (molecule.get_exact_mass(), molecule.get_charge());
+        self.adducts.iter().filter_map(|a| a.mz_from_mass(mass, charge).map(|mz| (a, mz))).collect()
+    }
+}
+
+impl Default for AdductTable {
+    fn default() -> AdductTable {
+        AdductTable { adducts: COMMON_ADDUCTS.iter().map(|a| Adduct::from_string(a).unwrap()).collect() }
+    }
+}
@@ -1,7 +1,9 @@
 use std::iter::Iterator;
-use std::ops::{Index, IndexMut};
+use std::ops::{Add, Index, IndexMut, Mul, Sub};
 use std::fmt::{self, Display};
 use std::collections::BTreeMap;
+use std::cmp::Ordering;
+use std::hash::{Hash, Hasher};
 
 use ptable::Element;
 
@@ -9,9 +11,25 @@ use crate::*;
 
 pub trait BasicMolecule {
     fn get_molecular_weight(&self) -> f32;
+
+    /// Mass with the most abundant isotope of every element
+    fn get_monoisotopic_mass(&self) -> f64;
+
+    /// Mass with the isotopes of the labeled atoms and the most abundant isotope of the others
+    fn get_exact_mass(&self) -> f64 {
+        self.get_monoisotopic_mass()
+    }
+
+    /// Mass averaged over the natural abundances of the isotopes
+    fn get_average_mass(&self) -> f64;
+
+    /// Net formal charge, the formulas are neutral
+    fn get_charge(&self) -> isize {
+        0
+    }
 }
 
-#[derive(Debug, Clone, Ord, PartialOrd, Hash)]
+#[derive(Debug, Clone)]
 pub struct EmpiricalFormula(Vec<(Element, usize)>);
 
 impl EmpiricalFormula {
@@ -19,21 +37,22 @@ impl EmpiricalFormula {
         EmpiricalFormula(formula)
     }
 
-    pub fn from_string(string: &str) -> Result<EmpiricalFormula, ()> {
-        let mut string = string.as_bytes();
+    pub fn from_string(string: &str) -> Result<EmpiricalFormula, ParserError> {
+        let whole = string.as_bytes();
+        let mut string = whole;
         let mut elements = Vec::new();
         if string.is_empty() {
-            return Err(());
+            return Err(ParseError::new(whole, string, ParseErrorReason::UnexpectedEnd).into());
         }
         while !string.is_empty() {
             if !string[0].is_ascii_uppercase() {
-                return Err(());
+                return Err(ParseError::new(whole, string, ParseErrorReason::UnexpectedCharacter).into());
             }
             let (e, s) = parse_element(string);
             let e = match e {
                 Some(e) => e,
                 None => {
-                    return Err(());
+                    return Err(ParseError::new(whole, string, ParseErrorReason::UnknownElement).into());
                 },
             };
             let mut count = 1;
@@ -81,6 +100,95 @@ impl EmpiricalFormula {
     pub unsafe fn get_unchecked_mut(&mut self, i: usize) -> &mut (Element, usize) {
         self.0.get_unchecked_mut(i)
     }
+
+    /// Atoms of every element, the repeated elements are added together
+    fn counts(&self) -> BTreeMap<Element, usize> {
+        let mut counts = BTreeMap::new();
+        for (e, n) in self.0.iter().filter(|(_, n)| *n > 0) {
+            *counts.entry(*e).or_insert(0) += *n;
+        }
+        counts
+    }
+
+    /// The same formula with every element once, as `C2H6` for `CH3CH3`, in the order of the atomic numbers
+    pub fn normalized(&self) -> EmpiricalFormula {
+        EmpiricalFormula(self.counts().into_iter().collect())
+    }
+
+    pub fn count(&self, element: Element) -> usize {
+        self.0.iter().filter(|(e, _)| *e == element).map(|(_, n)| *n).sum()
+    }
+
+    /// If the formula has at least the atoms of the other one
+    pub fn contains(&self, other: &EmpiricalFormula) -> bool {
+        other.counts().iter().all(|(e, n)| self.count(*e) >= *n)
+    }
+
+    /// The atoms of the formula without the ones of the other, `None` if some are missing
+    pub fn checked_sub(&self, other: &EmpiricalFormula) -> Option<EmpiricalFormula> {
+        let mut counts = self.counts();
+        for (e, n) in other.counts() {
+            let count = counts.get_mut(&e)?;
+            *count = count.checked_sub(n)?;
+        }
+        Some(EmpiricalFormula(counts.into_iter().filter(|(_, n)| *n > 0).collect()))
+    }
+}
+
+impl<'a> Add for &'a EmpiricalFormula {
+    type Output = EmpiricalFormula;
+
+    fn add(self, other: &'a EmpiricalFormula) -> EmpiricalFormula {
+        let mut counts = self.counts();
+        for (e, n) in other.counts() {
+            *counts.entry(e).or_insert(0) += n;
+        }
+        EmpiricalFormula(counts.into_iter().collect())
+    }
+}
+
+impl Add for EmpiricalFormula {
+    type Output = EmpiricalFormula;
+
+    fn add(self, other: EmpiricalFormula) -> EmpiricalFormula {
+        &self + &other
+    }
+}
+
+/// It panics if the atoms of the other formula are not in this one, see `checked_sub`
+impl<'a> Sub for &'a EmpiricalFormula {
+    type Output = EmpiricalFormula;
+
+    fn sub(self, other: &'a EmpiricalFormula) -> EmpiricalFormula {
+        match self.checked_sub(other) {
+            Some(formula) => formula,
+            None => panic!("{} does not contain {}", self, other)
+        }
+    }
+}
+
+impl Sub for EmpiricalFormula {
+    type Output = EmpiricalFormula;
+
+    fn sub(self, other: EmpiricalFormula) -> EmpiricalFormula {
+        &self - &other
+    }
+}
+
+impl Mul<usize> for &EmpiricalFormula {
+    type Output = EmpiricalFormula;
+
+    fn mul(self, times: usize) -> EmpiricalFormula {
+        EmpiricalFormula(self.counts().into_iter().map(|(e, n)| (e, n * times)).filter(|(_, n)| *n > 0).collect())
+    }
+}
+
+impl Mul<usize> for EmpiricalFormula {
+    type Output = EmpiricalFormula;
+
+    fn mul(self, times: usize) -> EmpiricalFormula {
+        &self * times
+    }
 }
 
 impl BasicMolecule for EmpiricalFormula {
@@ -91,6 +199,14 @@ impl BasicMolecule for EmpiricalFormula {
         }
         weight
     }
+
+    fn get_monoisotopic_mass(&self) -> f64 {
+        self.0.iter().map(|(e, c)| masses::monoisotopic_mass(*e) * *c as f64).sum()
+    }
+
+    fn get_average_mass(&self) -> f64 {
+        self.0.iter().map(|(e, c)| masses::average_mass(*e) * *c as f64).sum()
+    }
 }
 
 impl Index<usize> for EmpiricalFormula {
@@ -107,12 +223,21 @@ impl IndexMut<usize> for EmpiricalFormula {
     }
 }
 
+/// The formula in the Hill order: the carbons, the hydrogens and the rest alphabetically, or all
+/// alphabetically if there is no carbon
 impl Display for EmpiricalFormula {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        for e in self.0.iter() {
-            write!(f, "{}", e.0.get_symbol())?;
-            if e.1 != 1 {
-                write!(f, "{}", e.1)?;
+        let mut counts: Vec<(Element, usize)> = self.counts().into_iter().collect();
+        let carbon = counts.iter().any(|(e, _)| *e == Element::Carbon);
+        counts.sort_by_key(|(e, _)| match e {
+            Element::Carbon if carbon => (0, ""),
+            Element::Hydrogen if carbon => (1, ""),
+            e => (2, e.get_symbol())
+        });
+        for (e, n) in counts {
+            write!(f, "{}", e.get_symbol())?;
+            if n != 1 {
+                write!(f, "{}", n)?;
             }
         }
         Ok(())
@@ -136,45 +261,45 @@ impl<'a> Iterator for EmpiricalFormulaIterator<'a> {
     }
 }
 
+/// The formulas are equal if they have the same atoms, in any order
 impl PartialEq for EmpiricalFormula {
     fn eq(&self, other: &Self) -> bool {
-        let mut check = vec![false; self.0.len()];
-        for (e, n) in other.0.iter() {
-            let mut exist = false;
-            for (i, (_e, _n)) in self.0.iter().enumerate() {
-                if e == _e {
-                    if n != _n {
-                        return false;
-                    }
-                    check[i] = true;
-                    exist = true;
-                    break;
-                }
-            }
-            if !exist {
-                return false;
-            }
-        }
-        for c in check {
-            if c == false {
-                return false;
-            }
-        }
-        true
+        self.counts() == other.counts()
     }
 }
 
 impl Eq for EmpiricalFormula {}
 
+/// Consistent with the equality, the order of the atoms doesn't change the hash
+impl Hash for EmpiricalFormula {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.counts().hash(state);
+    }
+}
+
+/// Consistent with the equality, the formulas are compared by their counts in the order of the atomic numbers
+impl Ord for EmpiricalFormula {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.counts().cmp(&other.counts())
+    }
+}
+
+impl PartialOrd for EmpiricalFormula {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
 #[derive(Debug, Clone, Eq, PartialEq, Ord, PartialOrd, Hash)]
 pub enum ElementOrGroup {
     Element(Element),
     Group(MolecularFormula),
 }
 
-fn parse_group(mut string: &[u8]) -> Result<(MolecularFormula, &[u8]), ()> {
+// whole = The complete formula, to give the position of the errors
+fn parse_group<'a>(whole: &[u8], opening: &[u8], mut string: &'a [u8]) -> Result<(MolecularFormula, &'a [u8]), ParseError> {
     if string.is_empty() {
-        return Err(());
+        return Err(ParseError::new(whole, opening, ParseErrorReason::UnmatchedParenthesis));
     }
     let mut result = Vec::new();
     loop {
@@ -183,19 +308,19 @@ fn parse_group(mut string: &[u8]) -> Result<(MolecularFormula, &[u8]), ()> {
             break;
         }
         let e = if string[0] == b'(' {
-            let (g, s) = parse_group(&string[1..])?;
+            let (g, s) = parse_group(whole, string, &string[1..])?;
             string = s;
             ElementOrGroup::Group(g)
         }
         else {
             if !string[0].is_ascii_uppercase() {
-                return Err(());
+                return Err(ParseError::new(whole, string, ParseErrorReason::UnexpectedCharacter));
             }
             let (e, s) = parse_element(string);
             let e = match e {
                 Some(e) => e,
                 None => {
-                    return Err(());
+                    return Err(ParseError::new(whole, string, ParseErrorReason::UnknownElement));
                 },
             };
             string = s;
@@ -203,7 +328,7 @@ fn parse_group(mut string: &[u8]) -> Result<(MolecularFormula, &[u8]), ()> {
         };
         let mut count = 1;
         if string.is_empty() {
-            return Err(());
+            return Err(ParseError::new(whole, opening, ParseErrorReason::UnmatchedParenthesis));
         }
         if string[0].is_ascii_digit() {
             let (c, s) = parse_number(string);
@@ -211,7 +336,7 @@ fn parse_group(mut string: &[u8]) -> Result<(MolecularFormula, &[u8]), ()> {
             result.push((e, count));
             string = s;
             if string.is_empty() {
-                return Err(());
+                return Err(ParseError::new(whole, opening, ParseErrorReason::UnmatchedParenthesis));
             }
         }
         else {
@@ -229,27 +354,31 @@ impl MolecularFormula {
         MolecularFormula(formula)
     }
 
-    pub fn from_string(string: &str) -> Result<MolecularFormula, ()> {
-        let mut string = string.as_bytes();
+    pub fn from_string(string: &str) -> Result<MolecularFormula, ParserError> {
+        let whole = string.as_bytes();
+        let mut string = whole;
         let mut result = Vec::new();
         if string.is_empty() {
-            return Err(());
+            return Err(ParseError::new(whole, string, ParseErrorReason::UnexpectedEnd).into());
         }
         while !string.is_empty() {
             let e = if string[0] == b'(' {
-                let (g, s) = parse_group(&string[1..])?;
+                let (g, s) = parse_group(whole, string, &string[1..])?;
                 string = s;
                 ElementOrGroup::Group(g)
             }
             else {
                 if !string[0].is_ascii_uppercase() {
-                    return Err(());
+                    if string[0] == b')' {
+                        return Err(ParseError::new(whole, string, ParseErrorReason::UnmatchedParenthesis).into());
+                    }
+                    return Err(ParseError::new(whole, string, ParseErrorReason::UnexpectedCharacter).into());
                 }
                 let (e, s) = parse_element(string);
                 let e = match e {
                     Some(e) => e,
                     None => {
-                        return Err(());
+                        return Err(ParseError::new(whole, string, ParseErrorReason::UnknownElement).into());
                     },
                 };
                 string = s;
@@ -323,6 +452,14 @@ impl MolecularFormula {
         EmpiricalFormula::new(res)
     }
 
+    /// Sum of the masses of the elements, the groups are multiplied by their count
+    fn mass(&self, element_mass: &dyn Fn(Element) -> f64) -> f64 {
+        self.0.iter().map(|(i, c)| *c as f64 * match i {
+            ElementOrGroup::Element(e) => element_mass(*e),
+            ElementOrGroup::Group(g) => g.mass(element_mass)
+        }).sum()
+    }
+
     fn get_empirical_formula_optimize(&self, empirical: &mut BTreeMap<Element, usize>) {
         for a in self.0.iter() {
             match a.0 {
@@ -352,6 +489,14 @@ impl BasicMolecule for MolecularFormula {
         }
         weight
     }
+
+    fn get_monoisotopic_mass(&self) -> f64 {
+        self.mass(&masses::monoisotopic_mass)
+    }
+
+    fn get_average_mass(&self) -> f64 {
+        self.mass(&masses::average_mass)
+    }
 }
 
 impl Index<usize> for MolecularFormula {
@@ -18,6 +18,8 @@ pub const F32_ATOMIC_MASS_CONSTANT_ENERGY_EQUIVALENT: f32 = 1.49241808560e-10;
 pub const F32_ATOMIC_MASS_CONSTANT_ENERGY_EQUIVALENT_IN_MEV: f32 = 931.49410242;
 /// C/mol-1
 pub const F32_FARADAY_CONSTANT: f32 = 96485.33212;
+/// Da
+pub const F32_ELECTRON_MASS_IN_DALTONS: f32 = 5.485799e-4;
 
 /// m/s-2
 pub const F64_SPEED_OF_GRAVITY: f64 = 9.80665;
@@ -38,4 +40,6 @@ pub const F64_ATOMIC_MASS_CONSTANT_ENERGY_EQUIVALENT: f64 = 1.49241808560e-10;
 /// MeV
 pub const F64_ATOMIC_MASS_CONSTANT_ENERGY_EQUIVALENT_IN_MEV: f64 = 931.49410242;
 /// C/mol-1
-pub const F64_FARADAY_CONSTANT: f64 = 96485.33212;
\ No newline at end of file
+pub const F64_FARADAY_CONSTANT: f64 = 96485.33212;
+/// Da
+pub const F64_ELECTRON_MASS_IN_DALTONS: f64 = 5.48579909065e-4;
\ No newline at end of file
@@ -1,6 +1,7 @@
 use ptable::Element;
 use std::num::NonZeroU8;
 use crate::ion::Ion;
+use crate::masses;
 
 
 #[derive(Debug, Copy, Clone, Eq, PartialEq, Ord, PartialOrd, Hash)]
@@ -47,6 +48,19 @@ impl Isotope {
     pub fn get_element_mut(&mut self) -> &mut Element {
         self.ion.get_element_mut()
     }
+
+    /// Protons and neutrons of a labeled isotope
+    pub fn get_mass_number(&self) -> Option<usize> {
+        self.neutrons.map(|n| n.get() as usize + self.get_element().get_atomic_number())
+    }
+
+    /// Mass of the labeled isotope, or the monoisotopic mass of the element
+    pub fn get_exact_mass(&self) -> f64 {
+        match self.get_mass_number() {
+            Some(mass_number) => masses::isotope_mass(*self.get_element(), mass_number),
+            None => masses::monoisotopic_mass(*self.get_element())
+        }
+    }
 }
 
 impl From<Element> for Isotope {
@@ -0,0 +1,121 @@
+use ptable::Element;
+
+use crate::consts::F64_ELECTRON_MASS_IN_DALTONS;
+use crate::masses::{self, NaturalIsotope};
+use crate::EmpiricalFormula;
+
+/// Peaks closer than this are the same peak of the fine structure
+const SAME_PEAK: f64 = 1e-6;
+
+/// A peak of an isotope pattern, the mass is the m/z for the charged ions
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Peak {
+    pub mass: f64,
+    pub intensity: f64,
+}
+
+/// A term of the polynomial of the abundances, the nominal mass tells which peaks can be merged
+#[derive(Debug, Copy, Clone)]
+struct Term {
+    nominal: usize,
+    mass: f64,
+    intensity: f64,
+}
+
+/// Theoretical isotopic distribution of a formula, computed as the product of the polynomials of
+/// the natural abundances of its elements. The intensities are relative to the highest peak
+#[derive(Debug, Clone, PartialEq)]
+pub struct IsotopePattern {
+    peaks: Vec<Peak>,
+}
+
+impl IsotopePattern {
+    /// Every combination of isotopes with a different mass, as seen at a very high resolution.
+    /// The peaks below `threshold` times the highest one are pruned. An ion is given by its
+    /// complete formula, with the atoms of the adduct, and its charge
+    pub fn fine_structure(formula: &EmpiricalFormula, charge: i8, threshold: f64) -> IsotopePattern {
+        IsotopePattern::new(formula, charge, threshold, true)
+    }
+
+    /// The peaks M, M+1, M+2... with the isotopes of the same nominal mass merged, their mass is
+    /// the mean weighted by the intensities
+    pub fn aggregated(formula: &EmpiricalFormula, charge: i8, threshold: f64) -> IsotopePattern {
+        IsotopePattern::new(formula, charge, threshold, false)
+    }
+
+    fn new(formula: &EmpiricalFormula, charge: i8, threshold: f64, fine: bool) -> IsotopePattern {
+        let mut terms = vec![Term { nominal: 0, mass: 0.0, intensity: 1.0 }];
+        for (element, count) in formula.iter() {
+            let power = element_power(*element, *count, threshold, fine);
+            terms = multiply(&terms, &power, threshold, fine);
+        }
+        let highest = terms.iter().map(|t| t.intensity).fold(0.0, f64::max);
+        let mut peaks: Vec<Peak> = terms.iter().map(|t| {
+            let mut mass = t.mass - charge as f64 * F64_ELECTRON_MASS_IN_DALTONS;
+            if charge != 0 {
+                mass /= (charge as f64).abs();
+            }
+            Peak { mass, intensity: t.intensity / highest }
+        }).collect();
+        peaks.sort_by(|a, b| a.mass.partial_cmp(&b.mass).unwrap());
+        IsotopePattern { peaks }
+    }
+
+    /// The peaks from the lowest mass to the highest
+    pub fn peaks(&self) -> &[Peak] {
+        &self.peaks
+    }
+
+    /// The highest peak, it has an intensity of 1
+    pub fn base_peak(&self) -> Option<&Peak> {
+        self.peaks.iter().find(|p| p.intensity == 1.0)
+    }
+}
+
+/// Distribution of `count` atoms of the element, by squaring
+fn element_power(element: Element, mut count: usize, threshold: f64, fine: bool) -> Vec<Term> {
+    let natural: Vec<&NaturalIsotope> = masses::isotopes_of(element).iter().filter(|i| i.abundance > 0.0).collect();
+    let mut base: Vec<Term> = natural.iter()
+        .map(|i| Term { nominal: i.mass_number as usize, mass: i.mass, intensity: i.abundance }).collect();
+    // The elements that are not found in nature only have their most stable isotope
+    if base.is_empty() {
+        let mass = masses::monoisotopic_mass(element);
+        base.push(Term { nominal: mass.round() as usize, mass, intensity: 1.0 });
+    }
+    let mut result = vec![Term { nominal: 0, mass: 0.0, intensity: 1.0 }];
+    while count > 0 {
+        if count % 2 == 1 {
+            result = multiply(&result, &base, threshold, fine);
+        }
+        count /= 2;
+        if count > 0 {
+            base = multiply(&base, &base, threshold, fine);
+        }
+    }
+    result
+}
+
+/// Product of two distributions, with the equal peaks merged and the small ones pruned
+fn multiply(a: &[Term], b: &[Term], threshold: f64, fine: bool) -> Vec<Term> {
+    let mut product = Vec::with_capacity(a.len() * b.len());
+    for x in a.iter() {
+        for y in b.iter() {
+            product.push(Term { nominal: x.nominal + y.nominal, mass: x.mass + y.mass, intensity: x.intensity * y.intensity });
+        }
+    }
+    product.sort_by(|x, y| x.nominal.cmp(&y.nominal).then(x.mass.partial_cmp(&y.mass).unwrap()));
+    let mut merged: Vec<Term> = Vec::new();
+    for term in product {
+        match merged.last_mut() {
+            Some(last) if last.nominal == term.nominal && (!fine || (term.mass - last.mass).abs() < SAME_PEAK) => {
+                let intensity = last.intensity + term.intensity;
+                last.mass = (last.mass * last.intensity + term.mass * term.intensity) / intensity;
+                last.intensity = intensity;
+            },
+            _ => merged.push(term)
+        }
+    }
+    let highest = merged.iter().map(|t| t.intensity).fold(0.0, f64::max);
+    merged.retain(|t| t.intensity >= highest * threshold);
+    merged
+}
@@ -1,10 +1,11 @@
-use std::mem;
-
 use ptable::Element;
 
 mod ion;
 mod isotope;
 mod basic_formulas;
+mod masses;
+mod isotope_pattern;
+mod adduct;
 mod structural;
 pub mod ra;
 pub mod consts;
@@ -13,6 +14,9 @@ pub use ion::*;
 pub use isotope::*;
 pub use structural::*;
 pub use basic_formulas::*;
+pub use masses::*;
+pub use isotope_pattern::{IsotopePattern, Peak};
+pub use adduct::{Adduct, AdductTable};
 pub use crate::ra::Point;
 
 fn parse_element(string: &[u8]) -> (Option<Element>, &[u8]) {
@@ -39,6 +43,12 @@ fn parse_number(string: &[u8]) -> (usize, &[u8]) {
     (result, &string[end..])
 }
 
+/// Like `parse_number`, but `None` if the number doesn't fit in an `usize`
+fn checked_parse_number(string: &[u8]) -> (Option<usize>, &[u8]) {
+    let end = string.iter().position(|c| !c.is_ascii_digit()).unwrap_or(string.len());
+    (std::str::from_utf8(&string[..end]).ok().and_then(|n| n.parse().ok()), &string[end..])
+}
+
 pub trait AdvancedFormula {
     fn get_empirical_formula(&self) -> EmpiricalFormula;
 }
@@ -57,6 +67,7 @@ impl BondClass for QuantumBondKind {}
 mod tests {
     use crate::*;
     use std::fs::File;
+    use std::num::NonZeroU8;
 
     #[test]
     fn smiles_test() {
@@ -77,6 +88,1331 @@ mod tests {
         test!("C1=CC(=CC=C1C(=O)O)C(=O)O.C1=CC(=CC=C1N)N", "C14H14N2O4"); // Poly(p-phenylene terephthalamide)
         test!("C[N+](C)(C)CCOP(=O)([O-])OCC(COC(=O)CCCCCCCCCCCOC(=O)CCCCC1CSSC1)OC(=O)CCCCCCCCCCCOC(=O)CCCCC2CSSC2", "C48H88NO12PS4"); // Dilipoyl lipid
         test!("[2H]", "H"); // Deuterium
+        test!("c1ccsc1", "C4H4S"); // Thiophene
+        test!("c1ccoc1", "C4H4O"); // Furan
+        test!("Cn1cccc1", "C5H7N"); // N-methylpyrrole
+        test!("c1ccncc1", "C5H5N"); // Pyridine
+        test!("Cn1cnc2c1c(=O)n(C)c(=O)n2C", "C8H10N4O2"); // Caffeine
+    }
+
+    #[test]
+    fn smiles_writer_test() {
+        macro_rules! test {
+            ($smiles:expr, $expected:expr) => {
+                let molecule = Molecule::from_smiles($smiles).unwrap();
+                let written = molecule.to_smiles();
+                assert_eq!(written, $expected);
+                let reparsed = Molecule::from_smiles(&written).unwrap();
+                assert_eq!(reparsed.to_smiles(), written);
+                assert_eq!(reparsed.atoms.len(), molecule.atoms.len());
+                assert_eq!(reparsed.bonds.len(), molecule.bonds.len());
+            };
+        }
+        test!("O", "O"); // Aqua
+        test!("OS(=O)(=O)O", "OS(=O)(=O)O"); // Sulfuric acid
+        test!("c1ccccc1", "c1ccccc1"); // Benzene
+        test!("c1ccsc1", "c1ccsc1"); // Thiophene
+        test!("Cn1cccc1", "Cn1cccc1"); // N-methylpyrrole
+        test!("CC(O)=O", "CC(O)=O"); // Acetic acid
+        test!("CCOC(=O)C(=C)C#N", "CCOC(=O)C(=C)C#N"); // Ethyl 2-cyanoacrylate
+        test!("C1CC2CCC1CC2", "C1CC2CCC1CC2"); // Bicyclo[2.2.2]octane
+        test!("[2H]", "[2H]"); // Deuterium
+        test!("[O-]C(=O)C", "[O-]C(=O)C"); // Acetate
+        test!("c1cc[n+](C)cc1", "c1cc[n+](C)cc1"); // N-methylpyridinium
+        let compound = Compound::from_smiles("[Na+].[Cl-]").unwrap();
+        assert_eq!(compound.to_smiles(), "[Na+].[Cl-]");
+    }
+
+    #[test]
+    fn canonical_smiles_test() {
+        macro_rules! test {
+            ($($smiles:expr),+) => {
+                let all = [$(Molecule::from_smiles($smiles).unwrap().to_canonical_smiles()),+];
+                for written in all.iter() {
+                    assert_eq!(written, &all[0]);
+                    assert_eq!(&Molecule::from_smiles(written).unwrap().to_canonical_smiles(), written);
+                }
+            };
+        }
+        test!("CCO", "OCC", "C(O)C"); // Ethanol
+        test!("CC(O)=O", "OC(C)=O", "O=C(O)C"); // Acetic acid
+        test!("c1ccccc1", "c1ccccc1"); // Benzene
+        test!("Cc1ccccc1", "c1ccc(C)cc1", "c1cc(C)ccc1"); // Toluene
+        test!("OS(=O)(=O)O", "O=S(O)(=O)O"); // Sulfuric acid
+        test!("C1CC2CCC1CC2", "C1CC2CCC1CC2", "C12CCC(CC1)CC2"); // Bicyclo[2.2.2]octane
+        test!("[O-]C(=O)C", "CC([O-])=O"); // Acetate
+        test!("CCOC(=O)C(=C)C#N", "N#CC(=C)C(=O)OCC"); // Ethyl 2-cyanoacrylate
+        assert_ne!(Molecule::from_smiles("CCO").unwrap().to_canonical_smiles(), 
+            Molecule::from_smiles("COC").unwrap().to_canonical_smiles());
+        // The same molecule with the atoms and the bonds in the reverse order
+        let molecule = Molecule::from_smiles("CC(=O)Nc1ccc(O)cc1").unwrap(); // Paracetamol
+        let n = molecule.atoms.len();
+        let mut reversed = Molecule { atoms: Vec::new(), bonds: Vec::new(), coords: None };
+        for atom in molecule.atoms.iter().rev() {
+            reversed.atoms.push(AtomAndBondI::new(atom.atom, Vec::new()));
+        }
+        for bond in molecule.bonds.iter().rev() {
+            let id = reversed.bonds.len();
+            reversed.atoms[n - 1 - bond.a].bonds.push(id);
+            reversed.atoms[n - 1 - bond.b].bonds.push(id);
+            reversed.bonds.push(Bond::new(n - 1 - bond.b, n - 1 - bond.a, bond.k));
+        }
+        assert_ne!(reversed.to_smiles(), molecule.to_smiles());
+        assert_eq!(reversed.to_canonical_smiles(), molecule.to_canonical_smiles());
+        assert_eq!(Compound::from_smiles("[Na+].[Cl-]").unwrap().to_canonical_smiles(), 
+            Compound::from_smiles("[Cl-].[Na+]").unwrap().to_canonical_smiles());
+    }
+
+    #[test]
+    fn stereo_smiles_test() {
+        macro_rules! same {
+            ($a:expr, $b:expr) => {
+                let a = Molecule::from_smiles($a).unwrap();
+                let b = Molecule::from_smiles($b).unwrap();
+                assert_eq!(a.to_canonical_smiles(), b.to_canonical_smiles());
+                for molecule in [a, b].iter() {
+                    let reparsed = Molecule::from_smiles(&molecule.to_smiles()).unwrap();
+                    assert_eq!(reparsed.to_canonical_smiles(), molecule.to_canonical_smiles());
+                }
+            };
+        }
+        macro_rules! different {
+            ($a:expr, $b:expr) => {
+                assert_ne!(Molecule::from_smiles($a).unwrap().to_canonical_smiles(), 
+                    Molecule::from_smiles($b).unwrap().to_canonical_smiles());
+            };
+        }
+        let trans = Molecule::from_smiles("F/C=C/F").unwrap();
+        assert_eq!(trans.bonds[1].stereo, Some(BondStereo::Trans));
+        let cis = Molecule::from_smiles("F/C=C\\F").unwrap();
+        assert_eq!(cis.bonds[1].stereo, Some(BondStereo::Cis));
+        same!("F/C=C/F", "F\\C=C\\F");
+        same!("F/C=C/F", "C(\\F)=C/F");
+        same!("F/C=C\\F", "F\\C=C/F");
+        same!("C/C=C/C=C/C", "C\\C=C\\C=C\\C");
+        same!("F/C=C/1CCCCC1O", "F/C=C1CCCCC\\1O");
+        different!("F/C=C/1CCCCC1O", "F/C=C\\1CCCCC1O");
+        different!("F/C=C/F", "F/C=C\\F");
+        different!("F/C=C/F", "FC=CF");
+        assert_eq!(Molecule::from_smiles("N[C@](C)(F)O").unwrap().atoms[1].chirality, Some(Chirality::Anticlockwise));
+        same!("N[C@](C)(F)O", "N[C@@](F)(C)O");
+        same!("N[C@](C)(F)O", "C[C@@](N)(F)O");
+        same!("N[C@](C)(F)O", "O[C@@](N)(C)F");
+        same!("O[C@@]1(F)CCCN1", "O[C@@]1(F)CCCN1");
+        same!("N[C@@]1(F)CCC(O)C1", "[C@]1(N)(F)CCC(O)C1");
+        different!("N[C@@]1(F)CCC(O)C1", "[C@@]1(N)(F)CCC(O)C1");
+        same!("N[C@@H](C)C(=O)O", "C[C@H](N)C(=O)O"); // L-Alanine
+        same!("N[C@@H](C)C(=O)O", "[C@@H](C)(N)C(=O)O");
+        different!("N[C@@H](C)C(=O)O", "N[C@H](C)C(=O)O");
+        different!("N[C@](C)(F)O", "N[C@@](C)(F)O");
+        different!("N[C@](C)(F)O", "NC(C)(F)O");
+        // Meso compounds are the same as their mirror images
+        same!("O[C@H]1C[C@@H](O)C1", "O[C@@H]1C[C@H](O)C1");
+        same!("O[C@H]1C[C@H](O)C1", "O[C@@H]1C[C@@H](O)C1");
+        different!("O[C@H]1C[C@@H](O)C1", "O[C@H]1C[C@H](O)C1");
+        same!("C[C@H]1CCCC[C@H]1C", "C[C@@H]1CCCC[C@@H]1C"); // cis-1,2-Dimethylcyclohexane
+        same!("C[C@@H]1CCCC[C@H]1C", "C1C[C@@H](C)[C@H](C)CC1");
+        different!("C[C@H]1CCCC[C@@H]1C", "C[C@@H]1CCCC[C@H]1C");
+        same!("O[C@H](F)[C@@H](O)F", "F[C@H](O)[C@@H](F)O");
+    }
+
+    #[test]
+    fn smiles_bracket_atoms_test() {
+        macro_rules! test {
+            ($smiles:expr, $f:expr) => {
+                let molecule = Molecule::from_smiles($smiles).unwrap();
+                assert_eq!(molecule.get_empirical_formula(), EmpiricalFormula::from_string($f).unwrap());
+                assert_eq!(molecule.to_smiles(), $smiles);
+            };
+        }
+        test!("[NH4+]", "NH4"); // Ammonium
+        test!("[OH-]", "OH"); // Hydroxide
+        test!("[O-2]", "O"); // Oxide
+        test!("c1cc[se]c1", "C4H4Se"); // Selenophene
+        test!("c1cc[te]c1", "C4H4Te"); // Tellurophene
+        test!("c1ccc2[nH]ccc2c1", "C8H7N"); // Indole
+        test!("[CH3:1][OH:2]", "CH4O"); // Methanol with atom classes
+        test!("*C(=O)O", "CHO2"); // Carboxylic acid
+        test!("[*:1]CC", "C2H5"); // Ethyl group
+        test!("[Sc+3]", "Sc"); // Scandium(III)
+        let ammonium = Molecule::from_smiles("[NH4+]").unwrap();
+        assert_eq!(ammonium.atoms[0].explicit_hydrogens, Some(4));
+        assert_eq!(*ammonium.atoms[0].get_ion().get_charge(), 1);
+        let methanol = Molecule::from_smiles("[CH3:7]O").unwrap();
+        assert_eq!(methanol.atoms[0].class, Some(7));
+        assert_eq!(methanol.atoms[1].class, None);
+        assert_eq!(methanol.atoms[1].explicit_hydrogens, None);
+        assert!(Molecule::from_smiles("*").unwrap().atoms[0].wildcard);
+        assert_eq!(*Molecule::from_smiles("[O--]").unwrap().atoms[0].get_ion().get_charge(), -2);
+        assert_eq!(*Molecule::from_smiles("[Fe+++]").unwrap().atoms[0].get_ion().get_charge(), 3);
+        assert_eq!(Molecule::from_smiles("[Cl:12]").unwrap().atoms[0].class, Some(12));
+        assert!(Molecule::from_smiles("[CH3:]").is_err());
+        assert!(Molecule::from_smiles("[Xx]").is_err());
+        assert!(Molecule::from_smiles("[13*]").is_err());
+    }
+
+    #[test]
+    fn parse_error_test() {
+        macro_rules! test {
+            ($result:expr, $position:expr, $character:expr, $reason:ident) => {
+                match $result {
+                    Err(ParserError::Parse(e)) => {
+                        assert_eq!(e, ParseError { position: $position, character: $character, reason: ParseErrorReason::$reason });
+                    },
+                    r => panic!("unexpected result {:?}", r)
+                }
+            };
+        }
+        test!(Molecule::from_smiles("CC1CC"), 2, Some('1'), UnclosedRing);
+        test!(Molecule::from_smiles("CC(C"), 2, Some('('), UnmatchedParenthesis);
+        test!(Molecule::from_smiles("CC)C"), 2, Some(')'), UnmatchedParenthesis);
+        test!(Molecule::from_smiles("CCX"), 2, Some('X'), UnknownElement);
+        test!(Molecule::from_smiles("C[Xy]"), 2, Some('X'), UnknownElement);
+        test!(Molecule::from_smiles("C[O+-]"), 4, Some('-'), BadCharge);
+        test!(Molecule::from_smiles("C[O+200]"), 4, Some('2'), BadCharge);
+        test!(Molecule::from_smiles("C[1C]"), 2, Some('1'), BadIsotope);
+        test!(Molecule::from_smiles("C[CH3:x]"), 6, Some('x'), BadAtomClass);
+        test!(Molecule::from_smiles("C%C"), 2, Some('C'), BadRingLabel);
+        test!(Molecule::from_smiles("C[CH3"), 5, None, UnexpectedEnd);
+        test!(Molecule::from_smiles("C?"), 1, Some('?'), UnexpectedCharacter);
+        test!(Molecule::from_smiles("C(=)C"), 3, Some(')'), DanglingBond);
+        test!(Compound::from_smiles("CCO.C1CC"), 5, Some('1'), UnclosedRing);
+        test!(Molecule::from_smiles("C[CH3:99999999999]"), 6, Some('9'), BadAtomClass);
+        test!(Molecule::from_smiles(""), 0, None, UnexpectedEnd);
+        test!(Molecule::from_smiles("[99999999999999999999999C]"), 1, Some('9'), BadIsotope);
+        test!(Molecule::from_smiles("[O+99999999999999999999999]"), 3, Some('9'), BadCharge);
+        test!(Molecule::from_smiles("C%99999999999999999999999C"), 2, Some('9'), BadRingLabel);
+        test!(Molecule::from_smiles("[CH99999999999999999999999]"), 3, Some('9'), BadHydrogenCount);
+        let many_charges = format!("[C{}]", "+".repeat(130));
+        test!(Molecule::from_smiles(&many_charges), 129, Some('+'), BadCharge);
+        test!(Molecule::from_smiles("(C)"), 0, Some('('), UnexpectedCharacter);
+        test!(Molecule::from_smiles("C((C))"), 2, Some('('), UnexpectedCharacter);
+        test!(Molecule::from_smiles("C()C"), 2, Some(')'), UnexpectedCharacter);
+        test!(Compound::from_smiles(""), 0, None, UnexpectedEnd);
+        test!(Compound::from_smiles("."), 0, Some('.'), UnexpectedCharacter);
+        test!(Compound::from_smiles("C..C"), 2, Some('.'), UnexpectedCharacter);
+        test!(Compound::from_smiles("C."), 2, None, UnexpectedEnd);
+        test!(EmpiricalFormula::from_string("H2Xz"), 2, Some('X'), UnknownElement);
+        test!(EmpiricalFormula::from_string("H2o"), 2, Some('o'), UnexpectedCharacter);
+        test!(EmpiricalFormula::from_string(""), 0, None, UnexpectedEnd);
+        test!(MolecularFormula::from_string("CH3(CH2"), 3, Some('('), UnmatchedParenthesis);
+        test!(MolecularFormula::from_string("CH3)2"), 3, Some(')'), UnmatchedParenthesis);
+        test!(MolecularFormula::from_string("Ca(OQ)2"), 4, Some('Q'), UnknownElement);
+        let error = Molecule::from_smiles("CCX").unwrap_err();
+        if let ParserError::Parse(e) = error {
+            assert_eq!(e.to_string(), "unknown element at byte 2 ('X')");
+        }
+    }
+
+    #[test]
+    fn formula_arithmetic_test() {
+        let formula = |f: &str| EmpiricalFormula::from_string(f).unwrap();
+        assert_eq!(formula("CH3CH3").normalized().to_string(), "C2H6");
+        assert_eq!(formula("CH3CH3"), formula("C2H6"));
+        assert_eq!(formula("CH3CH2OH").count(Element::Hydrogen), 6);
+        assert_eq!(formula("C2H6O") + formula("C2H4O2") - formula("H2O"), formula("C4H8O2")); // Ethyl acetate
+        assert_eq!(&formula("C6H12O6") * 2, formula("C12H24O12"));
+        assert_eq!((formula("H2O") * 3).to_string(), "H6O3");
+        assert!(formula("C6H12O6").contains(&formula("H2O")));
+        assert!(!formula("C6H12O6").contains(&formula("NH3")));
+        assert_eq!(formula("H2O").checked_sub(&formula("H3O")), None);
+        assert_eq!(formula("H2O").checked_sub(&formula("N")), None);
+        assert_eq!(formula("H2O").checked_sub(&formula("H2O")).unwrap().len(), 0);
+        // Hill order
+        assert_eq!(formula("OH2").to_string(), "H2O");
+        assert_eq!(formula("O2NHC7H4").to_string(), "C7H5NO2");
+        assert_eq!(formula("NaCl").to_string(), "ClNa");
+        assert_eq!(formula("SO4H2").to_string(), "H2O4S");
+        // Hash and order agree with the equality
+        let set: std::collections::HashSet<_> = vec![formula("CH3CH3"), formula("C2H6"), formula("H6C2")].into_iter().collect();
+        assert_eq!(set.len(), 1);
+        let set: std::collections::BTreeSet<_> = vec![formula("CH3CH3"), formula("C2H6"), formula("H2O")].into_iter().collect();
+        assert_eq!(set.len(), 2);
+        assert_eq!(formula("CH3CH3").cmp(&formula("H6C2")), std::cmp::Ordering::Equal);
+    }
+
+    #[test]
+    fn masses_test() {
+        macro_rules! test {
+            ($value:expr, $expected:expr) => {
+                assert!(($value - $expected).abs() < 1e-5, "{} != {}", $value, $expected);
+            };
+        }
+        let water = EmpiricalFormula::from_string("H2O").unwrap();
+        test!(water.get_monoisotopic_mass(), 18.010565);
+        test!(water.get_exact_mass(), 18.010565);
+        assert!((water.get_average_mass() - 18.015).abs() < 1e-3);
+        test!(EmpiricalFormula::from_string("C8H10N4O2").unwrap().get_monoisotopic_mass(), 194.080376); // Caffeine
+        test!(MolecularFormula::from_string("Ca(OH)2").unwrap().get_monoisotopic_mass(), 73.968070);
+        assert!((MolecularFormula::from_string("Ca(OH)2").unwrap().get_average_mass() - 74.093).abs() < 1e-3);
+        // The labeled atoms only change the exact mass
+        let heavy_water = Compound::from_smiles("[2H]O[2H]").unwrap();
+        test!(heavy_water.get_monoisotopic_mass(), 18.010565);
+        test!(heavy_water.get_exact_mass(), 20.023118);
+        assert!((heavy_water.get_average_mass() - 20.027).abs() < 1e-3);
+        test!(Molecule::from_smiles("[13CH4]").unwrap().get_exact_mass(), 17.034655);
+        assert!(Molecule::from_smiles("[2H]").unwrap().get_molecular_weight() > Molecule::from_smiles("[H]").unwrap().get_molecular_weight());
+        test!(Isotope::from_element(Element::Carbon, NonZeroU8::new(8)).get_exact_mass(), 14.003242);
+        test!(monoisotopic_mass(Element::Chlorine), 34.968853);
+        test!(isotope_mass(Element::Chlorine, 37), 36.965903);
+        assert_eq!(isotopes_of(Element::Uranium).len(), 3);
+        assert!(isotopes_of(Element::Oganesson).is_empty());
+    }
+
+    #[test]
+    fn isotope_pattern_test() {
+        let chlorine = IsotopePattern::aggregated(&EmpiricalFormula::from_string("Cl2").unwrap(), 0, 1e-6);
+        let intensities: Vec<f64> = chlorine.peaks().iter().map(|p| p.intensity).collect();
+        assert_eq!(intensities.len(), 3);
+        assert!((intensities[1] - 0.63991).abs() < 1e-4 && (intensities[2] - 0.10237).abs() < 1e-4);
+        assert!((chlorine.peaks()[0].mass - 69.937705).abs() < 1e-5);
+        // With a hundred carbons the M+1 peak is higher than the monoisotopic one
+        let carbon = IsotopePattern::aggregated(&EmpiricalFormula::from_string("C100").unwrap(), 0, 1e-3);
+        assert!((carbon.base_peak().unwrap().mass - 1201.003355).abs() < 1e-5);
+        assert!((carbon.peaks()[0].intensity - 0.92456).abs() < 1e-4);
+        // The fine structure separates the 13C and the 15N peaks of M+1
+        let urea = EmpiricalFormula::from_string("CH4N2O").unwrap();
+        let fine = IsotopePattern::fine_structure(&urea, 0, 1e-4);
+        let aggregated = IsotopePattern::aggregated(&urea, 0, 1e-4);
+        let m1 = |pattern: &IsotopePattern| pattern.peaks().iter().filter(|p| p.mass > 60.5 && p.mass < 61.5).count();
+        assert_eq!(m1(&aggregated), 1);
+        assert!(m1(&fine) >= 3);
+        let total: f64 = fine.peaks().iter().filter(|p| p.mass > 60.5 && p.mass < 61.5).map(|p| p.intensity).sum();
+        let merged = aggregated.peaks().iter().find(|p| p.mass > 60.5 && p.mass < 61.5).unwrap().intensity;
+        assert!((total - merged).abs() < 1e-9);
+        // [M+2H]2+ of urea
+        let ion = IsotopePattern::aggregated(&EmpiricalFormula::from_string("CH6N2O").unwrap(), 2, 1e-4);
+        assert!((ion.base_peak().unwrap().mass - 31.023458).abs() < 1e-5);
+    }
+
+    #[test]
+    fn adduct_test() {
+        let caffeine = Compound::from_smiles("CN1C=NC2=C1C(=O)N(C)C(=O)N2C").unwrap();
+        let table = AdductTable::default();
+        macro_rules! test {
+            ($name:expr, $mz:expr) => {
+                let adduct = table.get($name).unwrap();
+                assert!((adduct.mz(&caffeine).unwrap() - $mz).abs() < 1e-5, "{}", $name);
+            };
+        }
+        test!("[M+H]+", 195.087652);
+        test!("[M+Na]+", 217.069596);
+        test!("[M-H]-", 193.073099);
+        test!("[M+2H]2+", 98.047464);
+        test!("[2M+H]+", 389.168028);
+        test!("[M+NH4]+", 212.114201);
+        test!("[M+H-H2O]+", 177.077087);
+        let formula = EmpiricalFormula::from_string("C8H10N4O2").unwrap();
+        assert!((table.get("[M+H]+").unwrap().mz(&formula).unwrap() - table.get("[M+H]+").unwrap().mz(&caffeine).unwrap()).abs() < 1e-9);
+        assert_eq!(table.mz_values(&caffeine).len(), table.iter().count());
+        // The charge of the molecule adds to the one of the adduct
+        let tetramethylammonium = Compound::from_smiles("C[N+](C)(C)C").unwrap();
+        assert!((table.get("[M+H]+").unwrap().mz(&tetramethylammonium).unwrap() - 37.551851).abs() < 1e-5);
+        assert_eq!(table.get("[M-H]-").unwrap().mz(&tetramethylammonium), None);
+        assert!(table.mz_values(&tetramethylammonium).iter().all(|(a, _)| a.name() != "[M-H]-"));
+        // The users can add their own adducts
+        let mut table = AdductTable::new();
+        table.add(Adduct::from_string("[M+C2H3N+H]+").unwrap());
+        table.add(Adduct::new("[M+Ag]+", 1, vec![(1, EmpiricalFormula::from_string("Ag").unwrap())], 1));
+        assert_eq!(table.mz_values(&caffeine).len(), 2);
+        let adduct = Adduct::from_string("[2M-3H]3-").unwrap();
+        assert_eq!((adduct.molecules, adduct.charge, adduct.terms.len()), (2, -3, 1));
+        assert_eq!(adduct.to_string(), "[2M-3H]3-");
+        assert!(matches!(Adduct::from_string("[M+Xy]+"),
+            Err(ParserError::Parse(ParseError { position: 3, reason: ParseErrorReason::UnknownElement, .. }))));
+        assert!(matches!(Adduct::from_string("[M+H]"),
+            Err(ParserError::Parse(ParseError { position: 5, reason: ParseErrorReason::UnexpectedEnd, .. }))));
+        assert!(matches!(Adduct::from_string("[M+H]+x"),
+            Err(ParserError::Parse(ParseError { position: 5, reason: ParseErrorReason::BadCharge, .. }))));
+        assert!(matches!(Adduct::from_string("[M+0H]+"),
+            Err(ParserError::Parse(ParseError { position: 3, reason: ParseErrorReason::UnexpectedCharacter, .. }))));
+        assert!(matches!(Adduct::from_string("[M+99999999999999999999999H]+"),
+            Err(ParserError::Parse(ParseError { position: 3, reason: ParseErrorReason::UnexpectedCharacter, .. }))));
+        assert!(matches!(Adduct::from_string("[99999999999999999999999M+H]+"),
+            Err(ParserError::Parse(ParseError { position: 1, reason: ParseErrorReason::UnexpectedCharacter, .. }))));
+        assert!(matches!(Adduct::from_string("[M+H]99999999999999999999999+"),
+            Err(ParserError::Parse(ParseError { position: 5, reason: ParseErrorReason::BadCharge, .. }))));
+        assert!(std::panic::catch_unwind(|| Adduct::new("[M+H]", 1, Vec::new(), 0)).is_err());
+    }
+
+    #[test]
+    fn aromaticity_test() {
+        macro_rules! test {
+            ($smiles:expr, $aromatic:expr) => {
+                let molecule = Molecule::from_smiles($smiles).unwrap();
+                let count = molecule.bonds.iter().filter(|b| b.k == StructuralBond::Aromatic).count();
+                assert_eq!(count, $aromatic, "{}", $smiles);
+            };
+        }
+        test!("C1=CC=CC=C1", 6); // Benzene
+        test!("C1=CC=C2C=CC=CC2=C1", 11); // Naphthalene
+        test!("C1=CC2=CC=CC=CC2=C1", 11); // Azulene
+        test!("C1=CNC=C1", 5); // Pyrrole
+        test!("C1=COC=C1", 5); // Furan
+        test!("O=C1C=CC=CN1", 6); // 2-Pyridone
+        test!("[CH-]1C=CC=C1", 5); // Cyclopentadienyl anion
+        test!("C1=CCCCC1", 0); // Cyclohexene
+        test!("C1=CC=C1", 0); // Cyclobutadiene
+        test!("C1=CC=CC=CC=C1", 0); // Cyclooctatetraene
+        test!("C1=CC=C(C=C1)C1=CC=CC=C1", 12); // Biphenyl
+        test!("C1=CC=C2C(=C1)C1=CC=CC=C21", 12); // Biphenylene
+        assert_eq!(Molecule::from_smiles("C1=CC=CC=C1").unwrap().to_smiles(), "c1ccccc1");
+        assert_eq!(Molecule::from_smiles("C1=CNC=C1").unwrap().to_smiles(), "c1c[nH]cc1");
+        // Kekulé rings as they come from a file, the flag tells if the bond to the next atom is double
+        let ring = |atoms: &[(Element, bool)]| {
+            let mut molecule = Molecule { atoms: Vec::new(), bonds: Vec::new(), coords: None };
+            let n = atoms.len();
+            for (i, (element, double)) in atoms.iter().enumerate() {
+                molecule.atoms.push(AtomAndBondI::new(Isotope::from(*element), vec![i, (i + n - 1) % n]));
+                let k = if *double { StructuralBond::Double } else { StructuralBond::Single };
+                molecule.bonds.push(Bond::new(i, (i + 1) % n, k));
+            }
+            molecule
+        };
+        let aromatic_count = |molecule: &Molecule| molecule.bonds.iter().filter(|b| b.k == StructuralBond::Aromatic).count();
+        let c = Element::Carbon;
+        let benzene = [(c, true), (c, false), (c, true), (c, false), (c, true), (c, false)];
+        let furan = [(Element::Oxygen, false), (c, true), (c, false), (c, true), (c, false)];
+        for model in [AromaticityModel::Daylight, AromaticityModel::Mdl].iter() {
+            let mut molecule = ring(&benzene);
+            molecule.perceive_aromaticity(*model);
+            assert_eq!(aromatic_count(&molecule), 6);
+        }
+        let mut molecule = ring(&furan);
+        molecule.perceive_aromaticity(AromaticityModel::Mdl);
+        assert_eq!(aromatic_count(&molecule), 0);
+        molecule.perceive_aromaticity(AromaticityModel::Daylight);
+        assert_eq!(aromatic_count(&molecule), 5);
+    }
+
+    #[test]
+    fn kekulization_test() {
+        macro_rules! test {
+            ($smiles:expr, $doubles:expr) => {
+                let mut molecule = Molecule::from_smiles($smiles).unwrap();
+                molecule.kekulize().unwrap();
+                assert!(molecule.bonds.iter().all(|b| b.k != StructuralBond::Aromatic), "{}", $smiles);
+                let count = molecule.bonds.iter().filter(|b| b.k == StructuralBond::Double).count();
+                assert_eq!(count, $doubles, "{}", $smiles);
+                // Every atom has at most one double bond
+                for atom in molecule.atoms.iter() {
+                    assert!(atom.bonds.iter().filter(|b| molecule.bonds[**b].k == StructuralBond::Double).count() <= 1, "{}", $smiles);
+                }
+            };
+        }
+        test!("c1ccccc1", 3); // Benzene
+        test!("c1ccc2ccccc2c1", 5); // Naphthalene
+        test!("c1ccc2cc3ccccc3cc2c1", 7); // Anthracene
+        test!("c1cc2cccccc2c1", 5); // Azulene
+        test!("c1cc[nH]c1", 2); // Pyrrole
+        test!("c1ccncc1", 3); // Pyridine
+        test!("c1ccoc1", 2); // Furan
+        test!("O=c1cccc[nH]1", 3); // 2-Pyridone
+        test!("Cn1cnc2c1c(=O)n(C)c(=O)n2C", 4); // Caffeine
+        test!("c1ccc(cc1)-c1ccccc1", 6); // Biphenyl
+        test!("c1cc:*:cc1", 3);
+        let mut benzene = Molecule::from_smiles("c1ccccc1").unwrap();
+        benzene.kekulize().unwrap();
+        assert_eq!(benzene.to_smiles(), "C1=CC=CC=C1");
+        benzene.perceive_aromaticity(AromaticityModel::Mdl);
+        assert_eq!(benzene.to_smiles(), "c1ccccc1");
+        let mut impossible = Molecule::from_smiles("c1cccc1").unwrap();
+        let original = impossible.clone();
+        assert!(impossible.kekulize().is_err());
+        assert_eq!(impossible, original);
+        let mut compound = Compound::from_smiles("c1ccccc1.c1cccc1").unwrap();
+        assert!(compound.kekulize().is_err());
+        assert_eq!(compound.to_smiles(), "c1ccccc1.c1cccc1");
+    }
+
+    #[test]
+    fn rings_test() {
+        macro_rules! test {
+            ($smiles:expr, $sssr:expr, $relevant:expr) => {
+                let molecule = Molecule::from_smiles($smiles).unwrap();
+                let mut sssr: Vec<usize> = molecule.sssr().iter().map(|r| r.len()).collect();
+                sssr.sort_unstable();
+                assert_eq!(sssr, $sssr, "{}", $smiles);
+                let mut relevant: Vec<usize> = molecule.relevant_cycles().iter().map(|r| r.len()).collect();
+                relevant.sort_unstable();
+                assert_eq!(relevant, $relevant, "{}", $smiles);
+            };
+        }
+        test!("CCCC", vec![], vec![]);
+        test!("C1CCCCC1", vec![6], vec![6]);
+        test!("c1ccc2ccccc2c1", vec![6, 6], vec![6, 6]); // Naphthalene
+        test!("C12C3C4C1C5C2C3C45", vec![4, 4, 4, 4, 4], vec![4, 4, 4, 4, 4, 4]); // Cubane
+        test!("C1CC2CCC1C2", vec![5, 5], vec![5, 5]); // Norbornane
+        test!("C1CCC2(CC1)CCC2", vec![4, 6], vec![4, 6]); // Spiro
+        test!("C1CC1C1CCCC1", vec![3, 5], vec![3, 5]);
+        let molecule = Molecule::from_smiles("OC1CC2CCC1C2").unwrap();
+        assert!(!molecule.is_in_ring(0));
+        assert!(molecule.is_in_ring(1));
+        assert!(!molecule.is_bond_in_ring(molecule.atoms[0].bonds[0]));
+        assert_eq!(molecule.ring_sizes(0), vec![]);
+        assert_eq!(molecule.ring_sizes(1), vec![5]);
+        assert_eq!(molecule.ring_sizes(7), vec![5]);
+        for ring in molecule.sssr() {
+            // The atoms are given in order along the ring
+            for (i, bond) in ring.bonds.iter().enumerate() {
+                let (a, b) = (ring.atoms[i], ring.atoms[(i + 1) % ring.len()]);
+                assert!(molecule.bonds[*bond].a == a && molecule.bonds[*bond].b == b ||
+                    molecule.bonds[*bond].a == b && molecule.bonds[*bond].b == a);
+            }
+        }
+        let molecule = Molecule::from_smiles("c1ccccc1CC1CCC2(CC1)CC2").unwrap();
+        assert_eq!(molecule.ring_systems(), vec![vec![0, 1, 2, 3, 4, 5], vec![7, 8, 9, 10, 11, 12, 13, 14]]);
+        assert_eq!(Molecule::from_smiles("C1CCCCC1").unwrap().get_name(), None);
+    }
+
+    #[test]
+    fn substructure_test() {
+        let acid = Molecule::from_smiles("C(=O)O").unwrap();
+        macro_rules! count {
+            ($smiles:expr, $query:expr) => {
+                Molecule::from_smiles($smiles).unwrap().substructure_matches(&Molecule::from_smiles($query).unwrap()).len()
+            };
+        }
+        assert!(Molecule::from_smiles("CC(=O)O").unwrap().has_substructure(&acid)); // Acetic acid
+        assert!(Molecule::from_smiles("OC(=O)c1ccccc1").unwrap().has_substructure(&acid)); // Benzoic acid
+        assert!(!Molecule::from_smiles("CC(=O)C").unwrap().has_substructure(&acid)); // Acetone
+        assert!(!Molecule::from_smiles("CC(=O)[O-]").unwrap().has_substructure(&acid));
+        assert!(!Molecule::from_smiles("CCOC").unwrap().has_substructure(&acid));
+        assert_eq!(count!("c1ccccc1", "c1ccccc1"), 12); // Automorphisms
+        assert_eq!(count!("c1ccccc1", "C1=CC=CC=C1"), 12);
+        assert_eq!(count!("C1CCCCC1", "c1ccccc1"), 0);
+        assert_eq!(count!("CCC", "CC"), 4);
+        assert_eq!(count!("CCO", "*O"), 2); // The carbon and the hydrogen of the hydroxyl
+        assert_eq!(count!("[13CH4]", "C"), 1);
+        assert_eq!(count!("C", "[13CH4]"), 0);
+        assert_eq!(count!("[NH4+]", "N"), 0);
+        // The look-ahead keeps every automorphism
+        assert_eq!(count!("c1ccc2ccccc2c1", "c1ccc2ccccc2c1"), 4);
+        assert_eq!(count!("C12C3C4C1C5C2C3C45", "C12C3C4C1C5C2C3C45"), 48); // Cubane
+        assert_eq!(count!("CC(C)(C)C", "CC(C)C"), 24);
+        assert_eq!(count!("CC(C)C", "CC(C)(C)C"), 0);
+        let mapping = &Molecule::from_smiles("OCC=O").unwrap().substructure_matches(&Molecule::from_smiles("C=O").unwrap())[0];
+        assert_eq!(mapping[0..2], [Some(2), Some(3)]);
+        assert!(mapping[2..].iter().all(|m| m.is_none()));
+        let library = Compound::from_smiles("CCO.CC(=O)O.c1ccccc1C(=O)O").unwrap();
+        let matches = library.substructure_matches(&acid);
+        assert_eq!(matches.iter().map(|m| m.0).collect::<Vec<usize>>(), vec![1, 2]);
+        assert!(library.has_substructure(&acid));
+        assert!(!Compound::from_smiles("CCO.CCN").unwrap().has_substructure(&acid));
+    }
+
+    #[test]
+    fn smarts_test() {
+        macro_rules! count {
+            ($smarts:expr, $smiles:expr) => {
+                Smarts::from_string($smarts).unwrap().matches(&Molecule::from_smiles($smiles).unwrap()).len()
+            };
+        }
+        macro_rules! matches {
+            ($smarts:expr, $smiles:expr) => {
+                Smarts::from_string($smarts).unwrap().is_match(&Molecule::from_smiles($smiles).unwrap())
+            };
+        }
+        // Carboxylic acid
+        assert!(matches!("C(=O)[OX2H1]", "CC(=O)O"));
+        assert!(matches!("C(=O)[OX2H1]", "OC(=O)c1ccccc1"));
+        assert!(!matches!("C(=O)[OX2H1]", "CC(=O)OC"));
+        assert!(!matches!("C(=O)[OX2H1]", "CC(=O)[O-]"));
+        assert!(matches!("[CX3](=O)[OX1H0-,OX2H1]", "CC(=O)[O-]"));
+        // Aromaticity, elements and atomic numbers
+        assert_eq!(count!("c", "c1ccccc1"), 6);
+        assert_eq!(count!("C", "c1ccccc1"), 0);
+        assert_eq!(count!("[#6]", "c1ccccc1C"), 7);
+        assert_eq!(count!("a", "c1ccncc1"), 6);
+        assert_eq!(count!("[a;!c]", "c1ccncc1"), 1);
+        assert_eq!(count!("A", "CCO"), 9); // Hydrogens included
+        assert_eq!(count!("[!#1]", "CCO"), 3);
+        assert_eq!(count!("[Cl]", "ClCCl"), 2);
+        assert_eq!(count!("Br", "BrCCl"), 1);
+        // Counts
+        assert_eq!(count!("[CH3]", "CC(C)O"), 2);
+        assert_eq!(count!("[CD3]", "CC(C)O"), 1);
+        assert_eq!(count!("[CX4]", "CC(C)O"), 3);
+        assert_eq!(count!("[Cv4]", "C=CC#N"), 3);
+        assert_eq!(count!("[nv3]", "c1cc[nH]c1"), 1);
+        assert_eq!(count!("[NH2]", "NCC"), 1);
+        assert_eq!(count!("[H]", "[H][H]"), 2);
+        assert_eq!(count!("[2H]", "[2H]C"), 1);
+        assert_eq!(count!("[13C]", "[13CH4]"), 1);
+        assert_eq!(count!("[+]", "C[N+](C)(C)C"), 1);
+        assert_eq!(count!("[N+1]", "C[N+](C)(C)C"), 1);
+        assert_eq!(count!("[O-]", "CC(=O)[O-]"), 1);
+        assert_eq!(count!("[#8--]", "[O-2]"), 1);
+        // Rings
+        assert_eq!(count!("[R]", "C1CCCC1C"), 5);
+        assert_eq!(count!("[R0;#6]", "C1CCCC1C"), 1);
+        assert_eq!(count!("[R2]", "c1ccc2ccccc2c1"), 2);
+        assert_eq!(count!("[r5]", "C1CCCC1C1CC1"), 5);
+        assert_eq!(count!("[r3]", "C1CCCC1C1CC1"), 3);
+        assert_eq!(count!("[x3]", "c1ccc2ccccc2c1"), 2);
+        assert_eq!(count!("C@C", "C1CC1CC"), 6);
+        assert_eq!(count!("C!@C", "C1CC1CC"), 4);
+        // Bonds
+        assert_eq!(count!("C=O", "CC(=O)O"), 1);
+        assert_eq!(count!("C~O", "CC(=O)O"), 2);
+        assert_eq!(count!("C-,=O", "CC(=O)O"), 2);
+        assert_eq!(count!("c:c", "c1ccccc1"), 12);
+        assert_eq!(count!("cc", "c1ccccc1"), 12);
+        assert_eq!(count!("C#N", "CC#N"), 1);
+        assert_eq!(count!("C/C=C/C", "C/C=C\\C"), 2);
+        assert_eq!(count!("C1CC1", "C1CC1"), 6);
+        assert_eq!(count!("C%10CC%10", "C1CC1"), 6);
+        assert_eq!(count!("C.C", "CC"), 2);
+        // Logical operators and recursive SMARTS
+        assert_eq!(count!("[C,N]", "CNO"), 2);
+        assert_eq!(count!("[!C]", "CNO"), 7);
+        assert_eq!(count!("[C,N;H1]", "CN(C)C=N"), 2);
+        assert_eq!(count!("[C,N&H1]", "CN(C)C=N"), 4);
+        assert_eq!(count!("[$(CO)]", "CCO"), 1);
+        assert_eq!(count!("[C;$(C=O),$(C#N)]", "CC(=O)CC#N"), 2);
+        assert_eq!(count!("[$([OH]C(=O))]", "OC(=O)CCO"), 1);
+        assert_eq!(count!("[$(C(=O)[$([OH])])]", "OC(=O)CCO"), 1);
+        // Errors
+        macro_rules! error {
+            ($smarts:expr, $position:expr, $reason:ident) => {
+                match Smarts::from_string($smarts) {
+                    Err(ParserError::Parse(e)) => {
+                        assert_eq!((e.position, e.reason), ($position, ParseErrorReason::$reason), "{}", $smarts);
+                    },
+                    r => panic!("{}: {:?}", $smarts, r)
+                }
+            };
+        }
+        error!("C[Q]", 2, UnknownElement);
+        error!("C[Xy]", 3, UnexpectedCharacter);
+        error!("C[C", 3, UnexpectedEnd);
+        error!("C1CC", 1, UnclosedRing);
+        error!("C(C", 1, UnmatchedParenthesis);
+        error!("CC)", 2, UnmatchedParenthesis);
+        error!("CC=", 2, DanglingBond);
+        error!("[$(C[Q])]", 5, UnknownElement);
+        error!("", 0, UnexpectedEnd);
+        error!("[#99999999999999999999999]", 2, UnknownElement);
+        error!("[99999999999999999999999C]", 1, BadIsotope);
+        error!("[C+99999999999999999999999]", 3, BadCharge);
+        error!("[CX99999999999999999999999]", 3, UnexpectedCharacter);
+        error!("[CH99999999999999999999999]", 3, BadHydrogenCount);
+        error!(&format!("[C{}]", "+".repeat(130)), 129, BadCharge);
+        let library = Compound::from_smiles("CCO.CC(=O)O.c1ccccc1C(=O)O").unwrap();
+        let smarts = Smarts::from_string("C(=O)[OH]").unwrap();
+        assert_eq!(library.smarts_matches(&smarts).iter().map(|m| m.0).collect::<Vec<usize>>(), vec![1, 2]);
+    }
+
+    #[test]
+    fn molfile_writer_test() {
+        let compound = Compound::from_smiles("[13CH3]C(=O)[O-]").unwrap();
+        let molfile = compound.to_molfile();
+        let lines: Vec<&str> = molfile.lines().collect();
+        assert_eq!(lines[0], "");
+        assert_eq!(lines[1], "  chembas           2D");
+        assert_eq!(lines[3], "  7  6  0  0  0  0  0  0  0  0999 V2000");
+        assert_eq!(lines[4], "    0.0000    0.0000    0.0000 C   0  0  0  0  0  0  0  0  0  0  0  0");
+        assert_eq!(lines[7], "    0.0000    0.0000    0.0000 O   0  5  0  0  0  0  0  0  0  0  0  0");
+        assert_eq!(lines[11], "  1  2  1  0");
+        assert_eq!(lines[12], "  2  3  2  0");
+        assert_eq!(lines[17], "M  CHG  1   4  -1");
+        assert_eq!(lines[18], "M  ISO  1   1  13");
+        assert_eq!(lines[19], "M  END");
+        assert_eq!(lines.len(), 20);
+        // The aromatic bonds are kekulized
+        let benzene = Compound::from_smiles("c1ccccc1").unwrap().to_molfile();
+        let kinds: Vec<&str> = benzene.lines().skip(4 + 12).take(12).map(|l| &l[6..9]).collect();
+        assert_eq!(kinds.iter().filter(|k| **k == "  2").count(), 3);
+        assert!(kinds.iter().all(|k| *k == "  1" || *k == "  2"));
+        let read = Compound::from_mol(benzene.as_bytes()).unwrap();
+        assert_eq!(read.get_empirical_formula(), Compound::from_smiles("c1ccccc1").unwrap().get_empirical_formula());
+        assert_eq!(read.atoms_count(), 12);
+        assert_eq!(read.bonds_count(), 12);
+        // The aromatic bonds that can't be kekulized are read back
+        let pyrrole = Compound::from_smiles("c1cccc1").unwrap();
+        let molfile = pyrrole.to_molfile();
+        assert_eq!(molfile.lines().skip(4 + 10).take(10).filter(|l| &l[6..9] == "  4").count(), 5);
+        let read = Compound::from_mol(molfile.as_bytes()).unwrap();
+        assert_eq!(read.get_empirical_formula(), pyrrole.get_empirical_formula());
+        assert_eq!(read.iter().next().unwrap().bonds.iter().filter(|b| b.k == StructuralBond::Aromatic).count(), 5);
+        // The stereo survives the round trip, and the chiral flag is only set if there are parities
+        for smiles in ["C[C@H](N)C(=O)O", "C[C@@H](N)C(=O)O", "F/C=C/F", "F/C=C\\F", "C/C=C(\\C)/C=C/C", "N[C@@H](C/C=C/C)O"].iter() {
+            let compound = Compound::from_smiles(smiles).unwrap();
+            let molfile = compound.to_molfile();
+            let read = Compound::from_mol(molfile.as_bytes()).unwrap();
+            assert_eq!(read.to_canonical_smiles(), compound.to_canonical_smiles(), "{}", smiles);
+            let chiral = smiles.contains('@');
+            assert_eq!(&molfile.lines().nth(3).unwrap()[12..15], if chiral { "  1" } else { "  0" });
+        }
+        // The double bonds without stereo are not given one by the generated coordinates
+        let diene = Compound::from_smiles("F/C=C/C=CC").unwrap();
+        let read = Compound::from_mol(diene.to_molfile().as_bytes()).unwrap();
+        assert_eq!(read.to_canonical_smiles(), diene.to_canonical_smiles());
+        // The generated layout has no atoms on top of each other
+        let branched = Compound::from_smiles("CC(C)(C)/C=C/C(C)(C)/C=C\\C(C)(C)C(C)(C)C").unwrap();
+        let read = Compound::from_mol(branched.to_molfile().as_bytes()).unwrap();
+        assert_eq!(read.to_canonical_smiles(), branched.to_canonical_smiles());
+        let points = read.iter().next().unwrap().coords.clone().unwrap();
+        for (i, a) in points.iter().enumerate() {
+            for b in points[i + 1..].iter() {
+                assert!((a.x - b.x).abs() + (a.y - b.y).abs() > 1.0);
+            }
+        }
+        // The coordinates of the molecule are kept, the stereo that they don't give is written as either
+        let mut difluoroethene = Molecule::from_smiles("F/C=C/F").unwrap();
+        let line: Vec<Point> = (0..difluoroethene.atoms.len()).map(|i| Point::new(i as f64, 0.0, 0.5 * i as f64)).collect();
+        difluoroethene.coords = Some(line.clone());
+        let molfile = Compound::from(difluoroethene).to_molfile();
+        assert!(molfile.lines().any(|l| l.starts_with("  2  3  2  3")));
+        let read = Compound::from_mol(molfile.as_bytes()).unwrap();
+        assert_eq!(read.iter().next().unwrap().coords.as_ref().unwrap()[..line.len()], line[..]);
+        assert_eq!(read.to_canonical_smiles(), Compound::from_smiles("FC=CF").unwrap().to_canonical_smiles());
+        // Coordinates and the header of MolFile
+        let mut water = Molecule::from_smiles("O").unwrap();
+        water.coords = Some(vec![Point::new(0.0, 0.0, 0.1173), Point::new(0.0, 0.7572, -0.4692), Point::new(0.0, -0.7572, -0.4692)]);
+        let molfile = MolFile::new("water".to_owned(), "A comment".to_owned(), Compound::from(water));
+        let mut out = Vec::new();
+        molfile.write(&mut out).unwrap();
+        let text = String::from_utf8(out).unwrap();
+        let lines: Vec<&str> = text.lines().collect();
+        assert_eq!(lines[0], "water");
+        assert_eq!(lines[1], "  chembas           3D");
+        assert_eq!(lines[2], "A comment");
+        assert_eq!(lines[5], "    0.0000    0.7572   -0.4692 H   0  0  0  0  0  0  0  0  0  0  0  0");
+        let read = MolFile::parse(text.as_bytes()).unwrap();
+        assert_eq!(read.name(), "water");
+        assert_eq!(read.compound().atoms_count(), 3);
+        let sdf = Compound::from_smiles("C.N").unwrap().to_sdf();
+        assert_eq!(sdf.matches("$$$$\n").count(), 2);
+        assert_eq!(sdf.matches("M  END\n").count(), 2);
+    }
+
+    #[test]
+    fn molfile_v3000_test() {
+        let benzene = "benzene
+  -ISIS-  10171409252D
+
+  0  0  0     0  0            999 V3000
+M  V30 BEGIN CTAB
+M  V30 COUNTS 6 6 0 0 0
+M  V30 BEGIN ATOM
+M  V30 1 C 1.9050 -0.7932 0 0
+M  V30 2 C 1.9050 -2.1232 0 0
+M  V30 3 C 0.7531 -0.1282 0 0
+M  V30 4 C 0.7531 -2.7882 0 0
+M  V30 5 C -0.3987 -0.7932 0 0
+M  V30 6 C -0.3987 -2.1232 0 0
+M  V30 END ATOM
+M  V30 BEGIN BOND
+M  V30 1 2 3 1
+M  V30 2 2 4 2
+M  V30 3 1 1 2
+M  V30 4 2 5 6
+M  V30 5 1 3 5
+M  V30 6 1 4 6
+M  V30 END BOND
+M  V30 END CTAB
+M  END
+";
+        let read = MolFile::parse(benzene.as_bytes()).unwrap();
+        assert_eq!(read.name(), "benzene");
+        assert_eq!(read.compound().atoms_count(), 6);
+        assert_eq!(read.compound().bonds_count(), 6);
+        assert_eq!(read.compound().iter().next().unwrap().sssr().len(), 1);
+        let query = benzene.replace("M  V30 1 C 1.9050 -0.7932 0 0", "M  V30 1 NOT[N,O] 1.9050 -0.7932 0 0 RAD=2");
+        let read = Compound::from_mol(query.as_bytes()).unwrap();
+        let atom = &read.iter().next().unwrap().atoms[0];
+        assert_eq!(atom.atom_list, Some(AtomList { elements: vec![Element::Nitrogen, Element::Oxygen], excluded: true }));
+        assert_eq!(atom.radical, Some(Radical::Doublet));
+        // Charges, isotopes, valences, a continuation line and blocks that are skipped
+        let acetate = "
+  chembas           2D
+
+  0  0  0     0  0            999 V3000
+M  V30 BEGIN CTAB
+M  V30 COUNTS 4 3 1 0 0
+M  V30 BEGIN ATOM
+M  V30 1 C 0 0 0 0 MASS=13 VAL=4
+M  V30 2 C 0.8660 0.5 0 0 VAL=4
+M  V30 3 O 1.7321 0 0 0
+M  V30 4 O 0.8660 1.5 0 0 -
+M  V30 CHG=-1
+M  V30 END ATOM
+M  V30 BEGIN BOND
+M  V30 1 1 1 2
+M  V30 2 2 2 3
+M  V30 3 1 2 4
+M  V30 END BOND
+M  V30 BEGIN SGROUP
+M  V30 1 SUP 0 ATOMS=(2 3 4) LABEL=\"CO2\"
+M  V30 END SGROUP
+M  V30 END CTAB
+M  END
+";
+        let read = Compound::from_mol(acetate.as_bytes()).unwrap();
+        assert_eq!(read.to_canonical_smiles(), Compound::from_smiles("[13CH3]C(=O)[O-]").unwrap().to_canonical_smiles());
+        // The chirality from the parity and from a wedge
+        let chiral = |atom: &str, bond: &str| format!("
+  chembas           2D
+
+  0  0  0     0  0            999 V3000
+M  V30 BEGIN CTAB
+M  V30 COUNTS 4 3 0 0 1
+M  V30 BEGIN ATOM
+M  V30 1 C 0 0 0 0 VAL=4 {}
+M  V30 2 F 0 1 0 0
+M  V30 3 Cl 0.8660 -0.5 0 0
+M  V30 4 Br -0.8660 -0.5 0 0
+M  V30 END ATOM
+M  V30 BEGIN BOND
+M  V30 1 1 1 2 {}
+M  V30 2 1 1 3
+M  V30 3 1 1 4
+M  V30 END BOND
+M  V30 END CTAB
+M  END
+", atom, bond);
+        let expected = Compound::from_smiles("F[C@@H](Cl)Br").unwrap().to_canonical_smiles();
+        let mirror = Compound::from_smiles("F[C@H](Cl)Br").unwrap().to_canonical_smiles();
+        assert_ne!(expected, mirror);
+        assert_eq!(Compound::from_mol(chiral("CFG=1", "").as_bytes()).unwrap().to_canonical_smiles(), expected);
+        assert_eq!(Compound::from_mol(chiral("CFG=2", "").as_bytes()).unwrap().to_canonical_smiles(), mirror);
+        assert_eq!(Compound::from_mol(chiral("", "CFG=1").as_bytes()).unwrap().to_canonical_smiles(), expected);
+        assert_eq!(Compound::from_mol(chiral("", "CFG=3").as_bytes()).unwrap().to_canonical_smiles(), mirror);
+        // A trans double bond drawn in 2D
+        let butene = "
+  chembas           2D
+
+  0  0  0     0  0            999 V3000
+M  V30 BEGIN CTAB
+M  V30 COUNTS 4 3 0 0 0
+M  V30 BEGIN ATOM
+M  V30 1 C 0 0 0 0 VAL=4
+M  V30 2 C 0.8660 0.5 0 0 VAL=4
+M  V30 3 C 1.7321 0 0 0 VAL=4
+M  V30 4 C 2.5981 0.5 0 0 VAL=4
+M  V30 END ATOM
+M  V30 BEGIN BOND
+M  V30 1 1 1 2
+M  V30 2 2 2 3
+M  V30 3 1 3 4
+M  V30 END BOND
+M  V30 END CTAB
+M  END
+";
+        let read = Compound::from_mol(butene.as_bytes()).unwrap();
+        assert_eq!(read.to_canonical_smiles(), Compound::from_smiles("C/C=C/C").unwrap().to_canonical_smiles());
+        assert_ne!(read.to_canonical_smiles(), Compound::from_smiles("C/C=C\\C").unwrap().to_canonical_smiles());
+        // An end with two methyls has no configuration
+        let methylbutene = butene.replace("COUNTS 4 3", "COUNTS 5 4").replace("M  V30 END ATOM", "M  V30 5 C 1.7321 -1 0 0 VAL=4\nM  V30 END ATOM")
+            .replace("M  V30 END BOND", "M  V30 4 1 3 5\nM  V30 END BOND");
+        let read = Compound::from_mol(methylbutene.as_bytes()).unwrap();
+        assert!(read.iter().next().unwrap().bonds.iter().all(|b| b.stereo.is_none()));
+        assert_eq!(read.to_canonical_smiles(), Compound::from_smiles("CC=C(C)C").unwrap().to_canonical_smiles());
+        // Errors
+        assert!(matches!(Compound::from_mol(benzene.replace("COUNTS 6 6", "COUNTS 6 7").as_bytes()), Err(ParserError::Syntax)));
+        assert!(matches!(Compound::from_mol(benzene.replace("M  V30 3 1 1 2", "M  V30 3 7 1 2").as_bytes()), Err(ParserError::Syntax)));
+        let truncated: Vec<&str> = benzene.lines().take(12).collect();
+        assert!(matches!(Compound::from_mol(truncated.join("\n").as_bytes()), Err(ParserError::UnexpectedEof)));
+        // The compounds that don't fit on V2000 are written as V3000
+        let polyethylene = Compound::from_smiles(&"C".repeat(400)).unwrap();
+        let molfile = polyethylene.to_molfile();
+        let lines: Vec<&str> = molfile.lines().collect();
+        assert_eq!(lines[3], "  0  0  0     0  0            999 V3000");
+        assert_eq!(lines[5], "M  V30 COUNTS 1202 1201 0 0 0");
+        assert_eq!(lines[7], "M  V30 1 C 0.0000 0.0000 0.0000 0");
+        assert!(lines.contains(&"M  V30 1 1 1 2"));
+        assert_eq!(lines[lines.len() - 1], "M  END");
+        let read = Compound::from_mol(molfile.as_bytes()).unwrap();
+        assert_eq!(read.atoms_count(), 1202);
+        assert_eq!(read.bonds_count(), 1201);
+        assert_eq!(read.get_empirical_formula(), polyethylene.get_empirical_formula());
+        // The stereo survives the round trip through V3000
+        let chiral = Compound::from_smiles(&format!("N[C@@H](O)/C=C/{}", "C".repeat(400))).unwrap();
+        let molfile = chiral.to_molfile();
+        assert!(molfile.lines().nth(5).unwrap().ends_with(" 1"));
+        assert!(molfile.lines().any(|l| l.starts_with("M  V30 2 C ") && l.contains(" CFG=")));
+        let read = Compound::from_mol(molfile.as_bytes()).unwrap();
+        assert_eq!(read.to_canonical_smiles(), chiral.to_canonical_smiles());
+        let mirror = Compound::from_smiles(&format!("N[C@H](O)/C=C\\{}", "C".repeat(400))).unwrap();
+        assert_eq!(Compound::from_mol(mirror.to_molfile().as_bytes()).unwrap().to_canonical_smiles(), mirror.to_canonical_smiles());
+        // The atom lists that don't fit on `M  ALS` need V3000
+        let mut query = Molecule::from_smiles("*C").unwrap();
+        let elements = vec![Element::Lithium, Element::Sodium, Element::Potassium, Element::Rubidium, Element::Cesium,
+            Element::Beryllium, Element::Magnesium, Element::Calcium, Element::Strontium, Element::Barium, Element::Fluorine,
+            Element::Chlorine, Element::Bromine, Element::Iodine, Element::Oxygen, Element::Sulfur, Element::Selenium];
+        query.atoms[0].atom_list = Some(AtomList { elements, excluded: false });
+        let query = Compound::from(query);
+        let molfile = query.to_molfile();
+        assert!(molfile.contains("V3000"));
+        let read = Compound::from_mol(molfile.as_bytes()).unwrap();
+        assert_eq!(read.iter().next().unwrap().atoms[0].atom_list, query.iter().next().unwrap().atoms[0].atom_list);
+    }
+
+    #[test]
+    fn molfile_properties_test() {
+        let atom = |symbol: &str, x: f64, mass_difference: i32, charge: u8| format!(
+            "{:10.4}{:10.4}{:10.4} {:<3}{:2}{:3}  0  0  0  0  0  0  0  0  0  0", x, 0.0, 0.0, symbol, mass_difference, charge);
+        let molfile = |symbols: [&str; 3], properties: &str| format!("\n  -ISIS-  10171409252D\n\n  3  2  0  0  0  0  0  0  0  0999 V2000\n{}\n{}\n{}\n  1  2  1  0\n  2  3  1  0\n{}M  END\n",
+            atom(symbols[0], 0.0, 1, 0), atom(symbols[1], 1.0, 0, 0), atom(symbols[2], 2.0, 0, 5), properties);
+        // Mass difference and charge on the atom block
+        let read = Compound::from_mol(molfile(["C", "C", "O"], "").as_bytes()).unwrap();
+        assert_eq!(read.to_canonical_smiles(), Compound::from_smiles("[13C][C][O-]").unwrap().to_canonical_smiles());
+        // The properties replace the atom block
+        let read = Compound::from_mol(molfile(["C", "C", "O"], "M  CHG  1   3   1\nM  ISO  1   2  14\nM  RAD  1   2   2\n").as_bytes()).unwrap();
+        assert_eq!(read.to_canonical_smiles(), Compound::from_smiles("[C][14C][O+]").unwrap().to_canonical_smiles());
+        let molecule = read.iter().next().unwrap();
+        assert_eq!(molecule.atoms[1].radical, Some(Radical::Doublet));
+        assert_eq!(molecule.atoms[0].radical, None);
+        // Atom lists
+        let read = Compound::from_mol(molfile(["L", "C", "O"], "M  CHG  1   3   0\nM  ALS   1  2 F N   O   \n").as_bytes()).unwrap();
+        let query = read.iter().next().unwrap();
+        assert_eq!(query.atoms[0].atom_list, Some(AtomList { elements: vec![Element::Nitrogen, Element::Oxygen], excluded: false }));
+        assert!(Molecule::from_smiles("NCO").unwrap().has_substructure(query));
+        assert!(Molecule::from_smiles("OCO").unwrap().has_substructure(query));
+        assert!(!Molecule::from_smiles("CCO").unwrap().has_substructure(query));
+        // Round trip through the writer
+        let text = read.to_molfile();
+        assert!(text.contains("M  ALS   1  2 F N   O   \n"));
+        let written = Compound::from_mol(text.as_bytes()).unwrap();
+        assert_eq!(written.iter().next().unwrap().atoms[0].atom_list, query.atoms[0].atom_list);
+        assert!(matches!(Compound::from_mol(molfile(["C", "C", "O"], "M  CHG  1   4   1\n").as_bytes()), Err(ParserError::Syntax)));
+    }
+
+    #[test]
+    fn components_test() {
+        let mut salt = Molecule::from_smiles("CC(=O)[O-]").unwrap();
+        let sodium = salt.atoms.len();
+        salt.atoms.push(AtomAndBondI::new(Isotope::from(Ion::new(Element::Sodium, 1)), Vec::new()));
+        let components = salt.components();
+        assert_eq!(components.len(), 2);
+        assert_eq!(components[0].1, (0..sodium).collect::<Vec<usize>>());
+        assert_eq!(components[1].1, vec![sodium]);
+        assert_eq!(components[1].0.atoms.len(), 1);
+        assert_eq!(components[0].0.bonds.len(), salt.bonds.len());
+        // Isolated atoms and the order of the atoms on a molfile
+        let molfile = "
+  chembas           2D
+
+  5  3  0  0  0  0  0  0  0  0999 V2000
+    0.0000    0.0000    0.0000 Na  0  3  0  0  0  0  0  0  0  0  0  0
+    1.0000    0.0000    0.0000 C   0  0  0  0  0  0  0  0  0  0  0  0
+    2.0000    0.0000    0.0000 O   0  5  0  0  0  0  0  0  0  0  0  0
+    3.0000    0.0000    0.0000 O   0  0  0  0  0  0  0  0  0  0  0  0
+    4.0000    0.0000    0.0000 C   0  0  0  0  0  0  0  0  0  0  0  0
+  5  2  1  0
+  2  3  1  0
+  2  4  2  0
+M  CHG  2   1   1   3  -1
+M  END
+";
+        let read = MolFile::parse(molfile.as_bytes()).unwrap();
+        assert_eq!(read.compound().atoms_count(), 5);
+        assert_eq!(read.atom_indices(), &[vec![0], vec![1, 2, 3, 4]][..]);
+        let molecules: Vec<&Molecule> = read.compound().iter().collect();
+        assert_eq!(*molecules[0].atoms[0].get_element(), Element::Sodium);
+        assert_eq!(*molecules[1].atoms[1].get_ion().get_charge(), -1);
+        assert_eq!(molecules[1].bonds[0].a, 3);
+        // CML keeps the order, the isolated atoms and the bonds that close rings
+        let cml = r#"<?xml version="1.0"?>
+<cml>
+  <molecule id="m1">
+    <atomArray>
+      <atom id="a1" elementType="O" x3="0.0" y3="0.0" z3="0.0"/>
+      <atom id="a2" elementType="C" x3="1.0" y3="0.0" z3="0.0"/>
+      <atom id="a3" elementType="C" x3="1.0" y3="1.0" z3="0.0"/>
+      <atom id="a4" elementType="C" x3="0.0" y3="1.0" z3="0.0"/>
+      <atom id="a5" elementType="Na" x3="5.0" y3="5.0" z3="0.0"/>
+    </atomArray>
+    <bondArray>
+      <bond atomRefs2="a1 a2" order="1"/>
+      <bond atomRefs2="a2 a3" order="1"/>
+      <bond atomRefs2="a3 a4" order="1"/>
+      <bond atomRefs2="a4 a1" order="1"/>
+    </bondArray>
+  </molecule>
+</cml>"#;
+        let read = Compound::from_cml(cml.as_bytes()).unwrap();
+        assert_eq!(read.atoms_count(), 5);
+        assert_eq!(read.bonds_count(), 4);
+        let molecules: Vec<&Molecule> = read.iter().collect();
+        assert_eq!(*molecules[0].atoms[0].get_element(), Element::Oxygen);
+        assert_eq!(molecules[0].sssr().len(), 1);
+        assert_eq!(*molecules[1].atoms[0].get_element(), Element::Sodium);
+    }
+
+    #[test]
+    fn cml_test() {
+        // Acetate with a 13C, written in 2D with implicit hydrogens
+        let cml = r#"<?xml version="1.0" encoding="UTF-8"?>
+<cml xmlns="http://www.xml-cml.org/schema" xmlns:cml="http://www.xml-cml.org/dict/cml">
+  <molecule id="m1">
+    <name>acetate</name>
+    <atomArray>
+      <atom id="a1" elementType="C" isotopeNumber="13" hydrogenCount="3" x2="0.0" y2="0.0"/>
+      <atom id="a2" elementType="C" hydrogenCount="0" x2="1.2990" y2="0.75"/>
+      <atom id="a3" elementType="O" x2="2.5981" y2="0.0"/>
+      <atom id="a4" elementType="O" formalCharge="-1" x2="1.2990" y2="2.25"/>
+    </atomArray>
+    <bondArray>
+      <bond id="b1" atomRefs2="a1 a2" order="S"/>
+      <bond id="b2" atomRefs2="a2 a3" order="2"/>
+      <bond id="b3" atomRefs2="a2 a4" order="1"/>
+    </bondArray>
+    <propertyList>
+      <property dictRef="cml:molwt" title="Molecular weight">
+        <scalar dataType="xsd:double">60.05</scalar>
+      </property>
+    </propertyList>
+  </molecule>
+</cml>"#;
+        let file = CmlFile::parse(cml.as_bytes()).unwrap();
+        assert_eq!(file.name(), "acetate");
+        assert_eq!(file.properties()["Molecular weight"], "60.05");
+        let acetate = Compound::from_smiles("[13CH3]C(=O)[O-]").unwrap();
+        assert_eq!(file.compound().to_canonical_smiles(), acetate.to_canonical_smiles());
+        let molecule = file.compound().iter().next().unwrap();
+        assert_eq!(molecule.coords.as_ref().unwrap()[3], Point::new(1.2990, 2.25, 0.0));
+        // Aromatic bonds
+        let benzene = Compound::from_smiles("c1ccccc1").unwrap();
+        let read = Compound::from_cml(benzene.to_cml().as_bytes()).unwrap();
+        assert!(benzene.to_cml().contains(r#"order="A""#));
+        assert_eq!(read.to_canonical_smiles(), benzene.to_canonical_smiles());
+        // Round trip of the whole document
+        let mut out = Vec::new();
+        file.write(&mut out).unwrap();
+        let written = String::from_utf8(out).unwrap();
+        assert!(written.contains(r#"isotopeNumber="13""#));
+        assert!(written.contains(r#"formalCharge="-1""#));
+        let read = CmlFile::parse(written.as_bytes()).unwrap();
+        assert_eq!(read.name(), file.name());
+        assert_eq!(read.properties(), file.properties());
+        assert_eq!(read.compound().to_canonical_smiles(), acetate.to_canonical_smiles());
+        assert_eq!(read.compound().iter().next().unwrap().coords, molecule.coords);
+        let salt = Compound::from_smiles("[Na+].[Cl-]").unwrap();
+        assert_eq!(Compound::from_cml(salt.to_cml().as_bytes()).unwrap().to_canonical_smiles(), salt.to_canonical_smiles());
+        // Every molecule has its own ids
+        let cml = r#"<cml>
+  <molecule id="m1">
+    <atomArray>
+      <atom id="a1" elementType="Na" formalCharge="1"/>
+    </atomArray>
+  </molecule>
+  <molecule id="m2">
+    <atomArray>
+      <atom id="a1" elementType="C" hydrogenCount="3"/>
+      <atom id="a2" elementType="O" hydrogenCount="0" formalCharge="-1"/>
+    </atomArray>
+    <bondArray>
+      <bond atomRefs2="a1 a2" order="1"/>
+    </bondArray>
+  </molecule>
+</cml>"#;
+        let read = Compound::from_cml(cml.as_bytes()).unwrap();
+        assert_eq!(read.to_canonical_smiles(), Compound::from_smiles("[Na+].C[O-]").unwrap().to_canonical_smiles());
+        // L-alanine, the center stands for its hydrogen
+        let cml = r#"<cml>
+  <molecule id="m1">
+    <atomArray>
+      <atom id="a1" elementType="N" hydrogenCount="2"/>
+      <atom id="a2" elementType="C" hydrogenCount="1">
+        <atomParity atomRefs4="a1 a2 a3 a4">1</atomParity>
+      </atom>
+      <atom id="a3" elementType="C" hydrogenCount="3"/>
+      <atom id="a4" elementType="C" hydrogenCount="0"/>
+      <atom id="a5" elementType="O" hydrogenCount="0"/>
+      <atom id="a6" elementType="O" hydrogenCount="1"/>
+    </atomArray>
+    <bondArray>
+      <bond atomRefs2="a1 a2" order="1"/>
+      <bond atomRefs2="a2 a3" order="1"/>
+      <bond atomRefs2="a2 a4" order="1"/>
+      <bond atomRefs2="a4 a5" order="2"/>
+      <bond atomRefs2="a4 a6" order="1"/>
+    </bondArray>
+  </molecule>
+</cml>"#;
+        let read = Compound::from_cml(cml.as_bytes()).unwrap();
+        assert_eq!(read.to_canonical_smiles(), Compound::from_smiles("N[C@@H](C)C(=O)O").unwrap().to_canonical_smiles());
+        for smiles in &["C[C@H](N)C(=O)O", "C[C@@H](N)C(=O)O", "F/C=C/F", "F/C=C\\F", "C/C=C(/F)Cl", "N[C@@H](C)/C=C/[C@H](O)Cl"] {
+            let compound = Compound::from_smiles(smiles).unwrap();
+            let written = compound.to_cml();
+            let read = Compound::from_cml(written.as_bytes()).unwrap();
+            assert_eq!(read.to_canonical_smiles(), compound.to_canonical_smiles(), "{}", smiles);
+        }
+        assert!(Compound::from_smiles("F/C=C/F").unwrap().to_cml().contains("<bondStereo"));
+    }
+
+    #[test]
+    fn xyz_test() {
+        let ethanol = "9
+ethanol, B3LYP/6-31G*
+C      1.1879   -0.3829    0.0000
+C      0.0000    0.5526    0.0000
+8     -1.1867   -0.2472    0.0000
+H     -1.9237    0.3850    0.0000
+H      2.0985    0.2306    0.0000
+H      1.1184   -1.0093    0.8869
+H      1.1184   -1.0093   -0.8869
+H     -0.0227    1.1812    0.8852
+H     -0.0227    1.1812   -0.8852
+";
+        let file = XyzFile::parse(ethanol.as_bytes()).unwrap();
+        assert_eq!(file.comment(), "ethanol, B3LYP/6-31G*");
+        let mut molecule = file.into_molecule();
+        assert_eq!(molecule.atoms.len(), 9);
+        assert!(molecule.bonds.is_empty());
+        assert_eq!(molecule.coords.as_ref().unwrap()[2], Point::new(-1.1867, -0.2472, 0.0));
+        molecule.perceive_bonds();
+        assert_eq!(molecule.bonds.len(), 8);
+        assert_eq!(molecule.to_canonical_smiles(), Molecule::from_smiles("CCO").unwrap().to_canonical_smiles());
+        let written = molecule.to_xyz();
+        assert_eq!(written.lines().nth(2), Some("C      1.187900    -0.382900     0.000000"));
+        let read = XyzFile::parse(written.as_bytes()).unwrap().into_molecule();
+        assert_eq!(read.coords, molecule.coords);
+        assert!(matches!(XyzFile::parse("3\nwater\nO 0 0 0\n".as_bytes()), Err(ParserError::UnexpectedEof)));
+        assert!(matches!(XyzFile::parse("1\n\nQq 0 0 0\n".as_bytes()), Err(ParserError::Syntax)));
+    }
+
+    #[test]
+    fn pdb_test() {
+        let pdb = "HEADER    TEST STRUCTURE
+MODEL        1
+ATOM      1  N   GLY A   1       0.000   0.000   0.000  1.00 20.00
+ATOM      2  CA  GLY A   1       1.458   0.000   0.000  1.00 20.00
+ATOM      3  C   GLY A   1       2.009   1.420   0.000  1.00 20.00
+ATOM      4  O   GLY A   1       1.246   2.390   0.000  1.00 20.00
+TER       5      GLY A   1
+HETATM    6  C1  EOH A 101      10.000   0.000   0.000  1.00 20.00           C
+HETATM    7  C2  EOH A 101      11.512   0.000   0.000  1.00 20.00           C
+HETATM    8  O  AEOH A 101      12.000   1.350   0.000  1.00 20.00           O
+HETATM    9  O  BEOH A 101      12.000  -1.350   0.000  1.00 20.00           O
+HETATM   10 ZN    ZN A 201      30.000   0.000   0.000  1.00 20.00          ZN2+
+HETATM   11  O   HOH A 301      20.000   0.000   0.000  1.00 20.00           O
+CONECT    6    7
+CONECT    7    6    8
+CONECT    8    7
+ENDMDL
+MODEL        2
+ATOM      1  N   GLY A   1       0.000   0.000   0.000  1.00 20.00           N
+ENDMDL
+END
+";
+        let file = PdbFile::parse(pdb.as_bytes()).unwrap();
+        let check = |file: &PdbFile| {
+            let molecules: Vec<&Molecule> = file.compound().iter().collect();
+            assert_eq!(molecules.len(), 4);
+            // The glycine is bonded from the distances, the ligand from `CONECT`
+            assert_eq!(molecules[0].atoms.len(), 4);
+            assert_eq!(molecules[0].bonds.len(), 3);
+            assert_eq!(*molecules[0].atoms[1].get_element(), Element::Carbon);
+            assert_eq!(molecules[1].to_canonical_smiles(), Molecule::from_smiles("[C][C][O]").unwrap().to_canonical_smiles());
+            assert_eq!(molecules[1].coords.as_ref().unwrap()[2], Point::new(12.0, 1.35, 0.0));
+            assert_eq!(*molecules[2].atoms[0].get_ion(), Ion::new(Element::Zinc, 2));
+            let site = &file.sites()[1][0];
+            assert_eq!((site.serial, &site.name[..], &site.residue_name[..], &site.chain[..], site.residue_number, site.hetero),
+                (6, "C1", "EOH", "A", 101, true));
+            assert!(!file.sites()[0][1].hetero);
+            assert_eq!(file.sites()[0][1].name, "CA");
+            let ligands = file.ligands();
+            assert_eq!(ligands.compound().iter().count(), 2);
+            assert_eq!(ligands.sites()[0][0].residue_name, "EOH");
+            assert_eq!(ligands.sites()[1][0].residue_name, "ZN");
+        };
+        check(&file);
+        let cif = "data_TEST
+#
+_entry.id TEST
+#
+loop_
+_atom_type.symbol
+C
+N
+O
+#
+loop_
+_atom_site.group_PDB
+_atom_site.id
+_atom_site.type_symbol
+_atom_site.label_atom_id
+_atom_site.label_alt_id
+_atom_site.label_comp_id
+_atom_site.label_asym_id
+_atom_site.label_seq_id
+_atom_site.pdbx_PDB_ins_code
+_atom_site.Cartn_x
+_atom_site.Cartn_y
+_atom_site.Cartn_z
+_atom_site.pdbx_formal_charge
+_atom_site.auth_seq_id
+_atom_site.auth_asym_id
+_atom_site.pdbx_PDB_model_num
+ATOM   1  N  N   . GLY A 1 ? 0.000  0.000 0.000 0 1   A 1
+ATOM   2  C  CA  . GLY A 1 ? 1.458  0.000 0.000 0 1   A 1
+ATOM   3  C  C   . GLY A 1 ? 2.009  1.420 0.000 0 1   A 1
+ATOM   4  O  O   . GLY A 1 ? 1.246  2.390 0.000 0 1   A 1
+HETATM 6  C  C1  . EOH B . ? 10.000 0.000 0.000 0 101 A 1
+HETATM 7  C  C2  . EOH B . ? 11.512 0.000 0.000 0 101 A 1
+HETATM 8  O  O   A EOH B . ? 12.000 1.350 0.000 0 101 A 1
+HETATM 9  O  O   B EOH B . ? 12.000 -1.350 0.000 0 101 A 1
+HETATM 10 ZN ZN  . ZN  C . ? 30.000 0.000 0.000 2 201 A 1
+HETATM 11 O  O   . HOH D . ? 20.000 0.000 0.000 0 301 A 1
+ATOM   1  N  N   . GLY A 1 ? 0.000  0.000 0.000 0 1   A 2
+#
+";
+        check(&PdbFile::parse_mmcif(cif.as_bytes()).unwrap());
+        assert!(matches!(PdbFile::parse_mmcif("data_TEST\n_entry.id TEST\n".as_bytes()), Err(ParserError::Syntax)));
+        // A zinc near a cysteine stays an ion, even with a `CONECT` to its sulfur, and the serials of more
+        // than 99999 atoms are hybrid-36
+        let pdb = "ATOM  99999  CB  CYS AA000       0.000   0.000   0.000  1.00 20.00           C
+ATOM  A0000  SG  CYS AA000       1.810   0.000   0.000  1.00 20.00           S
+HETATMA0001 ZN    ZN AA001       4.110   0.000   0.000  1.00 20.00          ZN
+HETATMA0002  C1  MOH AA002      10.000   0.000   0.000  1.00 20.00           C
+HETATMa0000  O1  MOH AA002      15.000   0.000   0.000  1.00 20.00           O
+CONECTA0001A0000
+CONECTA0002a0000
+END
+";
+        let file = PdbFile::parse(pdb.as_bytes()).unwrap();
+        assert_eq!(file.compound().iter().map(|m| m.atoms.len()).collect::<Vec<usize>>(), vec![2, 1, 2]);
+        assert_eq!(file.sites().iter().map(|s| s[0].serial).collect::<Vec<usize>>(), vec![99999, 100001, 100002]);
+        assert_eq!(file.sites()[0][1].serial, 100000);
+        assert_eq!(file.sites()[2][1].serial, 43770016);
+        assert_eq!(file.sites()[0][0].residue_number, 10000);
+        let ligands = file.ligands();
+        assert_eq!(ligands.compound().iter().count(), 2);
+        assert_eq!(ligands.sites()[0][0].residue_name, "ZN");
+    }
+
+    #[test]
+    fn inchi_test() {
+        macro_rules! test {
+            ($inchi:expr, $smiles:expr) => {
+                let mut compound = Compound::from_inchi($inchi).unwrap();
+                let mut expected = Compound::from_smiles($smiles).unwrap();
+                compound.perceive_aromaticity(AromaticityModel::Daylight);
+                expected.perceive_aromaticity(AromaticityModel::Daylight);
+                assert_eq!(compound.to_canonical_smiles(), expected.to_canonical_smiles(), "{}", $inchi);
+            };
+        }
+        test!("InChI=1S/C2H6O/c1-2-3/h3H,2H2,1H3", "CCO"); // Ethanol
+        test!("InChI=1S/H2O/h1H2", "O"); // Aqua
+        test!("InChI=1S/H2/h1H", "[H][H]"); // Hydrogen
+        test!("InChI=1S/CO2/c2-1-3", "O=C=O"); // Carbon dioxide
+        test!("InChI=1S/C2H3N/c1-2-3/h1H3", "CC#N"); // Acetonitrile
+        test!("InChI=1S/C6H6/c1-2-4-6-5-3-1/h1-6H", "C1=CC=CC=C1"); // Benzene
+        test!("InChI=1S/C2H4O2/c1-2(3)4/h1H3,(H,3,4)", "CC(=O)O"); // Acetic acid
+        test!("InChI=1S/C2H4O2/c1-2(3)4/h1H3,(H,3,4)/p-1", "CC(=O)[O-]"); // Acetate
+        test!("InChI=1S/H3N/h1H3/p+1", "[NH4+]"); // Ammonium
+        test!("InChI=1S/ClH.Na/h1H;/q;+1/p-1", "[Na+].[Cl-]"); // Sodium chloride
+        test!("InChI=1S/2C2H4O2.Ca/c2*1-2(3)4;/h2*1H3,(H,3,4);/q;;+2/p-2", "CC(=O)[O-].CC(=O)[O-].[Ca+2]"); // Calcium acetate
+        test!("InChI=1S/C4H12N/c1-5(2,3)4/h1-4H3/q+1", "C[N+](C)(C)C"); // Tetramethylammonium
+        test!("InChI=1S/C6H5NO2/c8-7(9)6-4-2-1-3-5-6/h1-5H", "C1=CC=CC=C1N(=O)=O"); // Nitrobenzene
+        test!("InChI=1S/C3H4N2/c1-2-5-3-4-1/h1-3H,(H,4,5)", "C1=CNC=N1"); // Imidazole
+        test!("InChI=1S/C2H6N2/c1-2(3)4/h1H3,(H3,3,4)", "CC(=N)N"); // Acetamidine
+        test!("InChI=1S/C8H10N4O2/c1-10-4-9-6-5(10)7(13)12(3)8(14)11(6)2/h4H,1-3H3", "CN1C=NC2=C1C(=O)N(C)C(=O)N2C"); // Caffeine
+        test!("InChI=1S/p+1", "[H+]"); // Proton
+        test!("InChI=1S/H2O4S/c1-5(2,3)4/h(H2,1,2,3,4)", "OS(=O)(=O)O"); // Sulfuric acid
+        test!("InChI=1S/H2O4S/c1-5(2,3)4/h(H2,1,2,3,4)/p-2", "[O-]S(=O)(=O)[O-]"); // Sulfate
+        test!("InChI=1S/CH4O3S/c1-5(2,3)4/h1H3,(H,2,3,4)", "CS(=O)(=O)O"); // Methanesulfonic acid
+        test!("InChI=1S/HNO3/c2-1(3)4/h(H,2,3,4)", "ON(=O)=O"); // Nitric acid
+        test!("InChI=1S/H3O4P/c1-5(2,3)4/h(H3,1,2,3,4)", "OP(=O)(O)O"); // Phosphoric acid
+        let inchi = InChI::parse("InChI=1S/2C2H4O2.Ca/c2*1-2(3)4;/h2*1H3,(H,3,4);/q;;+2/p-2").unwrap();
+        assert_eq!(inchi.formulas().len(), 3);
+        assert_eq!(inchi.formulas()[0], EmpiricalFormula::from_string("C2H4O2").unwrap());
+        // The stereo layers are skipped
+        let alanine = Compound::from_inchi("InChI=1S/C3H7NO2/c1-2(4)3(5)6/h2H,4H2,1H3,(H,5,6)/t2-/m0/s1").unwrap();
+        assert_eq!(alanine.to_canonical_smiles(), Compound::from_smiles("CC(N)C(=O)O").unwrap().to_canonical_smiles());
+        assert!(matches!(Compound::from_inchi("InChI=1S/C2H6O/c1-2-4"),
+            Err(ParserError::Parse(ParseError { position: 20, reason: ParseErrorReason::BadAtomNumber, .. }))));
+        assert!(matches!(Compound::from_inchi("InChI=1S/C2H6O/c99999999999999999999"),
+            Err(ParserError::Parse(ParseError { position: 16, reason: ParseErrorReason::BadAtomNumber, .. }))));
+        // The hydrogens must be the ones of the formula
+        for wrong in ["InChI=1S/C2H6O/c1-2-3/h1H99999", "InChI=1S/C2H6O/c1-2-3/h1H999999999999999999",
+            "InChI=1S/C2H6O/c1-2-3/h1H99999999999999999999999", "InChI=1S/C2H6O/c1-2-3/h3H,2H2", "InChI=1S/C2H6O/c1-2-3"].iter() {
+            assert!(matches!(Compound::from_inchi(wrong), Err(ParserError::Parse(ParseError { reason: ParseErrorReason::BadHydrogenCount, .. }))),
+                "{}", wrong);
+        }
+        // The protons can't take the charge out of range
+        assert!(matches!(Compound::from_inchi("InChI=1S/H3N/h1H3/q+127/p+1"),
+            Err(ParserError::Parse(ParseError { position: 19, reason: ParseErrorReason::BadCharge, .. }))));
+        assert!(matches!(Compound::from_inchi("InChl=1S/H2O/h1H2"),
+            Err(ParserError::Parse(ParseError { position: 4, .. }))));
+    }
+
+    #[test]
+    fn inchi_writer_test() {
+        macro_rules! test {
+            ($smiles:expr, $inchi:expr, $key:expr) => {
+                let compound = Compound::from_smiles($smiles).unwrap();
+                assert_eq!(compound.to_inchi(), $inchi, "{}", $smiles);
+                assert_eq!(compound.to_inchi_key(), $key, "{}", $smiles);
+            };
+        }
+        test!("CCO", "InChI=1S/C2H6O/c1-2-3/h3H,2H2,1H3", "LFQSCWFLJHTTHZ-UHFFFAOYSA-N"); // Ethanol
+        test!("O", "InChI=1S/H2O/h1H2", "XLYOFNOQVPJJNP-UHFFFAOYSA-N"); // Aqua
+        test!("c1ccccc1", "InChI=1S/C6H6/c1-2-4-6-5-3-1/h1-6H", "UHOVQNZJYSORNB-UHFFFAOYSA-N"); // Benzene
+        test!("Cc1ccccc1", "InChI=1S/C7H8/c1-7-5-3-2-4-6-7/h2-6H,1H3", "YXFVVABEGXRONW-UHFFFAOYSA-N"); // Toluene
+        test!("c1ccc2ccccc2c1", "InChI=1S/C10H8/c1-2-6-10-8-4-3-7-9(10)5-1/h1-8H", "UFWIBTONFRDIAS-UHFFFAOYSA-N"); // Naphthalene
+        test!("NC(N)=O", "InChI=1S/CH4N2O/c2-1(3)4/h(H4,2,3,4)", "XSQUKJJJFZCRTK-UHFFFAOYSA-N"); // Urea
+        test!("NCC(=O)O", "InChI=1S/C2H5NO2/c3-1-2(4)5/h1,3H2,(H,4,5)", "DHMQDGOQFOQNFH-UHFFFAOYSA-N"); // Glycine
+        test!("[NH3+]CC(=O)[O-]", "InChI=1S/C2H5NO2/c3-1-2(4)5/h1,3H2,(H,4,5)", "DHMQDGOQFOQNFH-UHFFFAOYSA-N"); // Zwitterion
+        test!("CN1C=NC2=C1C(=O)N(C)C(=O)N2C", "InChI=1S/C8H10N4O2/c1-10-4-9-6-5(10)7(13)12(3)8(14)11(6)2/h4H,1-3H3",
+            "RYYVLZVUVIJVGH-UHFFFAOYSA-N"); // Caffeine
+        test!("Cn1cnc2c1c(=O)n(C)c(=O)n2C", "InChI=1S/C8H10N4O2/c1-10-4-9-6-5(10)7(13)12(3)8(14)11(6)2/h4H,1-3H3",
+            "RYYVLZVUVIJVGH-UHFFFAOYSA-N"); // Aromatic caffeine
+        test!("c1ccsc1", "InChI=1S/C4H4S/c1-2-4-5-3-1/h1-4H", "YTPLMLYBLZKORZ-UHFFFAOYSA-N"); // Thiophene
+        test!("CC(=O)Oc1ccccc1C(=O)O", "InChI=1S/C9H8O4/c1-6(10)13-8-5-3-2-4-7(8)9(11)12/h2-5H,1H3,(H,11,12)",
+            "BSYNRYMUTXBXSQ-UHFFFAOYSA-N"); // Aspirin
+        test!("CC(C)Cc1ccc(cc1)C(C)C(=O)O", "InChI=1S/C13H18O2/c1-9(2)8-11-4-6-12(7-5-11)10(3)13(14)15/h4-7,9-10H,8H2,1-3H3,(H,14,15)",
+            "HEFNNWSXXWATRW-UHFFFAOYSA-N"); // Ibuprofen
+        test!("Nc1ncnc2[nH]cnc12", "InChI=1S/C5H5N5/c6-4-3-5(9-1-7-3)10-2-8-4/h1-2H,(H3,6,7,8,9,10)",
+            "GFFGJBXGBJISGV-UHFFFAOYSA-N"); // Adenine
+        test!("OP(=O)(O)O", "InChI=1S/H3O4P/c1-5(2,3)4/h(H3,1,2,3,4)", "NBIIXXVUZAFLBC-UHFFFAOYSA-N"); // Phosphoric acid
+        test!("OC(=O)C(=O)O", "InChI=1S/C2H2O4/c3-1(4)2(5)6/h(H,3,4)(H,5,6)", "MUBZPKHOEPUJKR-UHFFFAOYSA-N"); // Oxalic acid
+        test!("Nc1ncnc2c1ncn2C1OC(COP(=O)(O)OP(=O)(O)OP(=O)(O)O)C(O)C1O",
+            "InChI=1S/C10H16N5O13P3/c11-8-5-9(13-2-12-8)15(3-14-5)10-7(17)6(16)4(26-10)1-25-30(21,22)28-31(23,24)27-29(18,19)20/h2-4,6-7,10,16-17H,1H2,(H,21,22)(H,23,24)(H2,11,12,13)(H2,18,19,20)",
+            "ZKHQWZAMYRWXGA-UHFFFAOYSA-N"); // ATP
+        test!("[Na+].[Cl-]", "InChI=1S/ClH.Na/h1H;/q;+1/p-1", "FAPWRFPIFSIZLT-UHFFFAOYSA-M"); // Sodium chloride
+        test!("CC(=O)[O-].[Na+]", "InChI=1S/C2H4O2.Na/c1-2(3)4;/h1H3,(H,3,4);/q;+1/p-1", "VMHLLURERBWHNL-UHFFFAOYSA-M"); // Sodium acetate
+        assert_eq!(Compound::from_smiles("CC(=O)[O-].CC(=O)[O-].[Ca+2]").unwrap().to_inchi(),
+            "InChI=1S/2C2H4O2.Ca/c2*1-2(3)4;/h2*1H3,(H,3,4);/q;;+2/p-2");
+        assert_eq!(Compound::from_smiles("C[N+](C)(C)C").unwrap().to_inchi(), "InChI=1S/C4H12N/c1-5(2,3)4/h1-4H3/q+1");
+        assert_eq!(Compound::from_smiles("[H+]").unwrap().to_inchi(), "InChI=1S/p+1");
+        // The InChI is read back to the same molecule
+        let caffeine = Compound::from_smiles("CN1C=NC2=C1C(=O)N(C)C(=O)N2C").unwrap();
+        let inchi = Compound::from_inchi(&caffeine.to_inchi()).unwrap();
+        assert_eq!(inchi.to_inchi(), caffeine.to_inchi());
+        let atp = Compound::from_smiles("Nc1ncnc2c1ncn2C1OC(COP(=O)(O)OP(=O)(O)OP(=O)(O)O)C(O)C1O").unwrap();
+        assert_eq!(Compound::from_inchi(&atp.to_inchi()).unwrap().to_inchi(), atp.to_inchi());
+        assert_eq!(InChI::key("InChI=1S/C3H7NO2/c1-2(4)3(5)6/h2H,4H2,1H3,(H,5,6)/t2-/m0/s1").unwrap(),
+            "QNAYBMKLOCPYGJ-REOHCLBHSA-N"); // L-alanine
+        assert_eq!(InChI::key("InChI=1S/H3N/h1H3/p+1").unwrap(), "QGZKDVFQNNGYKY-UHFFFAOYSA-O"); // Ammonium
+        assert_eq!(InChI::key("SMILES=CCO"), None);
+    }
+
+    #[test]
+    fn sdf_reader_test() {
+        let records = Compound::from_smiles("CCO").unwrap().to_sdf().replace("$$$$\n",
+            "> <ID>\nCHEM-001\n\n>  25  <DESCRIPTION> (MD-1)\nEthanol\nsecond line\n\n$$$$\n") +
+            &Compound::from_smiles("[Na+].[Cl-]").unwrap().to_sdf();
+        let mut reader = SdfReader::new(records.as_bytes());
+        let (ethanol, fields) = reader.next().unwrap().unwrap();
+        assert_eq!(ethanol.to_canonical_smiles(), Compound::from_smiles("CCO").unwrap().to_canonical_smiles());
+        assert_eq!(fields.len(), 2);
+        assert_eq!(fields["ID"], "CHEM-001");
+        assert_eq!(fields["DESCRIPTION"], "Ethanol\nsecond line");
+        let (_, fields) = reader.next().unwrap().unwrap();
+        assert!(fields.is_empty());
+        let (_, fields) = reader.next().unwrap().unwrap();
+        assert!(fields.is_empty());
+        assert!(reader.next().is_none());
+        // A record without `M  END` is an error, and the last `$$$$` can be missing
+        let mut reader = SdfReader::new("broken\n$$$$\n".as_bytes());
+        assert!(matches!(reader.next(), Some(Err(ParserError::UnexpectedEof))));
+        assert!(reader.next().is_none());
+        let water = Compound::from_smiles("O").unwrap().to_sdf().replace("$$$$\n", "");
+        assert_eq!(SdfReader::new(water.as_bytes()).count(), 1);
     }
 
     #[test]
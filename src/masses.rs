@@ -0,0 +1,178 @@
+use ptable::Element;
+
+/// An isotope of an element, with its mass in daltons and its natural abundance as a fraction.
+/// The isotopes that are only made in the laboratory have an abundance of zero
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct NaturalIsotope {
+    pub mass_number: u16,
+    pub mass: f64,
+    pub abundance: f64,
+}
+
+const fn i(mass_number: u16, mass: f64, abundance: f64) -> NaturalIsotope {
+    NaturalIsotope { mass_number, mass, abundance }
+}
+
+/// The isotopes by atomic number, from the NIST atomic weights and isotopic compositions
+const ISOTOPES: [&[NaturalIsotope]; 92] = [
+    &[i(1, 1.00782503223, 0.999885), i(2, 2.01410177812, 0.000115), i(3, 3.0160492779, 0.0)],
+    &[i(3, 3.0160293201, 0.00000134), i(4, 4.00260325413, 0.99999866)],
+    &[i(6, 6.0151228874, 0.0759), i(7, 7.0160034366, 0.9241)],
+    &[i(9, 9.012183065, 1.0)],
+    &[i(10, 10.01293695, 0.199), i(11, 11.00930536, 0.801)],
+    &[i(11, 11.0114336, 0.0), i(12, 12.0, 0.9893), i(13, 13.00335483507, 0.0107), i(14, 14.0032419884, 0.0)],
+    &[i(13, 13.00573861, 0.0), i(14, 14.00307400443, 0.99636), i(15, 15.00010889888, 0.00364)],
+    &[i(15, 15.0030656, 0.0), i(16, 15.99491461957, 0.99757), i(17, 16.9991317565, 0.00038), i(18, 17.99915961286, 0.00205)],
+    &[i(18, 18.0009373, 0.0), i(19, 18.99840316273, 1.0)],
+    &[i(20, 19.9924401762, 0.9048), i(21, 20.993846685, 0.0027), i(22, 21.991385114, 0.0925)],
+    &[i(22, 21.9944364, 0.0), i(23, 22.989769282, 1.0)],
+    &[i(24, 23.985041697, 0.7899), i(25, 24.985836976, 0.1), i(26, 25.982592968, 0.1101)],
+    &[i(27, 26.98153853, 1.0)],
+    &[i(28, 27.97692653465, 0.92223), i(29, 28.9764946649, 0.04685), i(30, 29.973770136, 0.03092)],
+    &[i(31, 30.97376199842, 1.0), i(32, 31.97390764, 0.0)],
+    &[i(32, 31.9720711744, 0.9499), i(33, 32.9714589098, 0.0075), i(34, 33.967867004, 0.0425), i(35, 34.96903231, 0.0),
+        i(36, 35.96708071, 0.0001)],
+    &[i(35, 34.968852682, 0.7576), i(37, 36.965902602, 0.2424)],
+    &[i(36, 35.967545105, 0.003336), i(38, 37.96273211, 0.000629), i(40, 39.9623831237, 0.996035)],
+    &[i(39, 38.9637064864, 0.932581), i(40, 39.963998166, 0.000117), i(41, 40.9618252579, 0.067302)],
+    &[i(40, 39.962590863, 0.96941), i(42, 41.95861783, 0.00647), i(43, 42.95876644, 0.00135), i(44, 43.95548156, 0.02086),
+        i(46, 45.953689, 0.00004), i(48, 47.95252276, 0.00187)],
+    &[i(45, 44.95590828, 1.0)],
+    &[i(46, 45.95262772, 0.0825), i(47, 46.95175879, 0.0744), i(48, 47.94794198, 0.7372), i(49, 48.94786568, 0.0541),
+        i(50, 49.94478689, 0.0518)],
+    &[i(50, 49.94715601, 0.0025), i(51, 50.94395704, 0.9975)],
+    &[i(50, 49.94604183, 0.04345), i(52, 51.94050623, 0.83789), i(53, 52.94064815, 0.09501), i(54, 53.93887916, 0.02365)],
+    &[i(55, 54.93804391, 1.0)],
+    &[i(54, 53.93960899, 0.05845), i(56, 55.93493633, 0.91754), i(57, 56.93539284, 0.02119), i(58, 57.93327443, 0.00282)],
+    &[i(59, 58.93319429, 1.0), i(60, 59.9338163, 0.0)],
+    &[i(58, 57.93534241, 0.68077), i(60, 59.93078588, 0.26223), i(61, 60.93105557, 0.011399), i(62, 61.92834537, 0.036346),
+        i(64, 63.92796682, 0.009255)],
+    &[i(63, 62.92959772, 0.6915), i(65, 64.9277897, 0.3085)],
+    &[i(64, 63.92914201, 0.4917), i(66, 65.92603381, 0.2773), i(67, 66.92712775, 0.0404), i(68, 67.92484455, 0.1845),
+        i(70, 69.9253192, 0.0061)],
+    &[i(69, 68.9255735, 0.60108), i(71, 70.92470258, 0.39892)],
+    &[i(70, 69.92424875, 0.2057), i(72, 71.922075826, 0.2745), i(73, 72.923458956, 0.0775), i(74, 73.921177761, 0.365),
+        i(76, 75.921402726, 0.0773)],
+    &[i(75, 74.92159457, 1.0)],
+    &[i(74, 73.922475934, 0.0089), i(76, 75.919213704, 0.0937), i(77, 76.919914154, 0.0763), i(78, 77.91730928, 0.2377),
+        i(80, 79.9165218, 0.4961), i(82, 81.9166995, 0.0873)],
+    &[i(79, 78.9183376, 0.5069), i(81, 80.9162897, 0.4931)],
+    &[i(78, 77.92036494, 0.00355), i(80, 79.91637808, 0.02286), i(82, 81.91348273, 0.11593), i(83, 82.91412716, 0.115),
+        i(84, 83.9114977282, 0.56987), i(86, 85.9106106269, 0.17279)],
+    &[i(85, 84.9117897379, 0.7217), i(87, 86.909180531, 0.2783)],
+    &[i(84, 83.9134191, 0.0056), i(86, 85.9092606, 0.0986), i(87, 86.9088775, 0.07), i(88, 87.9056125, 0.8258)],
+    &[i(89, 88.9058403, 1.0)],
+    &[i(90, 89.9046977, 0.5145), i(91, 90.9056396, 0.1122), i(92, 91.9050347, 0.1715), i(94, 93.9063108, 0.1738),
+        i(96, 95.9082714, 0.028)],
+    &[i(93, 92.906373, 1.0)],
+    &[i(92, 91.90680796, 0.1453), i(94, 93.9050849, 0.0915), i(95, 94.90583877, 0.1584), i(96, 95.90467612, 0.1667),
+        i(97, 96.90601812, 0.096), i(98, 97.90540482, 0.2439), i(100, 99.9074718, 0.0982)],
+    &[i(98, 97.9072124, 0.0), i(99, 98.9062508, 0.0)],
+    &[i(96, 95.90759025, 0.0554), i(98, 97.9052868, 0.0187), i(99, 98.9059341, 0.1276), i(100, 99.9042143, 0.126),
+        i(101, 100.9055769, 0.1706), i(102, 101.9043441, 0.3155), i(104, 103.9054275, 0.1862)],
+    &[i(103, 102.905498, 1.0)],
+    &[i(102, 101.9056022, 0.0102), i(104, 103.9040305, 0.1114), i(105, 104.9050796, 0.2233), i(106, 105.9034804, 0.2733),
+        i(108, 107.9038916, 0.2646), i(110, 109.9051722, 0.1172)],
+    &[i(107, 106.9050916, 0.51839), i(109, 108.9047553, 0.48161)],
+    &[i(106, 105.9064599, 0.0125), i(108, 107.9041834, 0.0089), i(110, 109.90300661, 0.1249), i(111, 110.90418287, 0.128),
+        i(112, 111.90276287, 0.2413), i(113, 112.90440813, 0.1222), i(114, 113.90336509, 0.2873), i(116, 115.90476315, 0.0749)],
+    &[i(113, 112.90406184, 0.0429), i(115, 114.903878776, 0.9571)],
+    &[i(112, 111.90482387, 0.0097), i(114, 113.9027827, 0.0066), i(115, 114.903344699, 0.0034), i(116, 115.9017428, 0.1454),
+        i(117, 116.90295398, 0.0768), i(118, 117.90160657, 0.2422), i(119, 118.90331117, 0.0859), i(120, 119.90220163, 0.3258),
+        i(122, 121.9034438, 0.0463), i(124, 123.9052766, 0.0579)],
+    &[i(121, 120.903812, 0.5721), i(123, 122.9042132, 0.4279)],
+    &[i(120, 119.9040593, 0.0009), i(122, 121.9030435, 0.0255), i(123, 122.9042698, 0.0089), i(124, 123.9028171, 0.0474),
+        i(125, 124.9044299, 0.0707), i(126, 125.9033109, 0.1884), i(128, 127.90446128, 0.3174), i(130, 129.906222748, 0.3408)],
+    &[i(123, 122.905589, 0.0), i(125, 124.9046294, 0.0), i(127, 126.9044719, 1.0), i(131, 130.9061263, 0.0)],
+    &[i(124, 123.905892, 0.000952), i(126, 125.9042983, 0.00089), i(128, 127.903531, 0.019102), i(129, 128.9047808611, 0.264006),
+        i(130, 129.903509349, 0.04071), i(131, 130.90508406, 0.212324), i(132, 131.9041550856, 0.269086),
+        i(134, 133.90539466, 0.104357), i(136, 135.907214484, 0.088573)],
+    &[i(133, 132.905451961, 1.0)],
+    &[i(130, 129.9063207, 0.00106), i(132, 131.9050611, 0.00101), i(134, 133.90450818, 0.02417), i(135, 134.90568838, 0.06592),
+        i(136, 135.90457573, 0.07854), i(137, 136.90582714, 0.11232), i(138, 137.905247, 0.71698)],
+    &[i(138, 137.9071149, 0.0008881), i(139, 138.9063563, 0.9991119)],
+    &[i(136, 135.90712921, 0.00185), i(138, 137.905991, 0.00251), i(140, 139.9054431, 0.8845), i(142, 141.9092504, 0.11114)],
+    &[i(141, 140.9076576, 1.0)],
+    &[i(142, 141.907729, 0.27152), i(143, 142.90982, 0.12174), i(144, 143.910093, 0.23798), i(145, 144.9125793, 0.08293),
+        i(146, 145.9131226, 0.17189), i(148, 147.9168993, 0.05756), i(150, 149.9209022, 0.05638)],
+    &[i(145, 144.9127559, 0.0), i(147, 146.915145, 0.0)],
+    &[i(144, 143.9120065, 0.0307), i(147, 146.9149044, 0.1499), i(148, 147.9148292, 0.1124), i(149, 148.9171921, 0.1382),
+        i(150, 149.9172829, 0.0738), i(152, 151.9197397, 0.2675), i(154, 153.9222169, 0.2275)],
+    &[i(151, 150.9198578, 0.4781), i(153, 152.921238, 0.5219)],
+    &[i(152, 151.9197995, 0.002), i(154, 153.9208741, 0.0218), i(155, 154.9226305, 0.148), i(156, 155.9221312, 0.2047),
+        i(157, 156.9239686, 0.1565), i(158, 157.9241123, 0.2484), i(160, 159.9270624, 0.2186)],
+    &[i(159, 158.9253547, 1.0)],
+    &[i(156, 155.9242847, 0.00056), i(158, 157.9244159, 0.00095), i(160, 159.9252046, 0.02329), i(161, 160.9269405, 0.18889),
+        i(162, 161.9268056, 0.25475), i(163, 162.9287383, 0.24896), i(164, 163.9291819, 0.2826)],
+    &[i(165, 164.9303288, 1.0)],
+    &[i(162, 161.9287884, 0.00139), i(164, 163.9292088, 0.01601), i(166, 165.9302995, 0.33503), i(167, 166.9320546, 0.22869),
+        i(168, 167.9323767, 0.26978), i(170, 169.9354702, 0.1491)],
+    &[i(169, 168.9342179, 1.0)],
+    &[i(168, 167.9338896, 0.00123), i(170, 169.9347664, 0.02982), i(171, 170.9363302, 0.1409), i(172, 171.9363859, 0.2168),
+        i(173, 172.9382151, 0.16103), i(174, 173.9388664, 0.32026), i(176, 175.9425764, 0.12996)],
+    &[i(175, 174.9407752, 0.97401), i(176, 175.9426897, 0.02599)],
+    &[i(174, 173.9400461, 0.0016), i(176, 175.9414076, 0.0526), i(177, 176.9432277, 0.186), i(178, 177.9437058, 0.2728),
+        i(179, 178.9458232, 0.1362), i(180, 179.946557, 0.3508)],
+    &[i(180, 179.9474648, 0.0001201), i(181, 180.9479958, 0.9998799)],
+    &[i(180, 179.9467108, 0.0012), i(182, 181.94820394, 0.265), i(183, 182.95022275, 0.1431), i(184, 183.95093092, 0.3064),
+        i(186, 185.9543628, 0.2843)],
+    &[i(185, 184.9529545, 0.374), i(187, 186.9557501, 0.626)],
+    &[i(184, 183.9524885, 0.0002), i(186, 185.953835, 0.0159), i(187, 186.9557474, 0.0196), i(188, 187.9558352, 0.1324),
+        i(189, 188.9581442, 0.1615), i(190, 189.9584437, 0.2626), i(192, 191.961477, 0.4078)],
+    &[i(191, 190.9605893, 0.373), i(193, 192.9629216, 0.627)],
+    &[i(190, 189.9599297, 0.00012), i(192, 191.9610387, 0.00782), i(194, 193.9626809, 0.3286), i(195, 194.9647917, 0.3378),
+        i(196, 195.96495209, 0.2521), i(198, 197.9678949, 0.07356)],
+    &[i(197, 196.96656879, 1.0)],
+    &[i(196, 195.9658326, 0.0015), i(198, 197.9667686, 0.0997), i(199, 198.96828064, 0.1687), i(200, 199.96832659, 0.231),
+        i(201, 200.97030284, 0.1318), i(202, 201.9706434, 0.2986), i(204, 203.97349398, 0.0687)],
+    &[i(203, 202.9723446, 0.2952), i(205, 204.9744278, 0.7048)],
+    &[i(204, 203.973044, 0.014), i(206, 205.9744657, 0.241), i(207, 206.9758973, 0.221), i(208, 207.9766525, 0.524)],
+    &[i(209, 208.9803991, 1.0)],
+    &[i(209, 208.9824308, 0.0), i(210, 209.9828741, 0.0)],
+    &[i(210, 209.9871479, 0.0), i(211, 210.9874966, 0.0)],
+    &[i(222, 222.0175782, 0.0)],
+    &[i(223, 223.019736, 0.0)],
+    &[i(226, 226.0254103, 0.0)],
+    &[i(227, 227.0277523, 0.0)],
+    &[i(232, 232.0380558, 1.0)],
+    &[i(231, 231.0358842, 1.0)],
+    &[i(234, 234.0409523, 0.000054), i(235, 235.0439301, 0.007204), i(238, 238.0507884, 0.992742)],
+];
+
+/// Known isotopes of the element, empty for the elements after uranium
+pub fn isotopes_of(element: Element) -> &'static [NaturalIsotope] {
+    ISOTOPES.get(element.get_atomic_number() - 1).copied().unwrap_or(&[])
+}
+
+/// Mass of the most abundant isotope, or of the known isotope for the elements that are not found
+/// in nature
+pub fn monoisotopic_mass(element: Element) -> f64 {
+    let isotopes = isotopes_of(element);
+    match isotopes.iter().fold(None, |best: Option<&NaturalIsotope>, i| match best {
+        Some(b) if b.abundance >= i.abundance => Some(b),
+        _ => Some(i)
+    }) {
+        Some(isotope) => isotope.mass,
+        None => element.get_atomic_mass() as f64
+    }
+}
+
+/// Mass of an isotope from its mass number, the mass number itself is used for the isotopes that
+/// are not in the table
+pub fn isotope_mass(element: Element, mass_number: usize) -> f64 {
+    match isotopes_of(element).iter().find(|i| i.mass_number as usize == mass_number) {
+        Some(isotope) => isotope.mass,
+        None => mass_number as f64
+    }
+}
+
+/// Mass averaged over the natural abundances, the standard atomic weight is used for the elements
+/// that are not found in nature
+pub fn average_mass(element: Element) -> f64 {
+    let isotopes = isotopes_of(element);
+    let total: f64 = isotopes.iter().map(|i| i.abundance).sum();
+    if total == 0.0 {
+        return element.get_atomic_mass() as f64;
+    }
+    isotopes.iter().map(|i| i.mass * i.abundance).sum::<f64>() / total
+}
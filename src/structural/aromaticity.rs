@@ -0,0 +1,195 @@
+use std::collections::BTreeSet;
+
+use ptable::Element;
+
+use super::rings::{self, Ring};
+use super::{Molecule, StructuralBond};
+
+/// Rules used to decide which rings are aromatic
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Hash)]
+pub enum AromaticityModel {
+    /// Rings and fused ring systems with 4n+2 π electrons, the lone pairs of the heteroatoms (pyrrole,
+    /// furan), the charged carbons and the exocyclic `C=O`, `C=N` and `C=S` (pyridone) are taken into account
+    Daylight,
+    /// Only rings of alternating single and double bonds (benzene, pyridine), without lone pairs
+    Mdl,
+}
+
+/// Rings of a fused system that are combined to look for bigger aromatic cycles, all the combinations
+/// are tried up to this number of rings and only the pairs on bigger systems
+const MAX_FUSED_COMBINATIONS: usize = 6;
+
+/// π electrons that an atom gives to a ring of its ring system, `None` if it cannot be aromatic
+fn electrons(molecule: &Molecule, id: usize, in_system: &[bool], model: AromaticityModel) -> Option<usize> {
+    let atom = &molecule.atoms[id];
+    let element = *atom.get_element();
+    let charge = *atom.get_ion().get_charge();
+    let connections = atom.bonds.len();
+    let mut aromatic = false;
+    let mut endocyclic_double = false;
+    let mut exocyclic_double = None;
+    for bond_id in atom.bonds.iter() {
+        let bond = molecule.bonds[*bond_id];
+        let pair = if bond.a == id { bond.b } else { bond.a };
+        match bond.k {
+            StructuralBond::Aromatic if in_system[*bond_id] => aromatic = true,
+            StructuralBond::Double if in_system[*bond_id] => endocyclic_double = true,
+            StructuralBond::Double => exocyclic_double = Some(*molecule.atoms[pair].get_element()),
+            StructuralBond::Triple => return None,
+            _ => {}
+        }
+    }
+    if atom.wildcard {
+        return None;
+    }
+    if let (Some(pair), AromaticityModel::Daylight) = (exocyclic_double, model) {
+        if !endocyclic_double {
+            // The electrons go to the more electronegative atom, as in the pyridones
+            return match (element, pair) {
+                (Element::Carbon, Element::Oxygen) | (Element::Carbon, Element::Nitrogen) |
+                    (Element::Carbon, Element::Sulfur) => Some(0),
+                _ => None
+            };
+        }
+    }
+    if aromatic {
+        // Already aromatic, as the lowercase atoms of SMILES
+        if exocyclic_double.is_some() {
+            return None;
+        }
+        return match (element, charge, model) {
+            (Element::Carbon, 0, _) => Some(1),
+            (Element::Carbon, -1, AromaticityModel::Daylight) => Some(2),
+            (Element::Carbon, 1, AromaticityModel::Daylight) => Some(0),
+            (Element::Nitrogen, 0, _) | (Element::Phosphorus, 0, _) if connections == 2 => Some(1),
+            (Element::Nitrogen, 1, _) | (Element::Phosphorus, 1, _) if connections == 3 => Some(1),
+            (Element::Nitrogen, 0, AromaticityModel::Daylight) | (Element::Phosphorus, 0, AromaticityModel::Daylight)
+                if connections == 3 => Some(2),
+            (Element::Oxygen, 0, AromaticityModel::Daylight) | (Element::Sulfur, 0, AromaticityModel::Daylight) |
+                (Element::Selenium, 0, AromaticityModel::Daylight) | (Element::Tellurium, 0, AromaticityModel::Daylight) => Some(2),
+            (Element::Oxygen, 1, _) | (Element::Sulfur, 1, _) | (Element::Selenium, 1, _) | (Element::Tellurium, 1, _) => Some(1),
+            (Element::Boron, 0, AromaticityModel::Daylight) => Some(0),
+            _ => None
+        };
+    }
+    if endocyclic_double {
+        return if exocyclic_double.is_some() { None } else { Some(1) };
+    }
+    if model == AromaticityModel::Mdl || exocyclic_double.is_some() {
+        return None;
+    }
+    match (element, charge) {
+        (Element::Nitrogen, 0) | (Element::Phosphorus, 0) if connections == 3 => Some(2),
+        (Element::Nitrogen, -1) if connections == 2 => Some(2),
+        (Element::Oxygen, 0) | (Element::Sulfur, 0) | (Element::Selenium, 0) | (Element::Tellurium, 0)
+            if connections == 2 => Some(2),
+        (Element::Carbon, -1) if connections == 3 => Some(2),
+        (Element::Carbon, 1) if connections == 3 => Some(0),
+        (Element::Boron, 0) if connections == 3 => Some(0),
+        _ => None
+    }
+}
+
+/// Cycles that are checked: the rings and, for the Daylight model, the envelopes of fused rings. Every
+/// cycle goes with the bonds that become aromatic if it is, for the envelopes they include the bonds
+/// shared by the rings (as the central bond of azulene)
+fn candidate_cycles(molecule: &Molecule, rings: &[Ring], model: AromaticityModel) -> Vec<(Ring, Vec<usize>)> {
+    let mut cycles: Vec<(Ring, Vec<usize>)> = rings.iter().map(|r| (r.clone(), r.bonds.clone())).collect();
+    if model == AromaticityModel::Mdl {
+        return cycles;
+    }
+    // Group the rings that share bonds
+    let mut system = vec![usize::MAX; rings.len()];
+    let mut systems = 0;
+    for i in 0..rings.len() {
+        if system[i] != usize::MAX {
+            continue;
+        }
+        system[i] = systems;
+        let mut stack = vec![i];
+        while let Some(r) = stack.pop() {
+            for j in 0..rings.len() {
+                if system[j] == usize::MAX && rings[j].bonds.iter().any(|b| rings[r].bonds.contains(b)) {
+                    system[j] = systems;
+                    stack.push(j);
+                }
+            }
+        }
+        systems += 1;
+    }
+    for s in 0..systems {
+        let members: Vec<usize> = (0..rings.len()).filter(|r| system[*r] == s).collect();
+        if members.len() < 2 {
+            continue;
+        }
+        let max_size = if members.len() <= MAX_FUSED_COMBINATIONS { members.len() } else { 2 };
+        let mut combination = Vec::new();
+        combine(molecule, rings, &members, 0, max_size, &mut combination, &mut cycles);
+    }
+    cycles
+}
+
+fn combine(molecule: &Molecule, rings: &[Ring], members: &[usize], start: usize, max_size: usize,
+    combination: &mut Vec<usize>, cycles: &mut Vec<(Ring, Vec<usize>)>) {
+    if combination.len() >= 2 {
+        // The envelope is the symmetric difference of the bonds of the rings
+        let mut bonds = BTreeSet::new();
+        let mut all = BTreeSet::new();
+        for r in combination.iter() {
+            for b in rings[*r].bonds.iter() {
+                all.insert(*b);
+                if !bonds.remove(b) {
+                    bonds.insert(*b);
+                }
+            }
+        }
+        if let Some(cycle) = Ring::from_bonds(molecule, &bonds) {
+            cycles.push((cycle, all.into_iter().collect()));
+        }
+    }
+    if combination.len() == max_size {
+        return;
+    }
+    for i in start..members.len() {
+        combination.push(members[i]);
+        combine(molecule, rings, members, i + 1, max_size, combination, cycles);
+        combination.pop();
+    }
+}
+
+/// Mark as aromatic the bonds of the rings that are aromatic for the model, the bonds that are
+/// already aromatic are kept as they are
+pub fn perceive(molecule: &mut Molecule, model: AromaticityModel) {
+    let rings = rings::sssr(molecule);
+    if rings.is_empty() {
+        return;
+    }
+    let mut in_system = vec![false; molecule.bonds.len()];
+    for ring in rings.iter() {
+        for b in ring.bonds.iter() {
+            in_system[*b] = true;
+        }
+    }
+    let contributions: Vec<Option<usize>> = (0..molecule.atoms.len())
+        .map(|id| electrons(molecule, id, &in_system, model)).collect();
+    let mut aromatic = BTreeSet::new();
+    for (cycle, bonds) in candidate_cycles(molecule, &rings, model) {
+        let mut total = 0;
+        for id in cycle.atoms.iter() {
+            match contributions[*id] {
+                Some(e) => total += e,
+                None => {
+                    total = 0;
+                    break;
+                }
+            }
+        }
+        // Hückel's rule
+        if total % 4 == 2 {
+            aromatic.extend(bonds);
+        }
+    }
+    for b in aromatic {
+        molecule.bonds[b].k = StructuralBond::Aromatic;
+    }
+}
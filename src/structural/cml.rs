@@ -1,235 +1,514 @@
-use std::io::Read;
-use std::hint::unreachable_unchecked;
+use std::collections::BTreeMap;
+use std::io::{self, Read, Write};
+use std::num::NonZeroU8;
 
 use xml::{EventReader, reader::XmlEvent};
+use xml::writer::{EmitterConfig, XmlEvent as WriterEvent};
+use xml::name::OwnedName;
 use fnv::FnvHashMap;
 use ptable::Element;
-use super::{AtomAndBondI, ParserError};
+use super::{AtomAndBondI, BondStereo, Chirality, ParserError};
+use super::smiles::is_odd_permutation;
 use crate::*;
 
-#[derive(Debug)]
-struct Data {
-    atoms: FnvHashMap<String, (Element, Point)>,
-    bond_index: FnvHashMap<String, Vec<usize>>,
-    bonds: Vec<(String, String, StructuralBond)>
-}
+const CML_NAMESPACE: &str = "http://www.xml-cml.org/schema";
 
 enum CMLRegion {
     AtomArray,
     BondArray,
+    Name,
+    /// Inside a `property`, with its title (or its `dictRef`)
+    Property(String),
+    Scalar(String),
+    /// Inside the `atomParity` of an atom, with its `atomRefs4`
+    AtomParity(usize, Vec<String>),
+    /// Inside the `bondStereo` of a bond, with its `atomRefs4`
+    BondStereo(usize, Vec<String>),
     None
 }
 
-pub fn parse<R: Read>(reader: R) -> Result<Compound, ParserError> {
-    let mut xml_reader = EventReader::new(reader);
-    let mut in_molecule = false;
-    let mut cml_region = CMLRegion::None;
-    let mut atoms = FnvHashMap::default();
-    let mut bonds = Vec::new();
-    let mut bond_index = FnvHashMap::default();
-    loop {
-        let ev = xml_reader.next()?;
-        match ev {
-            XmlEvent::StartElement { name, attributes, namespace:_ } => {
-                if name.namespace.is_some() {
-                    // Ignore any unknown element
-                    continue;
-                }
-                if !in_molecule {
-                    if name.local_name == "molecule" {
-                        in_molecule = true;
+/// An atom as it is read from the atom array
+struct AtomRecord {
+    element: Element,
+    wildcard: bool,
+    charge: i8,
+    mass: Option<usize>,
+    /// All the hydrogens of the atom, the ones that are not on the atom array are added
+    hydrogens: Option<usize>,
+    point: Point,
+}
+
+/// The atoms and bonds of a `<molecule>`, the ids of the atoms are only valid inside it
+#[derive(Default)]
+struct MoleculeRecord {
+    atoms: Vec<AtomRecord>,
+    atom_index: FnvHashMap<String, usize>,
+    bonds: Vec<(String, String, StructuralBond)>,
+    /// Atoms with an `atomParity`: the atom, its four references and the sign of the parity
+    parities: Vec<(usize, Vec<String>, bool)>,
+    /// Double bonds with a `bondStereo` of `C` or `T`: the bond, its four references and the configuration
+    bond_stereo: Vec<(usize, Vec<String>, BondStereo)>,
+}
+
+/// A CML document with a molecule, its name and its properties (`<property>` with a `<scalar>`)
+#[derive(Debug)]
+pub struct CmlFile {
+    name: String,
+    compound: Compound,
+    properties: BTreeMap<String, String>,
+}
+
+/// The elements of CML have no namespace or the one of CML
+fn is_cml(name: &OwnedName) -> bool {
+    name.namespace.as_ref().is_none_or(|n| n == CML_NAMESPACE)
+}
+
+impl CmlFile {
+    pub fn new(name: String, compound: Compound) -> CmlFile {
+        CmlFile { name, compound, properties: BTreeMap::new() }
+    }
+
+    pub fn parse<R: Read>(reader: R) -> Result<CmlFile, ParserError> {
+        let mut xml_reader = EventReader::new(reader);
+        // The molecules that are open, the innermost one takes the atoms
+        let mut records: Vec<MoleculeRecord> = Vec::new();
+        let mut cml_region = CMLRegion::None;
+        let mut name = String::new();
+        let mut properties = BTreeMap::new();
+        let mut molecules = Vec::new();
+        loop {
+            let ev = xml_reader.next()?;
+            match ev {
+                XmlEvent::StartElement { name: element_name, attributes, namespace:_ } => {
+                    if !is_cml(&element_name) {
+                        // Ignore any unknown element
+                        continue;
                     }
-                    continue;
-                }
-                match cml_region {
-                    CMLRegion::None => {
-                        match &name.local_name[..] {
-                            "atomArray" => {
-                                cml_region = CMLRegion::AtomArray;
-                            },
-                            "bondArray" => {
-                                cml_region = CMLRegion::BondArray;
-                            },
-                            _ => {}
-                        }
-                    },
-                    CMLRegion::AtomArray => {
-                        if name.local_name != "atom" {
-                            continue;
-                        }
-                        let mut id = None;
-                        let mut et = Element::Hydrogen;
-                        let mut pos = Point::new(0., 0., 0.);
-                        for attr in attributes.iter() {
-                            if attr.name.namespace.is_some() {
-                                continue;
-                            }
-                            match &attr.name.local_name[..] {
-                                "id" => {
-                                    id = Some(attr.value.to_owned());
-                                },
-                                "elementType" => {
-                                    et = match Element::from_symbol(&attr.value) {
-                                        Some(e) => e,
-                                        None => {
-                                            return Err(ParserError::Syntax);
-                                        }
-                                    };
+                    if element_name.local_name == "molecule" {
+                        records.push(MoleculeRecord::default());
+                        continue;
+                    }
+                    let record = match records.last_mut() {
+                        Some(record) => record,
+                        None => continue
+                    };
+                    let attribute = |key: &str| attributes.iter()
+                        .find(|a| a.name.namespace.is_none() && a.name.local_name == key).map(|a| a.value.as_str());
+                    match cml_region {
+                        CMLRegion::None => {
+                            match &element_name.local_name[..] {
+                                "atomArray" => {
+                                    cml_region = CMLRegion::AtomArray;
                                 },
-                                "x3" => {
-                                    pos.x = attr.value.parse()?;
+                                "bondArray" => {
+                                    cml_region = CMLRegion::BondArray;
                                 },
-                                "y3" => {
-                                    pos.y = attr.value.parse()?;
+                                "name" if name.is_empty() => {
+                                    cml_region = CMLRegion::Name;
                                 },
-                                "z3" => {
-                                    pos.z = attr.value.parse()?;
+                                "property" => {
+                                    if let Some(key) = attribute("title").or_else(|| attribute("dictRef")) {
+                                        cml_region = CMLRegion::Property(key.to_owned());
+                                    }
                                 },
                                 _ => {}
                             }
-                        }
-                        let id = match id {
-                            Some(id) => id,
-                            None => {
-                                return Err(ParserError::Syntax);
+                        },
+                        CMLRegion::Property(ref key) => {
+                            if element_name.local_name == "scalar" {
+                                cml_region = CMLRegion::Scalar(key.clone());
                             }
-                        };
-                        atoms.insert(id, (et, pos));
-                    },
-                    CMLRegion::BondArray => {
-                        if name.local_name != "bond" {
-                            continue;
-                        }
-                        let mut kind = StructuralBond::Single;
-                        let mut a = String::new();
-                        let mut b = String::new();
-                        for attr in attributes.iter() {
-                            if attr.name.namespace.is_some() {
+                        },
+                        CMLRegion::AtomArray if element_name.local_name == "atomParity" => {
+                            if let (Some(refs), Some(_)) = (attribute("atomRefs4"), record.atoms.last()) {
+                                cml_region = CMLRegion::AtomParity(record.atoms.len() - 1, refs.split_whitespace().map(String::from).collect());
+                            }
+                        },
+                        CMLRegion::BondArray if element_name.local_name == "bondStereo" => {
+                            if let (Some(refs), Some(_)) = (attribute("atomRefs4"), record.bonds.last()) {
+                                cml_region = CMLRegion::BondStereo(record.bonds.len() - 1, refs.split_whitespace().map(String::from).collect());
+                            }
+                        },
+                        CMLRegion::AtomArray => {
+                            if element_name.local_name != "atom" {
                                 continue;
                             }
-                            match &attr.name.local_name[..] {
-                                "atomRefs2" => {
-                                    let mut ids = attr.value.split(' ');
-                                    a = match ids.next() {
-                                        Some(id) => id.to_owned(),
-                                        None => {
-                                            return Err(ParserError::Syntax);
-                                        }
-                                    };
-                                    b = match ids.next() {
-                                        Some(id) => id.to_owned(),
-                                        None => {
-                                            return Err(ParserError::Syntax);
-                                        }
-                                    };
-                                    if let Some(_) = ids.next() {
+                            let id = match attribute("id") {
+                                Some(id) => id.to_owned(),
+                                None => {
+                                    return Err(ParserError::Syntax);
+                                }
+                            };
+                            let (element, wildcard) = match attribute("elementType") {
+                                Some("R") | Some("Du") | Some("*") => (Element::Hydrogen, true),
+                                Some(symbol) => match Element::from_symbol(symbol) {
+                                    Some(e) => (e, false),
+                                    None => {
                                         return Err(ParserError::Syntax);
                                     }
                                 },
-                                "order" => {
-                                    match &attr.value[..] {
-                                        "1" => {
-                                            kind = StructuralBond::Single;
-                                        },
-                                        "2" => {
-                                            kind = StructuralBond::Double;
-                                        },
-                                        "3" => {
-                                            kind = StructuralBond::Triple;
-                                        },
-                                        _ => {
-                                            return Err(ParserError::Syntax);
-                                        }
-                                    }
-                                },
-                                _ => {}
+                                None => (Element::Hydrogen, false)
+                            };
+                            let mut atom = AtomRecord { element, wildcard, charge: 0, mass: None, hydrogens: None,
+                                point: Point::new(0., 0., 0.) };
+                            if let Some(charge) = attribute("formalCharge") {
+                                atom.charge = charge.parse()?;
                             }
+                            if let Some(mass) = attribute("isotopeNumber") {
+                                atom.mass = Some(mass.parse()?);
+                            }
+                            if let Some(hydrogens) = attribute("hydrogenCount") {
+                                atom.hydrogens = Some(hydrogens.parse()?);
+                            }
+                            // The 3D coordinates go before the 2D ones
+                            if let (Some(x), Some(y)) = (attribute("x3"), attribute("y3")) {
+                                atom.point = Point::new(x.parse()?, y.parse()?, attribute("z3").unwrap_or("0").parse()?);
+                            }
+                            else if let (Some(x), Some(y)) = (attribute("x2"), attribute("y2")) {
+                                atom.point = Point::new(x.parse()?, y.parse()?, 0.);
+                            }
+                            if record.atom_index.insert(id, record.atoms.len()).is_some() {
+                                return Err(ParserError::Syntax);
+                            }
+                            record.atoms.push(atom);
+                        },
+                        CMLRegion::BondArray => {
+                            if element_name.local_name != "bond" {
+                                continue;
+                            }
+                            let kind = match attribute("order") {
+                                Some("1") | Some("S") | None => StructuralBond::Single,
+                                Some("2") | Some("D") => StructuralBond::Double,
+                                Some("3") | Some("T") => StructuralBond::Triple,
+                                Some("A") => StructuralBond::Aromatic,
+                                _ => {
+                                    return Err(ParserError::Syntax);
+                                }
+                            };
+                            let ids: Vec<&str> = match attribute("atomRefs2") {
+                                Some(refs) => refs.split_whitespace().collect(),
+                                None => Vec::new()
+                            };
+                            if ids.len() != 2 {
+                                return Err(ParserError::Syntax);
+                            }
+                            record.bonds.push((ids[0].to_owned(), ids[1].to_owned(), kind));
+                        },
+                        CMLRegion::Name | CMLRegion::Scalar(_) | CMLRegion::AtomParity(..) | CMLRegion::BondStereo(..) => {}
+                    }
+                },
+                XmlEvent::Characters(text) => {
+                    match cml_region {
+                        CMLRegion::Name => {
+                            name = text;
+                        },
+                        CMLRegion::Scalar(ref key) => {
+                            properties.insert(key.clone(), text);
+                        },
+                        // A positive parity is clockwise, looking from the first reference
+                        CMLRegion::AtomParity(atom, ref refs) => {
+                            let parity: f64 = text.trim().parse()?;
+                            if parity != 0.0 {
+                                if let Some(record) = records.last_mut() {
+                                    record.parities.push((atom, refs.clone(), parity > 0.0));
+                                }
+                            }
+                        },
+                        CMLRegion::BondStereo(bond, ref refs) => {
+                            let stereo = match text.trim() {
+                                "C" => Some(BondStereo::Cis),
+                                "T" => Some(BondStereo::Trans),
+                                _ => None
+                            };
+                            if let (Some(stereo), Some(record)) = (stereo, records.last_mut()) {
+                                record.bond_stereo.push((bond, refs.clone(), stereo));
+                            }
+                        },
+                        _ => {}
+                    }
+                },
+                XmlEvent::EndElement { name: element_name } => {
+                    if !is_cml(&element_name) {
+                        // Ignore any unknown element
+                        continue;
+                    }
+                    if element_name.local_name == "molecule" {
+                        if let Some(record) = records.pop() {
+                            let molecule = build_molecule(&record)?;
+                            molecules.extend(molecule.components().into_iter().map(|(m, _)| m));
                         }
-                        // If a not is empty then b tampoco
-                        if a.is_empty() {
-                            return Err(ParserError::Syntax);
-                        }
-                        bond_index.entry(a.clone()).or_insert(Vec::new()).push(bonds.len());
-                        bond_index.entry(b.clone()).or_insert(Vec::new()).push(bonds.len());
-                        bonds.push((a, b, kind));
-                    },
-                }
-            },
-            XmlEvent::EndElement { name } => {
-                if name.namespace.is_some() {
-                    // Ignore any unknown element
-                    continue;
-                }
-                if in_molecule {
-                    if name.local_name == "molecule" {
-                        in_molecule = false;
                     }
+                    cml_region = match (cml_region, &element_name.local_name[..]) {
+                        (CMLRegion::AtomArray, "atomArray") | (CMLRegion::BondArray, "bondArray") |
+                            (CMLRegion::Name, "name") | (CMLRegion::Property(_), "property") => CMLRegion::None,
+                        (CMLRegion::Scalar(key), "scalar") => CMLRegion::Property(key),
+                        (CMLRegion::AtomParity(..), "atomParity") => CMLRegion::AtomArray,
+                        (CMLRegion::BondStereo(..), "bondStereo") => CMLRegion::BondArray,
+                        (region, _) => region
+                    };
+                },
+                XmlEvent::EndDocument => {
+                    break;
                 }
-                match cml_region {
-                    CMLRegion::AtomArray if name.local_name == "atomArray" => {
-                        cml_region = CMLRegion::None;
-                    },
-                    CMLRegion::BondArray if name.local_name == "bondArray" => {
-                        cml_region = CMLRegion::None;
-                    },
-                    _ => {}
-                }
-            },
-            XmlEvent::EndDocument => {
-                break;
+                _ => {}
             }
-            _ => {}
         }
+        Ok(CmlFile { name, compound: Compound { molecules }, properties })
     }
-    let mut molecules = Vec::new();
-    let mut data = Data { atoms, bond_index, bonds };
-    while !data.atoms.is_empty() {
-        let (id, atom) = data.atoms.iter().next().unwrap();
-        let id = id.to_owned();
-        let atom = atom.to_owned();
-        let mut molecule = Molecule { atoms: Vec::new(), bonds: Vec::new(), coords: Some(Vec::new()) };
-        extract_group(&mut data, id, atom, &mut molecule);
-        for (i, bond) in molecule.bonds.iter().enumerate() {
-            molecule.atoms[bond.a].bonds.push(i);
-            molecule.atoms[bond.b].bonds.push(i);
-        }
-        molecules.push(molecule);
+
+    /// Write the compound as a single `<molecule>`, with the 2D coordinates if all the atoms are
+    /// on a plane and the 3D ones otherwise
+    pub fn write<W: Write>(&self, writer: W) -> io::Result<()> {
+        write_cml(writer, self).map_err(|e| match e {
+            xml::writer::Error::Io(e) => e,
+            e => io::Error::new(io::ErrorKind::InvalidData, e)
+        })
+    }
+
+    pub fn compound(&self) -> &Compound {
+        &self.compound
+    }
+
+    pub fn compound_mut(&mut self) -> &mut Compound {
+        &mut self.compound
+    }
+
+    pub fn into_compound(self) -> Compound {
+        self.compound
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn set_name(&mut self, name: String) {
+        self.name = name;
+    }
+
+    pub fn properties(&self) -> &BTreeMap<String, String> {
+        &self.properties
+    }
+
+    pub fn properties_mut(&mut self) -> &mut BTreeMap<String, String> {
+        &mut self.properties
     }
-    Ok(Compound { molecules })
 }
 
-fn extract_group(data: &mut Data, id: String, atom: (Element, Point), group: &mut Molecule) {
-    data.atoms.remove(&id);
-    let my_molecule_id = group.atoms.len();
-    group.atoms.push(AtomAndBondI { atom: Isotope::from(atom.0), bonds: Vec::new() });
-    unsafe {
-        match group.coords {
-            Some(ref mut c) => {
-                c.push(atom.1);
-            },
-            None => unreachable_unchecked()
+/// A single molecule with the atoms in the order of the document, plus the hydrogens of the
+/// `hydrogenCount` that are missing, and the stereo of the `atomParity` and `bondStereo` elements
+fn build_molecule(record: &MoleculeRecord) -> Result<Molecule, ParserError> {
+    let MoleculeRecord { atoms, atom_index, bonds, .. } = record;
+    let mut molecule = Molecule { atoms: Vec::with_capacity(atoms.len()), bonds: Vec::with_capacity(bonds.len()),
+        coords: Some(atoms.iter().map(|a| a.point).collect()) };
+    for record in atoms.iter() {
+        let mut isotope = Isotope::from(Ion::new(record.element, record.charge));
+        if let Some(mass) = record.mass {
+            let number = record.element.get_atomic_number();
+            if mass < number || mass - number > u8::MAX as usize {
+                return Err(ParserError::Syntax);
+            }
+            *isotope.get_neutrons_count_mut() = NonZeroU8::new((mass - number) as u8);
         }
+        let mut atom = AtomAndBondI::new(isotope, Vec::new());
+        atom.wildcard = record.wildcard;
+        molecule.atoms.push(atom);
+    }
+    for (a, b, kind) in bonds.iter() {
+        let (a, b) = match (atom_index.get(a), atom_index.get(b)) {
+            (Some(a), Some(b)) => (*a, *b),
+            _ => {
+                return Err(ParserError::Syntax);
+            }
+        };
+        let bond_id = molecule.bonds.len();
+        molecule.atoms[a].bonds.push(bond_id);
+        molecule.atoms[b].bonds.push(bond_id);
+        molecule.bonds.push(Bond::new(a, b, *kind));
     }
-    let bids = match data.bond_index.get(&id) {
-        Some(bids) => bids.clone(),
-        None => {
-            return;
+    for (id, record) in atoms.iter().enumerate() {
+        let hydrogens = match record.hydrogens {
+            Some(hydrogens) => hydrogens,
+            None => continue
+        };
+        let explicit = explicit_hydrogens(&molecule, id);
+        for _ in explicit..hydrogens {
+            let (h, bond_id) = (molecule.atoms.len(), molecule.bonds.len());
+            molecule.atoms.push(AtomAndBondI::new(Isotope::from(Element::Hydrogen), vec![bond_id]));
+            molecule.coords.as_mut().unwrap().push(record.point);
+            molecule.atoms[id].bonds.push(bond_id);
+            molecule.bonds.push(Bond::new(id, h, StructuralBond::Single));
         }
+    }
+    let atom = |id: &String| atom_index.get(id).copied().ok_or(ParserError::Syntax);
+    let pair = |molecule: &Molecule, bond: usize, atom: usize| {
+        let bond = molecule.bonds[bond];
+        if bond.a == atom { bond.b } else { bond.a }
     };
-    for bid in bids {
-        let bond = data.bonds[bid].clone();
-        let pair;
-        if bond.0 == id {
-            pair = &bond.1;
+    for (center, refs, clockwise) in record.parities.iter() {
+        let center = *center;
+        if refs.len() != 4 {
+            return Err(ParserError::Syntax);
         }
-        else {
-            pair = &bond.0;
+        // The atom itself stands for one of its hydrogens that is not on the references
+        let mut order = Vec::with_capacity(4);
+        for id in refs.iter() {
+            let neighbour = atom(id)?;
+            let bond = molecule.atoms[center].bonds.iter().copied().find(|b| !order.contains(b) && if neighbour == center {
+                let other = pair(&molecule, *b, center);
+                *molecule.atoms[other].get_element() == Element::Hydrogen && refs.iter().all(|r| atom_index.get(r) != Some(&other))
+            } else {
+                pair(&molecule, *b, center) == neighbour
+            });
+            match bond {
+                Some(bond) => order.push(bond),
+                None => {
+                    return Err(ParserError::Syntax);
+                }
+            }
         }
-        if let Some(atom) = data.atoms.get(pair) {
-            let atom = atom.clone();
-            let next_atom_molecular_id = group.atoms.len();
-            group.bonds.push(Bond { a: my_molecule_id, b: next_atom_molecular_id, k: bond.2 });
-            extract_group(data, pair.to_owned(), atom, group);
+        if order.len() != molecule.atoms[center].bonds.len() {
+            return Err(ParserError::Syntax);
         }
+        let chirality = if *clockwise { Chirality::Clockwise } else { Chirality::Anticlockwise };
+        let odd = is_odd_permutation(&order, &molecule.atoms[center].bonds);
+        molecule.atoms[center].chirality = Some(if odd { chirality.inverted() } else { chirality });
     }
-}
\ No newline at end of file
+    for (bond, refs, stereo) in record.bond_stereo.iter() {
+        if refs.len() != 4 {
+            return Err(ParserError::Syntax);
+        }
+        let refs = [atom(&refs[0])?, atom(&refs[1])?, atom(&refs[2])?, atom(&refs[3])?];
+        let double = molecule.bonds[*bond];
+        if double.k != StructuralBond::Double || !((refs[1], refs[2]) == (double.a, double.b) || (refs[2], refs[1]) == (double.a, double.b)) {
+            return Err(ParserError::Syntax);
+        }
+        // The stereo is given for the first neighbour of each end, the other one flips it
+        let mut cis = *stereo == BondStereo::Cis;
+        for (end, reference) in [(refs[1], refs[0]), (refs[2], refs[3])].iter() {
+            let others: Vec<usize> = molecule.atoms[*end].bonds.iter().filter(|b| **b != *bond).map(|b| pair(&molecule, *b, *end)).collect();
+            match others.iter().position(|o| o == reference) {
+                Some(0) => {},
+                Some(_) => cis = !cis,
+                None => {
+                    return Err(ParserError::Syntax);
+                }
+            }
+        }
+        molecule.bonds[*bond].stereo = Some(if cis { BondStereo::Cis } else { BondStereo::Trans });
+    }
+    Ok(molecule)
+}
+
+/// Hydrogens bonded to the atom
+fn explicit_hydrogens(molecule: &Molecule, atom: usize) -> usize {
+    molecule.atoms[atom].bonds.iter().filter(|b| {
+        let bond = molecule.bonds[**b];
+        let pair = if bond.a == atom { bond.b } else { bond.a };
+        !molecule.atoms[pair].wildcard && *molecule.atoms[pair].get_element() == Element::Hydrogen
+    }).count()
+}
+
+fn write_cml<W: Write>(writer: W, file: &CmlFile) -> xml::writer::Result<()> {
+    let mut xml_writer = EmitterConfig::new().perform_indent(true).create_writer(writer);
+    xml_writer.write(WriterEvent::start_element("cml").default_ns(CML_NAMESPACE))?;
+    xml_writer.write(WriterEvent::start_element("molecule").attr("id", "m1"))?;
+    if !file.name.is_empty() {
+        xml_writer.write(WriterEvent::start_element("name"))?;
+        xml_writer.write(WriterEvent::characters(&file.name))?;
+        xml_writer.write(WriterEvent::end_element())?;
+    }
+    let molecules = &file.compound.molecules;
+    let three_dimensional = molecules.iter().any(|m| m.coords.as_ref().is_some_and(|c| c.iter().any(|p| p.z != 0.0)));
+    xml_writer.write(WriterEvent::start_element("atomArray"))?;
+    let mut offset = 0;
+    for molecule in molecules.iter() {
+        for (id, atom) in molecule.atoms.iter().enumerate() {
+            let atom_id = format!("a{}", offset + id + 1);
+            let symbol = if atom.wildcard { "R" } else { atom.get_element().get_symbol() };
+            let mut event = WriterEvent::start_element("atom").attr("id", &atom_id).attr("elementType", symbol);
+            let charge = atom.get_ion().get_charge().to_string();
+            if *atom.get_ion().get_charge() != 0 {
+                event = event.attr("formalCharge", &charge);
+            }
+            let mass = atom.get_neutrons_count().map(|n| (n.get() as usize + atom.get_element().get_atomic_number()).to_string());
+            if let Some(ref mass) = mass {
+                event = event.attr("isotopeNumber", mass);
+            }
+            let hydrogens = explicit_hydrogens(molecule, id).to_string();
+            if atom.wildcard || *atom.get_element() != Element::Hydrogen {
+                event = event.attr("hydrogenCount", &hydrogens);
+            }
+            let coordinates = match molecule.coords.as_ref().and_then(|c| c.get(id)) {
+                Some(point) => vec![point.x.to_string(), point.y.to_string(), point.z.to_string()],
+                None => Vec::new()
+            };
+            if !coordinates.is_empty() {
+                if three_dimensional {
+                    event = event.attr("x3", &coordinates[0]).attr("y3", &coordinates[1]).attr("z3", &coordinates[2]);
+                }
+                else {
+                    event = event.attr("x2", &coordinates[0]).attr("y2", &coordinates[1]);
+                }
+            }
+            xml_writer.write(event)?;
+            // The parity is given for the neighbours in the order of the bonds of the atom
+            if let (Some(chirality), 4) = (atom.chirality, atom.bonds.len()) {
+                let refs: Vec<String> = atom.bonds.iter().map(|b| {
+                    let bond = molecule.bonds[*b];
+                    format!("a{}", offset + if bond.a == id { bond.b } else { bond.a } + 1)
+                }).collect();
+                xml_writer.write(WriterEvent::start_element("atomParity").attr("atomRefs4", &refs.join(" ")))?;
+                xml_writer.write(WriterEvent::characters(if chirality == Chirality::Clockwise { "1" } else { "-1" }))?;
+                xml_writer.write(WriterEvent::end_element())?;
+            }
+            xml_writer.write(WriterEvent::end_element())?;
+        }
+        offset += molecule.atoms.len();
+    }
+    xml_writer.write(WriterEvent::end_element())?;
+    if molecules.iter().any(|m| !m.bonds.is_empty()) {
+        xml_writer.write(WriterEvent::start_element("bondArray"))?;
+        let (mut offset, mut bond_id) = (0, 0);
+        for molecule in molecules.iter() {
+            for (index, bond) in molecule.bonds.iter().enumerate() {
+                bond_id += 1;
+                let id = format!("b{}", bond_id);
+                let refs = format!("a{} a{}", offset + bond.a + 1, offset + bond.b + 1);
+                let order = match bond.k {
+                    StructuralBond::Aromatic => "A",
+                    StructuralBond::Single => "1",
+                    StructuralBond::Double => "2",
+                    StructuralBond::Triple => "3",
+                };
+                xml_writer.write(WriterEvent::start_element("bond").attr("id", &id).attr("atomRefs2", &refs).attr("order", order))?;
+                // The configuration is given for the first neighbour of each end
+                let first = |end: usize| molecule.atoms[end].bonds.iter().find(|b| **b != index).map(|b| {
+                    let other = molecule.bonds[*b];
+                    if other.a == end { other.b } else { other.a }
+                });
+                if let (Some(stereo), Some(x), Some(y)) = (bond.stereo, first(bond.a), first(bond.b)) {
+                    let refs = format!("a{} a{} a{} a{}", offset + x + 1, offset + bond.a + 1, offset + bond.b + 1, offset + y + 1);
+                    xml_writer.write(WriterEvent::start_element("bondStereo").attr("atomRefs4", &refs))?;
+                    xml_writer.write(WriterEvent::characters(if stereo == BondStereo::Cis { "C" } else { "T" }))?;
+                    xml_writer.write(WriterEvent::end_element())?;
+                }
+                xml_writer.write(WriterEvent::end_element())?;
+            }
+            offset += molecule.atoms.len();
+        }
+        xml_writer.write(WriterEvent::end_element())?;
+    }
+    if !file.properties.is_empty() {
+        xml_writer.write(WriterEvent::start_element("propertyList"))?;
+        for (key, value) in file.properties.iter() {
+            xml_writer.write(WriterEvent::start_element("property").attr("title", key))?;
+            xml_writer.write(WriterEvent::start_element("scalar"))?;
+            xml_writer.write(WriterEvent::characters(value))?;
+            xml_writer.write(WriterEvent::end_element())?;
+            xml_writer.write(WriterEvent::end_element())?;
+        }
+        xml_writer.write(WriterEvent::end_element())?;
+    }
+    xml_writer.write(WriterEvent::end_element())?;
+    xml_writer.write(WriterEvent::end_element())
+}
@@ -0,0 +1,94 @@
+use ptable::Element;
+
+use super::{Bond, Molecule, StructuralBond};
+
+/// Covalent radii in Å from Cordero et al. (2008), by atomic number from hydrogen to curium
+const COVALENT_RADII: [f64; 96] = [
+    0.31, 0.28,
+    1.28, 0.96, 0.84, 0.76, 0.71, 0.66, 0.57, 0.58,
+    1.66, 1.41, 1.21, 1.11, 1.07, 1.05, 1.02, 1.06,
+    2.03, 1.76, 1.70, 1.60, 1.53, 1.39, 1.39, 1.32, 1.26, 1.24, 1.32, 1.22, 1.22, 1.20, 1.19, 1.20, 1.20, 1.16,
+    2.20, 1.95, 1.90, 1.75, 1.64, 1.54, 1.47, 1.46, 1.42, 1.39, 1.45, 1.44, 1.42, 1.39, 1.39, 1.38, 1.39, 1.40,
+    2.44, 2.15, 2.07, 2.04, 2.03, 2.01, 1.99, 1.98, 1.98, 1.96, 1.94, 1.92, 1.92, 1.89, 1.90, 1.87, 1.87, 1.75,
+    1.70, 1.62, 1.51, 1.44, 1.41, 1.36, 1.36, 1.32, 1.45, 1.46, 1.48, 1.40, 1.50, 1.50,
+    2.60, 2.21, 2.15, 2.06, 2.00, 1.96, 1.90, 1.87, 1.80, 1.69,
+];
+
+/// Radius for the elements out of the table
+const DEFAULT_RADIUS: f64 = 1.50;
+
+/// Distances longer than the sum of the radii that are still bonds
+const TOLERANCE: f64 = 0.45;
+
+/// Atoms closer than this are on top of each other and not bonded
+const MIN_DISTANCE: f64 = 0.40;
+
+pub fn covalent_radius(element: Element) -> f64 {
+    COVALENT_RADII.get(element.get_atomic_number() - 1).copied().unwrap_or(DEFAULT_RADIUS)
+}
+
+/// Add single bonds between the atoms whose distance is at most the sum of their covalent radii
+/// plus a tolerance. The hydrogens only take the nearest atom, the bonds that the molecule already
+/// has are kept
+pub fn perceive_bonds(molecule: &mut Molecule) {
+    let atoms = vec![true; molecule.atoms.len()];
+    perceive_bonds_among(molecule, &atoms)
+}
+
+/// Same as `perceive_bonds` but only between the atoms that are marked
+pub fn perceive_bonds_among(molecule: &mut Molecule, atoms: &[bool]) {
+    perceive_bonds_filtered(molecule, atoms, |_, _| true)
+}
+
+/// Same as `perceive_bonds_among` but only the pairs of atoms that the filter accepts are bonded
+pub fn perceive_bonds_filtered<F: Fn(usize, usize) -> bool>(molecule: &mut Molecule, atoms: &[bool], filter: F) {
+    let coords = match molecule.coords {
+        Some(ref coords) if coords.len() == molecule.atoms.len() => coords.clone(),
+        _ => return
+    };
+    let radii: Vec<f64> = molecule.atoms.iter().map(|a| covalent_radius(*a.get_element())).collect();
+    let max_radius = radii.iter().cloned().fold(0.0, f64::max);
+    // Sweep along x, so only the atoms that can be near are compared
+    let mut order: Vec<usize> = (0..coords.len()).filter(|a| atoms[*a]).collect();
+    order.sort_by(|a, b| coords[*a].x.partial_cmp(&coords[*b].x).unwrap_or(std::cmp::Ordering::Equal));
+    let mut candidates = Vec::new();
+    for (i, a) in order.iter().enumerate() {
+        let reach = radii[*a] + max_radius + TOLERANCE;
+        for b in order[i + 1..].iter() {
+            let (pa, pb) = (coords[*a], coords[*b]);
+            if pb.x - pa.x > reach {
+                break;
+            }
+            let distance = ((pa.x - pb.x).powi(2) + (pa.y - pb.y).powi(2) + (pa.z - pb.z).powi(2)).sqrt();
+            if distance > MIN_DISTANCE && distance <= radii[*a] + radii[*b] + TOLERANCE && filter(*a, *b) {
+                candidates.push((distance / (radii[*a] + radii[*b]), (*a).min(*b), (*a).max(*b)));
+            }
+        }
+    }
+    candidates.sort_by(|x, y| x.0.partial_cmp(&y.0).unwrap_or(std::cmp::Ordering::Equal));
+    let is_hydrogen = |atom: usize| !molecule.atoms[atom].wildcard && *molecule.atoms[atom].get_element() == Element::Hydrogen;
+    let mut degree: Vec<usize> = molecule.atoms.iter().map(|a| a.bonds.len()).collect();
+    let mut new_bonds = Vec::new();
+    for (_, a, b) in candidates {
+        if (is_hydrogen(a) && degree[a] > 0) || (is_hydrogen(b) && degree[b] > 0) {
+            continue;
+        }
+        let bonded = molecule.atoms[a].bonds.iter().any(|id| {
+            let bond = molecule.bonds[*id];
+            bond.a == b || bond.b == b
+        });
+        if bonded {
+            continue;
+        }
+        degree[a] += 1;
+        degree[b] += 1;
+        new_bonds.push((a, b));
+    }
+    new_bonds.sort_unstable();
+    for (a, b) in new_bonds {
+        let id = molecule.bonds.len();
+        molecule.atoms[a].bonds.push(id);
+        molecule.atoms[b].bonds.push(id);
+        molecule.bonds.push(Bond::new(a, b, StructuralBond::Single));
+    }
+}
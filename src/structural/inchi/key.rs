@@ -0,0 +1,120 @@
+/// Round constants of SHA-256
+const K: [u32; 64] = [
+    0x428a2f98, 0x71374491, 0xb5c0fbcf, 0xe9b5dba5, 0x3956c25b, 0x59f111f1, 0x923f82a4, 0xab1c5ed5,
+    0xd807aa98, 0x12835b01, 0x243185be, 0x550c7dc3, 0x72be5d74, 0x80deb1fe, 0x9bdc06a7, 0xc19bf174,
+    0xe49b69c1, 0xefbe4786, 0x0fc19dc6, 0x240ca1cc, 0x2de92c6f, 0x4a7484aa, 0x5cb0a9dc, 0x76f988da,
+    0x983e5152, 0xa831c66d, 0xb00327c8, 0xbf597fc7, 0xc6e00bf3, 0xd5a79147, 0x06ca6351, 0x14292967,
+    0x27b70a85, 0x2e1b2138, 0x4d2c6dfc, 0x53380d13, 0x650a7354, 0x766a0abb, 0x81c2c92e, 0x92722c85,
+    0xa2bfe8a1, 0xa81a664b, 0xc24b8b70, 0xc76c51a3, 0xd192e819, 0xd6990624, 0xf40e3585, 0x106aa070,
+    0x19a4c116, 0x1e376c08, 0x2748774c, 0x34b0bcb5, 0x391c0cb3, 0x4ed8aa4a, 0x5b9cca4f, 0x682e6ff3,
+    0x748f82ee, 0x78a5636f, 0x84c87814, 0x8cc70208, 0x90befffa, 0xa4506ceb, 0xbef9a3f7, 0xc67178f2,
+];
+
+pub(crate) fn sha256(data: &[u8]) -> [u8; 32] {
+    let mut h: [u32; 8] = [0x6a09e667, 0xbb67ae85, 0x3c6ef372, 0xa54ff53a, 0x510e527f, 0x9b05688c, 0x1f83d9ab, 0x5be0cd19];
+    let mut message = data.to_vec();
+    message.push(0x80);
+    while message.len() % 64 != 56 {
+        message.push(0);
+    }
+    message.extend_from_slice(&(data.len() as u64 * 8).to_be_bytes());
+    for chunk in message.chunks(64) {
+        let mut w = [0u32; 64];
+        for (i, word) in chunk.chunks(4).enumerate() {
+            w[i] = u32::from_be_bytes([word[0], word[1], word[2], word[3]]);
+        }
+        for i in 16..64 {
+            let s0 = w[i - 15].rotate_right(7) ^ w[i - 15].rotate_right(18) ^ (w[i - 15] >> 3);
+            let s1 = w[i - 2].rotate_right(17) ^ w[i - 2].rotate_right(19) ^ (w[i - 2] >> 10);
+            w[i] = w[i - 16].wrapping_add(s0).wrapping_add(w[i - 7]).wrapping_add(s1);
+        }
+        let mut v = h;
+        for i in 0..64 {
+            let s1 = v[4].rotate_right(6) ^ v[4].rotate_right(11) ^ v[4].rotate_right(25);
+            let choice = (v[4] & v[5]) ^ (!v[4] & v[6]);
+            let t1 = v[7].wrapping_add(s1).wrapping_add(choice).wrapping_add(K[i]).wrapping_add(w[i]);
+            let s0 = v[0].rotate_right(2) ^ v[0].rotate_right(13) ^ v[0].rotate_right(22);
+            let majority = (v[0] & v[1]) ^ (v[0] & v[2]) ^ (v[1] & v[2]);
+            let t2 = s0.wrapping_add(majority);
+            v = [t1.wrapping_add(t2), v[0], v[1], v[2], v[3].wrapping_add(t1), v[4], v[5], v[6]];
+        }
+        for (a, b) in h.iter_mut().zip(v.iter()) {
+            *a = a.wrapping_add(*b);
+        }
+    }
+    let mut digest = [0; 32];
+    for (i, word) in h.iter().enumerate() {
+        digest[i * 4..i * 4 + 4].copy_from_slice(&word.to_be_bytes());
+    }
+    digest
+}
+
+/// Letters for 14 bits: the triplets in alphabetical order, without the ones that start with `E` and
+/// only the first 160 that start with `T`
+fn triplet(mut index: usize) -> [u8; 3] {
+    let mut first = b'A';
+    loop {
+        let size = match first {
+            b'E' => 0,
+            b'T' => 160,
+            _ => 676
+        };
+        if index < size {
+            return [first, b'A' + (index / 26) as u8, b'A' + (index % 26) as u8];
+        }
+        index -= size;
+        first += 1;
+    }
+}
+
+/// The hash as `count` triplets and a doublet, from the bits of the digest in little endian order
+fn encode(data: &str, count: usize) -> String {
+    let digest = sha256(data.as_bytes());
+    let bits = |start: usize, length: usize| {
+        (start..start + length).fold(0, |value, bit| value | (((digest[bit / 8] >> (bit % 8)) & 1) as usize) << (bit - start))
+    };
+    let mut letters = Vec::with_capacity(count * 3 + 2);
+    for i in 0..count {
+        letters.extend_from_slice(&triplet(bits(i * 14, 14)));
+    }
+    let doublet = bits(count * 14, 9);
+    letters.push(b'A' + (doublet / 26) as u8);
+    letters.push(b'A' + (doublet % 26) as u8);
+    String::from_utf8(letters).unwrap()
+}
+
+/// Hashed key of an InChI: 14 letters for the skeleton with its hydrogens and charges, 8 letters
+/// for the stereo and isotopic layers, the standard and version flags and the protons of `/p`
+pub(crate) fn inchi_key(inchi: &str) -> Option<String> {
+    let inchi = inchi.trim().strip_prefix("InChI=")?;
+    let (version, layers) = inchi.split_at(inchi.find('/').unwrap_or(inchi.len()));
+    let standard = match version {
+        "1S" => 'S',
+        "1" => 'N',
+        _ => return None
+    };
+    let mut major = Vec::new();
+    let mut minor = String::new();
+    let mut protons = 0i32;
+    for layer in layers.split('/').skip(1) {
+        match layer.as_bytes().first() {
+            Some(b'p') => protons = layer[1..].parse().ok()?,
+            Some(b'b') | Some(b't') | Some(b'm') | Some(b's') | Some(b'i') | Some(b'f') | Some(b'r') => {
+                minor.push('/');
+                minor.push_str(layer);
+            },
+            _ if minor.is_empty() => major.push(layer),
+            _ => {}
+        }
+    }
+    // The short minor layers are hashed twice
+    if minor.len() < 256 {
+        minor = minor.repeat(2);
+    }
+    let protonation = match protons {
+        0 => 'N',
+        p if p.abs() <= 12 => (b'N' as i32 + p) as u8 as char,
+        _ => 'A'
+    };
+    Some(format!("{}-{}{}A-{}", encode(&major.join("/"), 4), encode(&minor, 2), standard, protonation))
+}
@@ -0,0 +1,659 @@
+use std::ops::Range;
+
+use ptable::Element;
+
+use super::kekulization::{valences, Matching};
+use super::{AtomAndBondI, Bond, Compound, Molecule, ParseError, ParseErrorReason, ParserError, StructuralBond};
+use crate::*;
+
+mod key;
+mod write;
+pub(crate) use self::write::write_inchi;
+
+/// Hydrogens that can be on any atom of a group, as `(H2,3,4)` in the hydrogen layer. The negative
+/// charges that moved with the protons (`(H-,3,4)`) take the place of a hydrogen too
+#[derive(Debug, Clone)]
+struct MobileGroup {
+    atoms: Vec<usize>,
+    hydrogens: usize,
+    negative: usize,
+}
+
+/// A component of the main layer, its atoms are the non hydrogen atoms of the formula in order
+#[derive(Debug, Clone)]
+struct Component {
+    elements: Vec<Element>,
+    bonds: Vec<(usize, usize)>,
+    hydrogens: Vec<usize>,
+    groups: Vec<MobileGroup>,
+    charges: Vec<i8>,
+    charge: i8,
+    /// Hydrogens of the formula that are not atoms of the main layer
+    formula_hydrogens: usize,
+}
+
+/// A standard InChI read from its formula, connections (`/c`), hydrogens (`/h`), charge (`/q`) and
+/// protons (`/p`) layers. InChI has no bond orders, they are found from the valences of the atoms and
+/// the mobile hydrogens are placed where they fit. The stereo and isotopic layers are skipped
+#[derive(Debug, Clone)]
+pub struct InChI {
+    formulas: Vec<EmpiricalFormula>,
+    compound: Compound,
+}
+
+impl InChI {
+    pub fn parse(string: &str) -> Result<InChI, ParserError> {
+        let string = string.trim_end().as_bytes();
+        let prefix = b"InChI=1";
+        if !string.starts_with(prefix) {
+            let wrong = string.iter().zip(prefix.iter()).take_while(|(a, b)| a == b).count();
+            return Err(error(string, wrong, if wrong == string.len() { ParseErrorReason::UnexpectedEnd }
+                else { ParseErrorReason::UnexpectedCharacter }));
+        }
+        // Layers as ranges of the string, the first one is the version
+        let mut layers = Vec::new();
+        let mut start = prefix.len();
+        for (i, c) in string.iter().enumerate().skip(start) {
+            if *c == b'/' {
+                layers.push(start..i);
+                start = i + 1;
+            }
+        }
+        layers.push(start..string.len());
+        let version = &string[layers[0].clone()];
+        if !version.is_empty() && version != b"S" {
+            return Err(error(string, layers[0].start, ParseErrorReason::UnexpectedCharacter));
+        }
+        let mut formulas = Vec::new();
+        let mut components = Vec::new();
+        // The formula is missing for a proton alone
+        let formula = layers.get(1).filter(|r| !string.get(r.start).is_some_and(|c| c.is_ascii_lowercase()));
+        if let Some(range) = formula {
+            for (range, count) in split_components(string, range.clone(), true)? {
+                let formula = parse_formula(string, range)?;
+                for _ in 0..count {
+                    components.push(Component::new(&formula));
+                    formulas.push(formula.clone());
+                }
+            }
+        }
+        let mut protons = 0;
+        let mut hydrogens_layer = string.len();
+        let mut charge_layer = string.len();
+        for range in layers.iter().skip(if formula.is_some() { 2 } else { 1 }) {
+            let layer = match string.get(range.start) {
+                Some(c) => *c,
+                None => {
+                    return Err(error(string, range.start, ParseErrorReason::UnexpectedEnd));
+                }
+            };
+            let range = range.start + 1..range.end;
+            if layer == b'h' {
+                hydrogens_layer = range.start;
+            }
+            if layer == b'q' {
+                charge_layer = range.start;
+            }
+            match layer {
+                b'c' | b'h' | b'q' => {
+                    let mut id = 0;
+                    for (range, count) in split_components(string, range, false)? {
+                        for _ in 0..count {
+                            let component = match components.get_mut(id) {
+                                Some(c) => c,
+                                None => {
+                                    return Err(error(string, range.start, ParseErrorReason::UnexpectedCharacter));
+                                }
+                            };
+                            match layer {
+                                b'c' => component.parse_connections(string, range.clone())?,
+                                b'h' => component.parse_hydrogens(string, range.clone())?,
+                                _ => component.charge = parse_charge(string, range.clone())?
+                            }
+                            id += 1;
+                        }
+                    }
+                },
+                b'p' => protons = parse_charge(string, range)?,
+                // The fixed hydrogens layer describes another structure, the rest is stereo and isotopes
+                b'f' | b'r' => break,
+                _ => {}
+            }
+        }
+        // The hydrogens of the formula are the ones of the atoms and the mobile ones
+        if components.iter().any(|c| c.placed_hydrogens() != c.formula_hydrogens) {
+            return Err(error(string, hydrogens_layer, ParseErrorReason::BadHydrogenCount));
+        }
+        move_protons(&mut components, protons);
+        let mut molecules = Vec::with_capacity(components.len());
+        for component in components.iter_mut() {
+            match component.build_molecule() {
+                Some(molecule) => molecules.push(molecule),
+                None => {
+                    return Err(error(string, charge_layer, ParseErrorReason::BadCharge));
+                }
+            }
+        }
+        Ok(InChI { formulas, compound: Compound::from(molecules) })
+    }
+
+    /// Formulas of the components, without the protons of the `/p` layer
+    pub fn formulas(&self) -> &[EmpiricalFormula] {
+        &self.formulas
+    }
+
+    pub fn compound(&self) -> &Compound {
+        &self.compound
+    }
+
+    pub fn into_compound(self) -> Compound {
+        self.compound
+    }
+
+    /// InChIKey of an InChI string, `None` if it does not start with `InChI=1`
+    pub fn key(inchi: &str) -> Option<String> {
+        key::inchi_key(inchi)
+    }
+}
+
+fn error(string: &[u8], position: usize, reason: ParseErrorReason) -> ParserError {
+    ParseError::new(string, &string[position.min(string.len())..], reason).into()
+}
+
+fn parse_number(string: &[u8], position: &mut usize, end: usize) -> Option<usize> {
+    let start = *position;
+    while *position < end && string[*position].is_ascii_digit() {
+        *position += 1;
+    }
+    if start == *position {
+        return None;
+    }
+    std::str::from_utf8(&string[start..*position]).ok()?.parse().ok()
+}
+
+/// Count after an `H` or a `-` of the hydrogen layer, one if there are no digits
+fn parse_count(string: &[u8], position: &mut usize, end: usize) -> Result<usize, ParserError> {
+    let start = *position;
+    match parse_number(string, position, end) {
+        Some(count) => Ok(count),
+        None if start == *position => Ok(1),
+        None => Err(error(string, start, ParseErrorReason::BadHydrogenCount))
+    }
+}
+
+/// Ranges of the components of a layer, separated by `;` or `.` and with an optional multiplier
+fn split_components(string: &[u8], range: Range<usize>, formula: bool) -> Result<Vec<(Range<usize>, usize)>, ParserError> {
+    let mut components = Vec::new();
+    let mut start = range.start;
+    for i in range.clone().chain(std::iter::once(range.end)) {
+        if i < range.end && string[i] != b';' && string[i] != b'.' {
+            continue;
+        }
+        let mut position = start;
+        let mut count = 1;
+        // `2*` in the layers, `2` before the formula
+        if let Some(n) = parse_number(string, &mut position, i) {
+            if string[..i].get(position) == Some(&b'*') {
+                count = n;
+                start = position + 1;
+            }
+            else if formula && position < i && string[position].is_ascii_uppercase() {
+                count = n;
+                start = position;
+            }
+        }
+        if count == 0 {
+            return Err(error(string, start, ParseErrorReason::UnexpectedCharacter));
+        }
+        components.push((start..i, count));
+        start = i + 1;
+    }
+    Ok(components)
+}
+
+fn parse_formula(string: &[u8], range: Range<usize>) -> Result<EmpiricalFormula, ParserError> {
+    let text = std::str::from_utf8(&string[range.clone()]).map_err(ParserError::Utf8)?;
+    match EmpiricalFormula::from_string(text) {
+        Ok(formula) => Ok(formula),
+        Err(ParserError::Parse(e)) => Err(ParserError::Parse(e.shifted(range.start))),
+        Err(e) => Err(e)
+    }
+}
+
+/// Charge as `+1`, `-2` or nothing
+fn parse_charge(string: &[u8], range: Range<usize>) -> Result<i8, ParserError> {
+    if range.is_empty() {
+        return Ok(0);
+    }
+    let sign = match string[range.start] {
+        b'+' => 1,
+        b'-' => -1,
+        _ => {
+            return Err(error(string, range.start, ParseErrorReason::BadCharge));
+        }
+    };
+    let mut position = range.start + 1;
+    match parse_number(string, &mut position, range.end) {
+        Some(n) if position == range.end && n <= i8::MAX as usize => Ok(sign * n as i8),
+        _ => Err(error(string, position, ParseErrorReason::BadCharge))
+    }
+}
+
+/// Valences for the bond orders, a bit wider than the ones of the kekulization
+fn allowed_valences(element: Element, charge: i8) -> &'static [usize] {
+    match (element, charge) {
+        (Element::Nitrogen, 0) => &[3, 5],
+        (Element::Chlorine, 0) | (Element::Bromine, 0) | (Element::Iodine, 0) => &[1, 3, 5, 7],
+        (element, charge) => valences(element, charge)
+    }
+}
+
+impl Component {
+    fn new(formula: &EmpiricalFormula) -> Component {
+        let mut elements = Vec::new();
+        for (element, count) in formula.iter() {
+            if *element != Element::Hydrogen {
+                elements.extend(std::iter::repeat_n(*element, *count));
+            }
+        }
+        // A molecule of hydrogen is numbered from one of its atoms
+        if elements.is_empty() && formula.iter().any(|(e, n)| *e == Element::Hydrogen && *n > 0) {
+            elements.push(Element::Hydrogen);
+        }
+        let n = elements.len();
+        let formula_hydrogens = formula.iter().filter(|(e, _)| *e == Element::Hydrogen).map(|(_, n)| *n).sum::<usize>() -
+            elements.iter().filter(|e| **e == Element::Hydrogen).count();
+        Component { elements, bonds: Vec::new(), hydrogens: vec![0; n], groups: Vec::new(), charges: vec![0; n], charge: 0,
+            formula_hydrogens }
+    }
+
+    fn atom(&self, string: &[u8], position: usize, number: usize) -> Result<usize, ParserError> {
+        if number == 0 || number > self.elements.len() {
+            return Err(error(string, position, ParseErrorReason::BadAtomNumber));
+        }
+        Ok(number - 1)
+    }
+
+    /// Connections as `1-2(3,4)5-1`: a number repeated closes a ring, the branches go inside the
+    /// parentheses separated by commas
+    fn parse_connections(&mut self, string: &[u8], range: Range<usize>) -> Result<(), ParserError> {
+        let mut position = range.start;
+        let mut current: Option<usize> = None;
+        let mut branches = Vec::new();
+        while position < range.end {
+            match string[position] {
+                b'-' => position += 1,
+                b'(' => {
+                    branches.push(current);
+                    position += 1;
+                },
+                b',' if !branches.is_empty() => {
+                    current = *branches.last().unwrap();
+                    position += 1;
+                },
+                b')' => {
+                    current = match branches.pop() {
+                        Some(atom) => atom,
+                        None => {
+                            return Err(error(string, position, ParseErrorReason::UnmatchedParenthesis));
+                        }
+                    };
+                    position += 1;
+                },
+                c if c.is_ascii_digit() => {
+                    let start = position;
+                    let number = parse_number(string, &mut position, range.end).unwrap_or(0);
+                    let atom = self.atom(string, start, number)?;
+                    if let Some(previous) = current {
+                        let bond = (previous.min(atom), previous.max(atom));
+                        if previous == atom {
+                            return Err(error(string, start, ParseErrorReason::BadAtomNumber));
+                        }
+                        if !self.bonds.contains(&bond) {
+                            self.bonds.push(bond);
+                        }
+                    }
+                    current = Some(atom);
+                },
+                _ => {
+                    return Err(error(string, position, ParseErrorReason::UnexpectedCharacter));
+                }
+            }
+        }
+        if !branches.is_empty() {
+            return Err(error(string, range.end, ParseErrorReason::UnmatchedParenthesis));
+        }
+        Ok(())
+    }
+
+    /// Hydrogens as `1-3H2,5H`, a list of atoms and ranges with their count, and the mobile groups
+    fn parse_hydrogens(&mut self, string: &[u8], range: Range<usize>) -> Result<(), ParserError> {
+        let mut position = range.start;
+        let mut atoms = Vec::new();
+        while position < range.end {
+            match string[position] {
+                b'(' => {
+                    position += 1;
+                    if string.get(position) != Some(&b'H') {
+                        return Err(error(string, position, ParseErrorReason::BadHydrogenCount));
+                    }
+                    position += 1;
+                    let hydrogens = parse_count(string, &mut position, range.end)?;
+                    let mut negative = 0;
+                    if position < range.end && string[position] == b'-' {
+                        position += 1;
+                        negative = parse_count(string, &mut position, range.end)?;
+                    }
+                    let mut group = MobileGroup { atoms: Vec::new(), hydrogens, negative };
+                    while position < range.end && string[position] == b',' {
+                        position += 1;
+                        let start = position;
+                        match parse_number(string, &mut position, range.end) {
+                            Some(number) => group.atoms.push(self.atom(string, start, number)?),
+                            None => {
+                                return Err(error(string, position, ParseErrorReason::UnexpectedCharacter));
+                            }
+                        }
+                    }
+                    if position >= range.end || string[position] != b')' {
+                        return Err(error(string, position, ParseErrorReason::UnmatchedParenthesis));
+                    }
+                    position += 1;
+                    self.groups.push(group);
+                },
+                b'H' => {
+                    if atoms.is_empty() {
+                        return Err(error(string, position, ParseErrorReason::UnexpectedCharacter));
+                    }
+                    position += 1;
+                    let count = parse_count(string, &mut position, range.end)?;
+                    for atom in atoms.drain(..) {
+                        self.hydrogens[atom] = usize::saturating_add(self.hydrogens[atom], count);
+                    }
+                },
+                b',' => position += 1,
+                c if c.is_ascii_digit() => {
+                    let start = position;
+                    let first = parse_number(string, &mut position, range.end).unwrap_or(0);
+                    let first = self.atom(string, start, first)?;
+                    let mut last = first;
+                    if position < range.end && string[position] == b'-' {
+                        position += 1;
+                        let start = position;
+                        last = match parse_number(string, &mut position, range.end) {
+                            Some(number) => self.atom(string, start, number)?,
+                            None => {
+                                return Err(error(string, position, ParseErrorReason::UnexpectedCharacter));
+                            }
+                        };
+                    }
+                    atoms.extend(first..=last);
+                },
+                _ => {
+                    return Err(error(string, position, ParseErrorReason::UnexpectedCharacter));
+                }
+            }
+        }
+        if !atoms.is_empty() {
+            return Err(error(string, range.end, ParseErrorReason::BadHydrogenCount));
+        }
+        Ok(())
+    }
+
+    /// Hydrogens of the atoms and of the mobile groups
+    fn placed_hydrogens(&self) -> usize {
+        self.hydrogens.iter().chain(self.groups.iter().map(|g| &g.hydrogens)).fold(0, |sum, h| sum.saturating_add(*h))
+    }
+
+    /// Bonds and hydrogens of the atom, for its valence
+    fn used_valence(&self, atom: usize) -> usize {
+        self.bonds.iter().filter(|(a, b)| *a == atom || *b == atom).count() + self.hydrogens[atom]
+    }
+
+    /// The `level`-th valence of the atom that fits its bonds and hydrogens
+    fn valence(&self, atom: usize, level: usize) -> Option<usize> {
+        let used = self.used_valence(atom);
+        allowed_valences(self.elements[atom], self.charges[atom]).iter().filter(|v| **v >= used).nth(level).copied()
+    }
+
+    /// Match the free valences of the bonded atoms, they are the double and triple bonds. Every free
+    /// valence is a vertex, the ones of the mobile groups can also take a hydrogen instead
+    fn match_valences(&self, levels: &[usize], in_group: &[Option<usize>]) -> (Vec<usize>, Vec<usize>, Vec<Option<usize>>) {
+        let n = self.elements.len();
+        let mut vertices = Vec::new();
+        let mut first_vertex = Vec::with_capacity(n + 1);
+        for atom in 0..n {
+            first_vertex.push(vertices.len());
+            let free = self.valence(atom, levels[atom]).map(|v| v - self.used_valence(atom)).unwrap_or(0);
+            // The mobile hydrogens are not counted yet, a group atom has at least a free valence
+            let free = if in_group[atom].is_some() { free.max(1) } else { free };
+            vertices.extend(std::iter::repeat_n(atom, free));
+        }
+        first_vertex.push(vertices.len());
+        let mut adjacency = vec![Vec::new(); vertices.len()];
+        for (a, b) in self.bonds.iter() {
+            for u in first_vertex[*a]..first_vertex[*a + 1] {
+                for v in first_vertex[*b]..first_vertex[*b + 1] {
+                    adjacency[u].push(v);
+                    adjacency[v].push(u);
+                }
+            }
+        }
+        let mut matching = Matching::new(&adjacency);
+        let required: Vec<usize> = (0..vertices.len()).filter(|v| in_group[vertices[*v]].is_none()).collect();
+        for v in required.iter() {
+            if matching.mate[*v].is_some() {
+                continue;
+            }
+            if let Some(to) = adjacency[*v].iter().find(|to| matching.mate[**to].is_none() && in_group[vertices[**to]].is_none()) {
+                matching.mate[*v] = Some(*to);
+                matching.mate[*to] = Some(*v);
+            }
+        }
+        for v in required.iter() {
+            if matching.mate[*v].is_none() {
+                matching.augment(*v);
+            }
+        }
+        // The groups keep as many free valences as hydrogens and charges they have
+        for (id, group) in self.groups.iter().enumerate() {
+            let members: Vec<usize> = (0..vertices.len()).filter(|v| in_group[vertices[*v]] == Some(id)).collect();
+            for v in members.iter() {
+                let left = members.iter().filter(|v| matching.mate[**v].is_none()).count();
+                if left <= group.hydrogens + group.negative {
+                    break;
+                }
+                if matching.mate[*v].is_none() {
+                    matching.augment(*v);
+                }
+            }
+        }
+        let mate = matching.mate;
+        (vertices, first_vertex, mate)
+    }
+
+    /// `None` if the charge of the `/q` layer and the protons don't fit on a single atom
+    fn build_molecule(&mut self) -> Option<Molecule> {
+        let n = self.elements.len();
+        if n == 1 {
+            self.charges[0] = self.charges[0].checked_add(self.charge)?;
+        }
+        else {
+            // Atoms with more bonds than a neutral valence take the charge
+            let mut charge = self.charge;
+            for atom in 0..n {
+                let sign = charge.signum();
+                if sign == 0 {
+                    break;
+                }
+                let used = self.used_valence(atom);
+                let neutral = allowed_valences(self.elements[atom], 0);
+                if self.charges[atom] == 0 && neutral.first().is_some_and(|v| *v < used) && !neutral.contains(&used) &&
+                    allowed_valences(self.elements[atom], sign).contains(&used) {
+                    self.charges[atom] = sign;
+                    charge -= sign;
+                }
+            }
+            self.charge = charge;
+        }
+        let mut in_group = vec![None; n];
+        for (id, group) in self.groups.iter().enumerate() {
+            for atom in group.atoms.iter() {
+                in_group[*atom] = Some(id);
+            }
+        }
+        // The atoms next to a free valence that is left take their next valence, as the nitrogen of `N(=O)=O`.
+        // The groups only leave free the valences that their hydrogens and charges fill, so the sulfur
+        // of `OS(=O)(=O)O` is raised too
+        let mut levels = vec![0; n];
+        let (vertices, first_vertex, mate) = loop {
+            let (vertices, first_vertex, mate) = self.match_valences(&levels, &in_group);
+            let mut unmatched: Vec<usize> = (0..vertices.len()).filter(|v| mate[*v].is_none() && in_group[vertices[*v]].is_none()).collect();
+            for (id, group) in self.groups.iter().enumerate() {
+                let free: Vec<usize> = (0..vertices.len()).filter(|v| mate[*v].is_none() && in_group[vertices[*v]] == Some(id)).collect();
+                unmatched.extend(free.iter().skip(group.hydrogens + group.negative));
+            }
+            let mut raised = false;
+            for v in unmatched {
+                let atom = vertices[v];
+                let neighbour = self.bonds.iter()
+                    .filter_map(|(a, b)| if *a == atom { Some(*b) } else if *b == atom { Some(*a) } else { None })
+                    .find(|a| self.valence(*a, levels[*a] + 1).is_some());
+                if let Some(neighbour) = neighbour {
+                    levels[neighbour] += 1;
+                    raised = true;
+                    break;
+                }
+            }
+            if !raised {
+                break (vertices, first_vertex, mate);
+            }
+        };
+        let mut hydrogens = self.hydrogens.clone();
+        let mut groups = self.groups.clone();
+        let mut charge = self.charge;
+        for v in 0..vertices.len() {
+            if mate[v].is_some() {
+                continue;
+            }
+            let atom = vertices[v];
+            if let Some(group) = in_group[atom].map(|g| &mut groups[g]) {
+                if group.negative > 0 && self.charges[atom] == 0 {
+                    group.negative -= 1;
+                    self.charges[atom] = -1;
+                    continue;
+                }
+                if group.hydrogens > 0 {
+                    group.hydrogens -= 1;
+                    hydrogens[atom] += 1;
+                    continue;
+                }
+            }
+            // A free valence left is an ion
+            if charge != 0 && self.charges[atom] == 0 {
+                self.charges[atom] = charge.signum();
+                charge -= charge.signum();
+            }
+        }
+        // Hydrogens that did not fit anywhere stay on the first atom of their group
+        for group in groups.iter() {
+            if let Some(atom) = group.atoms.first() {
+                hydrogens[*atom] += group.hydrogens;
+            }
+        }
+        if charge != 0 {
+            if let Some(atom) = (0..n).find(|a| self.charges[*a] == 0) {
+                self.charges[atom] = charge;
+            }
+        }
+        let mut molecule = Molecule { atoms: Vec::new(), bonds: Vec::new(), coords: None };
+        for (element, charge) in self.elements.iter().zip(self.charges.iter()) {
+            molecule.atoms.push(AtomAndBondI::new(Isotope::from(Ion::new(*element, *charge)), Vec::new()));
+        }
+        for (a, b) in self.bonds.iter() {
+            let extra = (first_vertex[*a]..first_vertex[*a + 1])
+                .filter(|v| mate[*v].is_some_and(|m| vertices[m] == *b)).count();
+            let k = match extra {
+                0 => StructuralBond::Single,
+                1 => StructuralBond::Double,
+                _ => StructuralBond::Triple
+            };
+            add_bond(&mut molecule, *a, *b, k);
+        }
+        for (atom, count) in hydrogens.iter().enumerate() {
+            for _ in 0..*count {
+                let h = molecule.atoms.len();
+                molecule.atoms.push(AtomAndBondI::new(Isotope::from(Element::Hydrogen), Vec::new()));
+                add_bond(&mut molecule, atom, h, StructuralBond::Single);
+            }
+        }
+        Some(molecule)
+    }
+}
+
+fn add_bond(molecule: &mut Molecule, a: usize, b: usize, k: StructuralBond) {
+    let id = molecule.bonds.len();
+    molecule.atoms[a].bonds.push(id);
+    molecule.atoms[b].bonds.push(id);
+    molecule.bonds.push(Bond::new(a, b, k));
+}
+
+/// Apply the `/p` layer: the protons are removed from the mobile groups first, then from the
+/// heteroatoms, and they are added to the saturated nitrogens and oxygens
+fn move_protons(components: &mut Vec<Component>, mut protons: i8) {
+    const ACCEPTORS: [(Element, usize); 4] = [(Element::Nitrogen, 3), (Element::Phosphorus, 3), (Element::Oxygen, 2),
+        (Element::Sulfur, 2)];
+    while protons < 0 {
+        let group = components.iter_mut().flat_map(|c| c.groups.iter_mut()).find(|g| g.hydrogens > 0);
+        if let Some(group) = group {
+            group.hydrogens -= 1;
+            group.negative += 1;
+            protons += 1;
+            continue;
+        }
+        let mut found = false;
+        'donors: for element in [Element::Oxygen, Element::Sulfur, Element::Nitrogen, Element::Chlorine, Element::Bromine,
+            Element::Iodine, Element::Fluorine, Element::Carbon].iter() {
+            for component in components.iter_mut() {
+                for atom in 0..component.elements.len() {
+                    if component.elements[atom] == *element && component.hydrogens[atom] > 0 && component.charges[atom] == 0 {
+                        component.hydrogens[atom] -= 1;
+                        component.charges[atom] = -1;
+                        found = true;
+                        break 'donors;
+                    }
+                }
+            }
+        }
+        if !found {
+            break;
+        }
+        protons += 1;
+    }
+    while protons > 0 {
+        let mut found = false;
+        'acceptors: for (element, valence) in ACCEPTORS.iter() {
+            for component in components.iter_mut() {
+                for atom in 0..component.elements.len() {
+                    if component.elements[atom] == *element && component.charges[atom] == 0 &&
+                        component.used_valence(atom) == *valence {
+                        component.hydrogens[atom] += 1;
+                        component.charges[atom] = 1;
+                        found = true;
+                        break 'acceptors;
+                    }
+                }
+            }
+        }
+        if !found {
+            // The protons alone, as in `InChI=1S/p+1`
+            for _ in 0..protons {
+                let mut component = Component::new(&EmpiricalFormula::new(vec![(Element::Hydrogen, 1)]));
+                component.charge = 1;
+                components.push(component);
+            }
+            return;
+        }
+        protons -= 1;
+    }
+}
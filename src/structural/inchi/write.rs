@@ -0,0 +1,474 @@
+use std::cmp::Reverse;
+
+use ptable::Element;
+
+use super::super::{AromaticityModel, Molecule, StructuralBond};
+
+/// Leaves of the search of the canonical numbering that are compared, enough for the usual symmetries
+const MAX_NUMBERINGS: usize = 2000;
+
+/// A component without its hydrogens, they are counted on the atoms or on the mobile groups
+struct Skeleton {
+    elements: Vec<Element>,
+    charges: Vec<i8>,
+    hydrogens: Vec<usize>,
+    /// Neighbours, the order of the bond and if it is aromatic
+    neighbours: Vec<Vec<(usize, usize, bool)>>,
+    groups: Vec<(Vec<usize>, usize)>,
+}
+
+/// The layers of a component, already numbered
+struct Layers {
+    atoms: usize,
+    formula: Vec<(&'static str, usize)>,
+    connections: String,
+    hydrogens: String,
+    charge: i32,
+}
+
+/// Standard InChI of the molecules: formula, connections, hydrogens, charge and protons layers.
+/// The charged acids and bases are neutralized into the `/p` layer and the hydrogens that can move
+/// between heteroatoms bonded to the same atom (`C(=O)O`, `C(=N)N`) make a mobile group
+pub(crate) fn write_inchi<'a, I: Iterator<Item = &'a Molecule>>(molecules: I) -> String {
+    let mut components = Vec::new();
+    let mut protons = 0;
+    for molecule in molecules {
+        for (component, _) in molecule.components() {
+            if let Some(mut skeleton) = Skeleton::new(&component, &mut protons) {
+                protons += skeleton.neutralize();
+                skeleton.find_groups();
+                components.push(skeleton.layers());
+            }
+        }
+    }
+    components.sort_by(|a, b| (Reverse(a.atoms), &a.formula, &a.connections, &a.hydrogens)
+        .cmp(&(Reverse(b.atoms), &b.formula, &b.connections, &b.hydrogens)));
+    let mut layers = Vec::new();
+    let formulas: Vec<String> = components.iter().map(|c| c.formula.iter()
+        .map(|(symbol, count)| if *count == 1 { symbol.to_string() } else { format!("{}{}", symbol, count) })
+        .collect()).collect();
+    if !formulas.is_empty() {
+        layers.push(join_components(&formulas, "", "."));
+    }
+    let connections: Vec<String> = components.iter().map(|c| c.connections.clone()).collect();
+    if connections.iter().any(|c| !c.is_empty()) {
+        layers.push(format!("c{}", join_components(&connections, "*", ";")));
+    }
+    let hydrogens: Vec<String> = components.iter().map(|c| c.hydrogens.clone()).collect();
+    if hydrogens.iter().any(|h| !h.is_empty()) {
+        layers.push(format!("h{}", join_components(&hydrogens, "*", ";")));
+    }
+    if components.iter().any(|c| c.charge != 0) {
+        let charges: Vec<String> = components.iter()
+            .map(|c| if c.charge == 0 { String::new() } else { format!("{:+}", c.charge) }).collect();
+        layers.push(format!("q{}", join_components(&charges, "*", ";")));
+    }
+    if protons != 0 {
+        layers.push(format!("p{:+}", protons));
+    }
+    format!("InChI=1S/{}", layers.join("/"))
+}
+
+/// Join the layers of the components, the same layer repeated goes once with its count
+fn join_components(entries: &[String], multiplier: &str, separator: &str) -> String {
+    let mut out = Vec::new();
+    let mut i = 0;
+    while i < entries.len() {
+        let mut count = 1;
+        while !entries[i].is_empty() && i + count < entries.len() && entries[i + count] == entries[i] {
+            count += 1;
+        }
+        if count == 1 {
+            out.push(entries[i].clone());
+        }
+        else {
+            out.push(format!("{}{}{}", count, multiplier, entries[i]));
+        }
+        i += count;
+    }
+    out.join(separator)
+}
+
+/// Ranks of the keys, the atoms with the same key take the last position of their class
+fn ranks_of<T: Ord>(keys: &[T]) -> Vec<usize> {
+    let mut order: Vec<usize> = (0..keys.len()).collect();
+    order.sort_by(|a, b| keys[*a].cmp(&keys[*b]));
+    let mut ranks = vec![0; keys.len()];
+    let mut start = 0;
+    while start < order.len() {
+        let mut end = start + 1;
+        while end < order.len() && keys[order[end]] == keys[order[start]] {
+            end += 1;
+        }
+        for atom in order[start..end].iter() {
+            ranks[*atom] = end;
+        }
+        start = end;
+    }
+    ranks
+}
+
+fn classes(ranks: &[usize]) -> usize {
+    let mut ranks = ranks.to_vec();
+    ranks.sort_unstable();
+    ranks.dedup();
+    ranks.len()
+}
+
+impl Skeleton {
+    /// The heavy atoms of the component, or a hydrogen for the hydrogen molecule. A proton alone
+    /// goes to the `/p` layer
+    fn new(component: &Molecule, protons: &mut i32) -> Option<Skeleton> {
+        // The bond orders and the aromatic rings only matter for the mobile hydrogens
+        let mut aromatic = component.clone();
+        aromatic.perceive_aromaticity(AromaticityModel::Daylight);
+        let mut kekulized = component.clone();
+        if kekulized.bonds.iter().any(|b| b.k == StructuralBond::Aromatic) {
+            let _ = kekulized.kekulize();
+        }
+        let is_hydrogen = |id: usize| *component.atoms[id].get_element() == Element::Hydrogen;
+        let mut heavy: Vec<usize> = (0..component.atoms.len()).filter(|a| !is_hydrogen(*a)).collect();
+        if heavy.is_empty() {
+            match component.atoms.first() {
+                Some(atom) if component.atoms.len() == 1 && *atom.get_ion().get_charge() == 1 => {
+                    *protons += 1;
+                    return None;
+                },
+                Some(_) => heavy.push(0),
+                None => return None
+            }
+        }
+        let mut index = vec![None; component.atoms.len()];
+        for (i, atom) in heavy.iter().enumerate() {
+            index[*atom] = Some(i);
+        }
+        let mut skeleton = Skeleton {
+            elements: heavy.iter().map(|a| *component.atoms[*a].get_element()).collect(),
+            charges: heavy.iter().map(|a| *component.atoms[*a].get_ion().get_charge()).collect(),
+            hydrogens: vec![0; heavy.len()],
+            neighbours: vec![Vec::new(); heavy.len()],
+            groups: Vec::new(),
+        };
+        for (bond, flagged) in kekulized.bonds.iter().zip(aromatic.bonds.iter()) {
+            match (index[bond.a], index[bond.b]) {
+                (Some(a), Some(b)) => {
+                    let order = match bond.k {
+                        StructuralBond::Aromatic => 1,
+                        k => k as usize
+                    };
+                    let aromatic = flagged.k == StructuralBond::Aromatic;
+                    skeleton.neighbours[a].push((b, order, aromatic));
+                    skeleton.neighbours[b].push((a, order, aromatic));
+                },
+                (Some(a), None) | (None, Some(a)) => skeleton.hydrogens[a] += 1,
+                (None, None) => {}
+            }
+        }
+        Some(skeleton)
+    }
+
+    /// Remove the charges of the charge separated bonds (`[N+][O-]`), the protons of the charged
+    /// bases and add the ones of the charged acids. It returns the protons removed
+    fn neutralize(&mut self) -> i32 {
+        for a in 0..self.elements.len() {
+            for i in 0..self.neighbours[a].len() {
+                let b = self.neighbours[a][i].0;
+                if self.charges[a] > 0 && self.charges[b] < 0 {
+                    self.charges[a] -= 1;
+                    self.charges[b] += 1;
+                    self.neighbours[a][i].1 += 1;
+                    if let Some(back) = self.neighbours[b].iter_mut().find(|(n, _, _)| *n == a) {
+                        back.1 += 1;
+                    }
+                }
+            }
+        }
+        let mut protons = 0;
+        for atom in 0..self.elements.len() {
+            match (self.elements[atom], self.charges[atom]) {
+                (Element::Oxygen, -1) | (Element::Sulfur, -1) | (Element::Selenium, -1) | (Element::Tellurium, -1) |
+                    (Element::Nitrogen, -1) | (Element::Fluorine, -1) | (Element::Chlorine, -1) | (Element::Bromine, -1) |
+                    (Element::Iodine, -1) => {
+                    self.charges[atom] = 0;
+                    self.hydrogens[atom] += 1;
+                    protons -= 1;
+                },
+                (Element::Nitrogen, 1) | (Element::Phosphorus, 1) | (Element::Oxygen, 1) | (Element::Sulfur, 1)
+                    if self.hydrogens[atom] > 0 => {
+                    self.charges[atom] = 0;
+                    self.hydrogens[atom] -= 1;
+                    protons += 1;
+                },
+                _ => {}
+            }
+        }
+        protons
+    }
+
+    /// Group the heteroatoms bonded to the same atom, one with a double or aromatic bond and one
+    /// with hydrogens or already in a group, their hydrogens are shared. The heteroatoms must have
+    /// room for a hydrogen, so a nitrogen with three neighbours (as the N9 of the nucleosides) is left out
+    fn find_groups(&mut self) {
+        let n = self.elements.len();
+        let endpoint = |atom: usize| self.charges[atom] == 0 && match self.elements[atom] {
+            Element::Nitrogen => self.neighbours[atom].len() < 3,
+            Element::Oxygen | Element::Sulfur | Element::Selenium | Element::Tellurium => self.neighbours[atom].len() < 2,
+            _ => false
+        };
+        fn root(parent: &mut [usize], mut atom: usize) -> usize {
+            while parent[atom] != atom {
+                parent[atom] = parent[parent[atom]];
+                atom = parent[atom];
+            }
+            atom
+        }
+        let mut parent: Vec<usize> = (0..n).collect();
+        let mut members = vec![false; n];
+        let mut changed = true;
+        while changed {
+            changed = false;
+            for center in 0..n {
+                let acceptors: Vec<usize> = self.neighbours[center].iter()
+                    .filter(|(a, order, aromatic)| (*order == 2 || *aromatic) && endpoint(*a)).map(|(a, _, _)| *a).collect();
+                let donors: Vec<usize> = self.neighbours[center].iter()
+                    .filter(|(a, order, aromatic)| (*order == 1 || *aromatic) && endpoint(*a) && (self.hydrogens[*a] > 0 || members[*a]))
+                    .map(|(a, _, _)| *a).collect();
+                if !donors.iter().any(|d| acceptors.iter().any(|a| a != d)) {
+                    continue;
+                }
+                for atom in acceptors.iter().chain(donors.iter()) {
+                    members[*atom] = true;
+                    let (a, b) = (root(&mut parent, acceptors[0]), root(&mut parent, *atom));
+                    if a != b {
+                        parent[b] = a;
+                        changed = true;
+                    }
+                }
+            }
+        }
+        let mut groups: Vec<(usize, Vec<usize>, usize)> = Vec::new();
+        for atom in (0..n).filter(|a| members[*a]) {
+            let r = root(&mut parent, atom);
+            let position = match groups.iter().position(|g| g.0 == r) {
+                Some(position) => position,
+                None => {
+                    groups.push((r, Vec::new(), 0));
+                    groups.len() - 1
+                }
+            };
+            groups[position].1.push(atom);
+            groups[position].2 += self.hydrogens[atom];
+            self.hydrogens[atom] = 0;
+        }
+        self.groups = groups.into_iter().map(|(_, atoms, hydrogens)| (atoms, hydrogens)).collect();
+    }
+
+    fn refine(&self, mut ranks: Vec<usize>) -> Vec<usize> {
+        loop {
+            let keys: Vec<(usize, Vec<usize>)> = (0..ranks.len()).map(|a| {
+                let mut neighbours: Vec<usize> = self.neighbours[a].iter().map(|(b, _, _)| ranks[*b]).collect();
+                neighbours.sort_unstable();
+                (ranks[a], neighbours)
+            }).collect();
+            let refined = ranks_of(&keys);
+            if classes(&refined) == classes(&ranks) {
+                return refined;
+            }
+            ranks = refined;
+        }
+    }
+
+    /// Connection table of a numbering: every atom followed by its neighbours with lower numbers
+    fn connection_table(&self, numbers: &[usize]) -> Vec<usize> {
+        let mut atoms: Vec<usize> = (0..numbers.len()).collect();
+        atoms.sort_by_key(|a| numbers[*a]);
+        let mut table = Vec::new();
+        for atom in atoms {
+            table.push(numbers[atom]);
+            let mut lower: Vec<usize> = self.neighbours[atom].iter().map(|(b, _, _)| numbers[*b])
+                .filter(|b| *b < numbers[atom]).collect();
+            lower.sort_unstable();
+            table.extend(lower);
+        }
+        table
+    }
+
+    /// Break the ties of the ranks on the first class with more than one atom, every atom of the
+    /// class is tried first. The numbering with the lowest connection table wins, then the one with
+    /// the lowest hydrogens in order
+    fn search(&self, ranks: Vec<usize>, best: &mut Option<(Vec<usize>, Vec<usize>, Vec<usize>)>, budget: &mut usize) {
+        let ranks = self.refine(ranks);
+        if classes(&ranks) == ranks.len() {
+            let table = self.connection_table(&ranks);
+            let mut hydrogens = vec![0; ranks.len()];
+            for (atom, rank) in ranks.iter().enumerate() {
+                hydrogens[rank - 1] = self.hydrogens[atom];
+            }
+            if best.as_ref().is_none_or(|b| (&table, &hydrogens) < (&b.0, &b.1)) {
+                *best = Some((table, hydrogens, ranks));
+            }
+            return;
+        }
+        let rank = (1..=ranks.len()).find(|r| ranks.iter().filter(|x| *x == r).count() > 1).unwrap();
+        let class: Vec<usize> = (0..ranks.len()).filter(|a| ranks[*a] == rank).collect();
+        for atom in class.iter() {
+            if *budget == 0 && best.is_some() {
+                return;
+            }
+            *budget = budget.saturating_sub(1);
+            let mut tied = ranks.clone();
+            tied[*atom] = rank + 1 - class.len();
+            self.search(tied, best, budget);
+        }
+    }
+
+    /// Canonical number of every atom, from 1: the elements in the order of the formula, the atoms
+    /// with fewer neighbours first and then the ranks of the neighbours
+    fn canonical_numbers(&self) -> Vec<usize> {
+        let keys: Vec<(bool, &str, usize)> = (0..self.elements.len())
+            .map(|a| (self.elements[a] != Element::Carbon, self.elements[a].get_symbol(), self.neighbours[a].len())).collect();
+        let mut best = None;
+        let mut budget = MAX_NUMBERINGS;
+        self.search(ranks_of(&keys), &mut best, &mut budget);
+        best.unwrap().2
+    }
+
+    fn layers(&self) -> Layers {
+        let n = self.elements.len();
+        let numbers = self.canonical_numbers();
+        let mut neighbours = vec![Vec::new(); n + 1];
+        for atom in 0..n {
+            let mut list: Vec<usize> = self.neighbours[atom].iter().map(|(b, _, _)| numbers[*b]).collect();
+            list.sort_unstable();
+            neighbours[numbers[atom]] = list;
+        }
+        let mut counts = std::collections::BTreeMap::new();
+        for element in self.elements.iter() {
+            *counts.entry(*element).or_insert(0) += 1;
+        }
+        let hydrogens = self.hydrogens.iter().sum::<usize>() + self.groups.iter().map(|g| g.1).sum::<usize>();
+        if hydrogens > 0 {
+            *counts.entry(Element::Hydrogen).or_insert(0) += hydrogens;
+        }
+        // Hill order: carbon and hydrogen first if there is carbon, the rest alphabetically
+        let carbon = self.elements.contains(&Element::Carbon);
+        let mut formula: Vec<(&'static str, usize)> = counts.into_iter().map(|(e, c)| (e.get_symbol(), c)).collect();
+        formula.sort_by_key(|(symbol, _)| match (carbon, *symbol) {
+            (true, "C") => (0, *symbol),
+            (true, "H") => (1, *symbol),
+            _ => (2, *symbol)
+        });
+        let mut hydrogen_counts = vec![0; n + 1];
+        for atom in 0..n {
+            hydrogen_counts[numbers[atom]] = self.hydrogens[atom];
+        }
+        let mut groups: Vec<(Vec<usize>, usize)> = self.groups.iter()
+            .map(|(atoms, h)| {
+                let mut atoms: Vec<usize> = atoms.iter().map(|a| numbers[*a]).collect();
+                atoms.sort_unstable();
+                (atoms, *h)
+            }).collect();
+        // The groups with fewer hydrogens go first
+        groups.sort_by(|a, b| (a.1, &a.0).cmp(&(b.1, &b.0)));
+        Layers {
+            atoms: n,
+            formula,
+            connections: write_connections(&neighbours),
+            hydrogens: write_hydrogens(&hydrogen_counts, &groups),
+            charge: self.charges.iter().map(|c| *c as i32).sum(),
+        }
+    }
+}
+
+/// The connections layer: a depth first walk from the first atom with a single neighbour, the ring
+/// closures and the smaller branches go first, between parentheses
+fn write_connections(neighbours: &[Vec<usize>]) -> String {
+    let n = neighbours.len() - 1;
+    if n < 2 {
+        return String::new();
+    }
+    let start = (1..=n).find(|a| neighbours[*a].len() == 1).unwrap_or(1);
+    let mut visited = vec![None; n + 1];
+    let mut parent = vec![0; n + 1];
+    let mut children = vec![Vec::new(); n + 1];
+    let mut order = Vec::new();
+    let mut stack = vec![start];
+    visited[start] = Some(0);
+    order.push(start);
+    // The walk goes to the neighbours in increasing order
+    while let Some(atom) = stack.last().copied() {
+        match neighbours[atom].iter().find(|b| visited[**b].is_none()) {
+            Some(next) => {
+                visited[*next] = Some(order.len());
+                order.push(*next);
+                parent[*next] = atom;
+                children[atom].push(*next);
+                stack.push(*next);
+            },
+            None => {
+                stack.pop();
+            }
+        }
+    }
+    let mut sizes = vec![1; n + 1];
+    for atom in order.iter().rev() {
+        if *atom != start {
+            sizes[parent[*atom]] += sizes[*atom];
+        }
+    }
+    let mut texts = vec![String::new(); n + 1];
+    for atom in order.iter().rev() {
+        let mut items: Vec<String> = neighbours[*atom].iter()
+            .filter(|b| **b != parent[*atom] && visited[**b] < visited[*atom])
+            .map(|b| b.to_string()).collect();
+        let mut branches = children[*atom].clone();
+        branches.sort_by_key(|b| (sizes[*b], *b));
+        items.extend(branches.iter().map(|b| std::mem::take(&mut texts[*b])));
+        let mut text = atom.to_string();
+        if let Some((last, rest)) = items.split_last() {
+            if rest.is_empty() {
+                text.push('-');
+            }
+            else {
+                text.push('(');
+                text.push_str(&rest.join(","));
+                text.push(')');
+            }
+            text.push_str(last);
+        }
+        texts[*atom] = text;
+    }
+    std::mem::take(&mut texts[start])
+}
+
+/// The hydrogens layer: the atoms with the same count of hydrogens, from one hydrogen up, and the
+/// mobile groups, which are not separated between them as in `1H3,(H,3,4)(H,5,6)`
+fn write_hydrogens(counts: &[usize], groups: &[(Vec<usize>, usize)]) -> String {
+    let mut entries = Vec::new();
+    let max = counts.iter().copied().max().unwrap_or(0);
+    for count in 1..=max {
+        let atoms: Vec<usize> = (1..counts.len()).filter(|a| counts[*a] == count).collect();
+        if atoms.is_empty() {
+            continue;
+        }
+        let mut ranges = Vec::new();
+        let mut i = 0;
+        while i < atoms.len() {
+            let mut j = i;
+            while j + 1 < atoms.len() && atoms[j + 1] == atoms[j] + 1 {
+                j += 1;
+            }
+            ranges.push(if i == j { atoms[i].to_string() } else { format!("{}-{}", atoms[i], atoms[j]) });
+            i = j + 1;
+        }
+        entries.push(format!("{}H{}", ranges.join(","), if count == 1 { String::new() } else { count.to_string() }));
+    }
+    let mobile: String = groups.iter().map(|(atoms, hydrogens)| {
+        let atoms: Vec<String> = atoms.iter().map(|a| a.to_string()).collect();
+        format!("(H{},{})", if *hydrogens == 1 { String::new() } else { hydrogens.to_string() }, atoms.join(","))
+    }).collect();
+    if !mobile.is_empty() {
+        entries.push(mobile);
+    }
+    entries.join(",")
+}
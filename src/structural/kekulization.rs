@@ -0,0 +1,233 @@
+use std::collections::VecDeque;
+use std::fmt::{self, Display};
+
+use ptable::Element;
+
+use super::{Molecule, StructuralBond};
+
+/// The aromatic bonds of a molecule cannot be written with single and double bonds, as in `c1cccc1`
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct KekulizationError {
+    /// Aromatic atoms that were left without a double bond
+    pub atoms: Vec<usize>,
+}
+
+impl Display for KekulizationError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "no single and double bonds pattern for the aromatic atoms")?;
+        for (i, atom) in self.atoms.iter().enumerate() {
+            write!(f, "{} {}", if i == 0 { "" } else { "," }, atom)?;
+        }
+        Ok(())
+    }
+}
+
+/// Valences that an atom can have, from the lowest to the highest
+pub(crate) fn valences(element: Element, charge: i8) -> &'static [usize] {
+    match (element, charge) {
+        (Element::Hydrogen, 0) | (Element::Fluorine, 0) | (Element::Chlorine, 0) | (Element::Bromine, 0) |
+            (Element::Iodine, 0) | (Element::Oxygen, -1) | (Element::Sulfur, -1) => &[1],
+        (Element::Oxygen, 0) | (Element::Nitrogen, -1) => &[2],
+        (Element::Sulfur, 0) | (Element::Selenium, 0) | (Element::Tellurium, 0) => &[2, 4, 6],
+        (Element::Boron, 0) | (Element::Carbon, 1) | (Element::Carbon, -1) | (Element::Nitrogen, 0) |
+            (Element::Oxygen, 1) => &[3],
+        (Element::Phosphorus, 0) | (Element::Arsenic, 0) | (Element::Sulfur, 1) | (Element::Selenium, 1) => &[3, 5],
+        (Element::Boron, -1) | (Element::Carbon, 0) | (Element::Silicon, 0) | (Element::Nitrogen, 1) |
+            (Element::Phosphorus, 1) | (Element::Arsenic, 1) => &[4],
+        _ => &[]
+    }
+}
+
+/// Maximum matching of a general graph with the blossom algorithm of Edmonds
+pub(crate) struct Matching<'a> {
+    adjacency: &'a [Vec<usize>],
+    pub(crate) mate: Vec<Option<usize>>,
+    parent: Vec<Option<usize>>,
+    base: Vec<usize>,
+    used: Vec<bool>,
+    blossom: Vec<bool>,
+}
+
+impl<'a> Matching<'a> {
+    pub(crate) fn new(adjacency: &'a [Vec<usize>]) -> Matching<'a> {
+        let n = adjacency.len();
+        Matching { adjacency, mate: vec![None; n], parent: vec![None; n], base: (0..n).collect(),
+            used: vec![false; n], blossom: vec![false; n] }
+    }
+
+    /// Nearest common ancestor of two vertices in the alternating tree
+    fn common_ancestor(&self, mut a: usize, mut b: usize) -> usize {
+        let mut seen = vec![false; self.adjacency.len()];
+        loop {
+            a = self.base[a];
+            seen[a] = true;
+            match self.mate[a] {
+                Some(m) => a = self.parent[m].unwrap(),
+                None => break
+            }
+        }
+        loop {
+            b = self.base[b];
+            if seen[b] {
+                return b;
+            }
+            b = self.parent[self.mate[b].unwrap()].unwrap();
+        }
+    }
+
+    fn mark_path(&mut self, mut v: usize, base: usize, mut child: usize) {
+        while self.base[v] != base {
+            let m = self.mate[v].unwrap();
+            self.blossom[self.base[v]] = true;
+            self.blossom[self.base[m]] = true;
+            self.parent[v] = Some(child);
+            child = m;
+            v = self.parent[m].unwrap();
+        }
+    }
+
+    /// Look for an augmenting path from a free vertex, it returns the other end of the path
+    fn find_path(&mut self, root: usize) -> Option<usize> {
+        let n = self.adjacency.len();
+        self.used.iter_mut().for_each(|u| *u = false);
+        self.parent.iter_mut().for_each(|p| *p = None);
+        for i in 0..n {
+            self.base[i] = i;
+        }
+        self.used[root] = true;
+        let mut queue = VecDeque::new();
+        queue.push_back(root);
+        while let Some(v) = queue.pop_front() {
+            for to in self.adjacency[v].iter().cloned() {
+                if self.base[v] == self.base[to] || self.mate[v] == Some(to) {
+                    continue;
+                }
+                if to == root || self.mate[to].is_some_and(|m| self.parent[m].is_some()) {
+                    // An odd cycle, it is contracted into its base
+                    let base = self.common_ancestor(v, to);
+                    self.blossom.iter_mut().for_each(|b| *b = false);
+                    self.mark_path(v, base, to);
+                    self.mark_path(to, base, v);
+                    for i in 0..n {
+                        if self.blossom[self.base[i]] {
+                            self.base[i] = base;
+                            if !self.used[i] {
+                                self.used[i] = true;
+                                queue.push_back(i);
+                            }
+                        }
+                    }
+                }
+                else if self.parent[to].is_none() {
+                    self.parent[to] = Some(v);
+                    match self.mate[to] {
+                        Some(m) => {
+                            self.used[m] = true;
+                            queue.push_back(m);
+                        },
+                        None => return Some(to)
+                    }
+                }
+            }
+        }
+        None
+    }
+
+    /// Try to match the vertex, the vertices that are already matched stay matched
+    pub(crate) fn augment(&mut self, root: usize) -> bool {
+        let mut v = match self.find_path(root) {
+            Some(end) => Some(end),
+            None => return false
+        };
+        while let Some(current) = v {
+            let previous = self.parent[current].unwrap();
+            let next = self.mate[previous];
+            self.mate[current] = Some(previous);
+            self.mate[previous] = Some(current);
+            v = next;
+        }
+        true
+    }
+}
+
+/// Replace the aromatic bonds by single and double bonds, every aromatic atom that still has a free
+/// valence gets exactly one double bond. The wildcard atoms can take a double bond but they don't need one
+pub fn kekulize(molecule: &mut Molecule) -> Result<(), KekulizationError> {
+    let aromatic: Vec<usize> = (0..molecule.bonds.len())
+        .filter(|b| molecule.bonds[*b].k == StructuralBond::Aromatic).collect();
+    if aromatic.is_empty() {
+        return Ok(());
+    }
+    // Vertices of the graph: the atoms that need a double bond and the wildcards
+    let mut vertex = vec![None; molecule.atoms.len()];
+    let mut atoms = Vec::new();
+    let mut required = Vec::new();
+    for bond_id in aromatic.iter() {
+        for id in [molecule.bonds[*bond_id].a, molecule.bonds[*bond_id].b].iter() {
+            if vertex[*id].is_some() {
+                continue;
+            }
+            let atom = &molecule.atoms[*id];
+            let needs_double = if atom.wildcard {
+                false
+            }
+            else {
+                let used: usize = atom.bonds.iter().map(|b| match molecule.bonds[*b].k {
+                    StructuralBond::Aromatic => 1,
+                    k => k as usize
+                }).sum();
+                let allowed = valences(*atom.get_element(), *atom.get_ion().get_charge());
+                match allowed.iter().find(|v| **v >= used) {
+                    Some(v) => *v > used,
+                    None => false
+                }
+            };
+            if needs_double || atom.wildcard {
+                vertex[*id] = Some(atoms.len());
+                if needs_double {
+                    required.push(atoms.len());
+                }
+                atoms.push(*id);
+            }
+        }
+    }
+    let mut adjacency = vec![Vec::new(); atoms.len()];
+    for bond_id in aromatic.iter() {
+        let bond = molecule.bonds[*bond_id];
+        if let (Some(a), Some(b)) = (vertex[bond.a], vertex[bond.b]) {
+            adjacency[a].push(b);
+            adjacency[b].push(a);
+        }
+    }
+    let mut matching = Matching::new(&adjacency);
+    // Start with the atoms with fewer choices, so that the search is rarely needed
+    required.sort_by_key(|v| adjacency[*v].len());
+    for v in required.iter() {
+        if matching.mate[*v].is_some() {
+            continue;
+        }
+        if let Some(to) = adjacency[*v].iter().find(|to| matching.mate[**to].is_none() && !molecule.atoms[atoms[**to]].wildcard) {
+            matching.mate[*v] = Some(*to);
+            matching.mate[*to] = Some(*v);
+        }
+    }
+    let mut unmatched = Vec::new();
+    for v in required.iter() {
+        if matching.mate[*v].is_none() && !matching.augment(*v) {
+            unmatched.push(atoms[*v]);
+        }
+    }
+    if !unmatched.is_empty() {
+        unmatched.sort_unstable();
+        return Err(KekulizationError { atoms: unmatched });
+    }
+    for bond_id in aromatic {
+        let bond = &mut molecule.bonds[bond_id];
+        let double = match (vertex[bond.a], vertex[bond.b]) {
+            (Some(a), Some(b)) => matching.mate[a] == Some(b),
+            _ => false
+        };
+        bond.k = if double { StructuralBond::Double } else { StructuralBond::Single };
+    }
+    Ok(())
+}
@@ -3,20 +3,106 @@ use std::str::Utf8Error;
 use std::collections::BTreeMap;
 use std::io::{self, Read};
 use std::ops::Deref;
+use std::fmt::{self, Display};
+
+use rayon::prelude::*;
 
 mod smiles;
 mod cml;
 mod mol;
 mod nomenclature;
-pub use mol::MolFile;
+mod rings;
+mod aromaticity;
+mod kekulization;
+mod substructure;
+mod smarts;
+mod connectivity;
+mod xyz;
+mod pdb;
+mod inchi;
+pub use mol::{MolFile, SdfReader};
+pub use cml::CmlFile;
+pub use xyz::XyzFile;
+pub use pdb::{AtomSite, PdbFile};
+pub use inchi::InChI;
+pub use aromaticity::AromaticityModel;
+pub use kekulization::KekulizationError;
+pub use rings::Ring;
+pub use smarts::Smarts;
 
 use crate::Point;
 use crate::*;
 
 include!(concat!(env!("OUT_DIR"), "/valences.rs"));
 
+/// Why a text could not be parsed
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Hash)]
+pub enum ParseErrorReason {
+    UnexpectedCharacter,
+    UnexpectedEnd,
+    UnknownElement,
+    BadCharge,
+    BadIsotope,
+    BadHydrogenCount,
+    BadAtomClass,
+    BadRingLabel,
+    UnclosedRing,
+    UnmatchedParenthesis,
+    DanglingBond,
+    BadAtomNumber,
+}
+
+impl Display for ParseErrorReason {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            ParseErrorReason::UnexpectedCharacter => "unexpected character",
+            ParseErrorReason::UnexpectedEnd => "unexpected end",
+            ParseErrorReason::UnknownElement => "unknown element",
+            ParseErrorReason::BadCharge => "bad charge",
+            ParseErrorReason::BadIsotope => "bad isotope",
+            ParseErrorReason::BadHydrogenCount => "bad hydrogen count",
+            ParseErrorReason::BadAtomClass => "bad atom class",
+            ParseErrorReason::BadRingLabel => "bad ring label",
+            ParseErrorReason::UnclosedRing => "unclosed ring",
+            ParseErrorReason::UnmatchedParenthesis => "unmatched parenthesis",
+            ParseErrorReason::DanglingBond => "bond without a second atom",
+            ParseErrorReason::BadAtomNumber => "atom number out of range",
+        })
+    }
+}
+
+/// Error of the parsers of single strings (SMILES, formulas, ...), the position is a byte offset
+/// and the character is the one found there, if the string does not end before
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Hash)]
+pub struct ParseError {
+    pub position: usize,
+    pub character: Option<char>,
+    pub reason: ParseErrorReason,
+}
+
+impl ParseError {
+    pub fn new(string: &[u8], rest: &[u8], reason: ParseErrorReason) -> ParseError {
+        ParseError { position: string.len() - rest.len(), character: rest.first().map(|c| *c as char), reason }
+    }
+
+    /// Move the error as if the parsed string started `offset` bytes later
+    pub fn shifted(self, offset: usize) -> ParseError {
+        ParseError { position: self.position + offset, ..self }
+    }
+}
+
+impl Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.character {
+            Some(c) => write!(f, "{} at byte {} ('{}')", self.reason, self.position, c),
+            None => write!(f, "{} at byte {} (end of input)", self.reason, self.position)
+        }
+    }
+}
+
 #[derive(Debug)]
 pub enum ParserError {
+    Parse(ParseError),
     Syntax,
     IO(io::Error),
     Utf8(Utf8Error),
@@ -37,6 +123,12 @@ impl From<xml::reader::Error> for ParserError {
     }
 }
 
+impl From<ParseError> for ParserError {
+    fn from(e: ParseError) -> ParserError {
+        ParserError::Parse(e)
+    }
+}
+
 impl From<ParseIntError> for ParserError {
     fn from(p: ParseIntError) -> ParserError {
         ParserError::ParseInt(p)
@@ -66,23 +158,87 @@ pub enum StructuralBond {
 
 impl BondClass for StructuralBond {}
 
+/// Configuration of a double bond, it is given for the first neighbour (in the order of the bonds
+/// of the atom) of each end that is not the double bond itself
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Ord, PartialOrd, Hash)]
+pub enum BondStereo {
+    Cis,
+    Trans,
+}
+
 #[derive(Debug, Copy, Clone, Eq, PartialEq, Ord, PartialOrd, Hash)]
 pub struct Bond<B: BondClass> {
     pub a: usize,
     pub b: usize,
-    pub k: B
+    pub k: B,
+    pub stereo: Option<BondStereo>
 }
 
 impl<B: BondClass> Bond<B> {
     pub fn new(a: usize, b: usize, k: B) -> Bond<B> {
-        Bond { a, b, k }
+        Bond { a, b, k, stereo: None }
+    }
+}
+
+/// Configuration of a tetrahedral center, looking from the first neighbour (in the order of the
+/// bonds of the atom) the other ones are placed anticlockwise (`@` in SMILES) or clockwise (`@@`)
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Ord, PartialOrd, Hash)]
+pub enum Chirality {
+    Anticlockwise,
+    Clockwise,
+}
+
+impl Chirality {
+    pub fn inverted(self) -> Chirality {
+        match self {
+            Chirality::Anticlockwise => Chirality::Clockwise,
+            Chirality::Clockwise => Chirality::Anticlockwise,
+        }
+    }
+}
+
+/// Spin multiplicity of a radical centre, as the `M  RAD` property of the molfiles
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Ord, PartialOrd, Hash)]
+pub enum Radical {
+    Singlet,
+    Doublet,
+    Triplet,
+}
+
+/// Elements that a query atom can be, or can't be if the list is excluded (`M  ALS` of the molfiles)
+#[derive(Debug, Clone, Eq, PartialEq, Hash)]
+pub struct AtomList {
+    pub elements: Vec<Element>,
+    pub excluded: bool,
+}
+
+impl AtomList {
+    pub fn contains(&self, element: Element) -> bool {
+        self.elements.contains(&element) != self.excluded
     }
 }
 
 #[derive(Debug, Clone, PartialEq)]
 pub struct AtomAndBondI {
     pub atom: Isotope,
-    pub bonds: Vec<usize>
+    pub bonds: Vec<usize>,
+    pub chirality: Option<Chirality>,
+    /// Atom class (`[CH3:7]` in SMILES), used for example to map the atoms of a reaction
+    pub class: Option<u32>,
+    /// Hydrogens written inside the brackets of a SMILES atom, they are also atoms of the molecule
+    pub explicit_hydrogens: Option<u8>,
+    /// Atom that can be any element (`*` in SMILES), the element of the isotope has no meaning
+    pub wildcard: bool,
+    /// Elements that a wildcard atom is restricted to
+    pub atom_list: Option<AtomList>,
+    pub radical: Option<Radical>,
+}
+
+impl AtomAndBondI {
+    pub fn new(atom: Isotope, bonds: Vec<usize>) -> AtomAndBondI {
+        AtomAndBondI { atom, bonds, chirality: None, class: None, explicit_hydrogens: None, wildcard: false,
+            atom_list: None, radical: None }
+    }
 }
 
 impl Deref for AtomAndBondI {
@@ -101,12 +257,159 @@ pub struct Molecule {
 }
 
 impl Molecule {
-    pub fn from_smiles(string: &str) -> Result<Molecule, ()> {
+    pub fn from_smiles(string: &str) -> Result<Molecule, ParserError> {
         smiles::parse(string)
     }
 
+    pub fn to_smiles(&self) -> String {
+        smiles::write(self)
+    }
+
+    /// Same as `to_smiles` but the result does not depend on the order of the atoms, so two
+    /// molecules with the same structure give the same string
+    pub fn to_canonical_smiles(&self) -> String {
+        smiles::write_canonical(self)
+    }
+
+    /// Standard InChI of the molecule, without the stereo and isotopic layers
+    pub fn to_inchi(&self) -> String {
+        inchi::write_inchi(std::iter::once(self))
+    }
+
+    pub fn to_inchi_key(&self) -> String {
+        InChI::key(&self.to_inchi()).unwrap()
+    }
+
+    /// Mark as aromatic the bonds of the rings that are aromatic under the given model, it works
+    /// on the molecules of any format, like the MOL files which only have single and double bonds
+    pub fn perceive_aromaticity(&mut self, model: AromaticityModel) {
+        aromaticity::perceive(self, model)
+    }
+
+    /// Smallest set of smallest rings, a minimum cycle basis. When there is more than one (as in
+    /// the cubane) the rings that are given depend on the order of the atoms, see `relevant_cycles`
+    pub fn sssr(&self) -> Vec<Ring> {
+        rings::sssr(self)
+    }
+
+    /// Cycles that are part of some minimum cycle basis, it is the union of all the possible SSSRs
+    pub fn relevant_cycles(&self) -> Vec<Ring> {
+        rings::relevant_cycles(self)
+    }
+
+    pub fn is_in_ring(&self, atom: usize) -> bool {
+        let in_ring = rings::ring_bonds(self);
+        self.atoms[atom].bonds.iter().any(|b| in_ring[*b])
+    }
+
+    pub fn is_bond_in_ring(&self, bond: usize) -> bool {
+        rings::ring_bonds(self)[bond]
+    }
+
+    /// Sizes of the relevant cycles that contain the atom, from the smallest to the largest and without repetitions
+    pub fn ring_sizes(&self, atom: usize) -> Vec<usize> {
+        if !self.is_in_ring(atom) {
+            return Vec::new();
+        }
+        let mut sizes: Vec<usize> = self.relevant_cycles().iter().filter(|r| r.contains_atom(atom)).map(|r| r.len()).collect();
+        sizes.sort_unstable();
+        sizes.dedup();
+        sizes
+    }
+
+    /// Atoms of every ring system, the rings that share atoms (fused, bridged or spiro rings) form one system
+    pub fn ring_systems(&self) -> Vec<Vec<usize>> {
+        rings::ring_systems(self)
+    }
+
+    /// Find the query inside the molecule, the atoms must have the same element, charge and isotope
+    /// (if the query has one) and the bonds the same kind. Every match gives the atom of the molecule
+    /// for each atom of the query; the hydrogens of the query that are bonded to heavy atoms are left
+    /// out (`None`), so `C(=O)O` also finds the acids whose carbon has no hydrogen
+    pub fn substructure_matches(&self, query: &Molecule) -> Vec<Vec<Option<usize>>> {
+        substructure::find(query, self, false)
+    }
+
+    pub fn has_substructure(&self, query: &Molecule) -> bool {
+        !substructure::find(query, self, true).is_empty()
+    }
+
+    /// Replace the aromatic bonds by alternating single and double bonds. The molecule is left as
+    /// it was if there is no way of doing it
+    pub fn kekulize(&mut self) -> Result<(), KekulizationError> {
+        kekulization::kekulize(self)
+    }
+
+    /// Add single bonds between the atoms that are near enough, from their coordinates and covalent
+    /// radii, as in the molecules read from XYZ files. Nothing is done if there are no coordinates
+    pub fn perceive_bonds(&mut self) {
+        connectivity::perceive_bonds(self)
+    }
+
+    /// The molecule as an XYZ file with an empty comment
+    pub fn to_xyz(&self) -> String {
+        let mut out = Vec::new();
+        xyz::write_xyz(&mut out, "", self).unwrap();
+        String::from_utf8(out).unwrap()
+    }
+
+    /// Split the molecule into its connected components, the isolated atoms make a component of
+    /// their own. The components are sorted by their first atom and keep the order of the atoms
+    /// and bonds; each one comes with the index on this molecule of every atom
+    pub fn components(&self) -> Vec<(Molecule, Vec<usize>)> {
+        let mut component = vec![usize::MAX; self.atoms.len()];
+        let mut count = 0;
+        for start in 0..self.atoms.len() {
+            if component[start] != usize::MAX {
+                continue;
+            }
+            component[start] = count;
+            let mut stack = vec![start];
+            while let Some(atom) = stack.pop() {
+                for b in self.atoms[atom].bonds.iter() {
+                    let bond = self.bonds[*b];
+                    let pair = if bond.a == atom { bond.b } else { bond.a };
+                    if component[pair] == usize::MAX {
+                        component[pair] = count;
+                        stack.push(pair);
+                    }
+                }
+            }
+            count += 1;
+        }
+        let mut components: Vec<(Molecule, Vec<usize>)> = (0..count).map(|_| {
+            (Molecule { atoms: Vec::new(), bonds: Vec::new(), coords: self.coords.as_ref().map(|_| Vec::new()) }, Vec::new())
+        }).collect();
+        // Position of every atom and bond inside its component
+        let mut atom_position = vec![0; self.atoms.len()];
+        for (id, c) in component.iter().enumerate() {
+            let (molecule, indices) = &mut components[*c];
+            atom_position[id] = molecule.atoms.len();
+            molecule.atoms.push(self.atoms[id].clone());
+            if let (Some(coords), Some(all)) = (molecule.coords.as_mut(), self.coords.as_ref()) {
+                coords.push(all.get(id).copied().unwrap_or_else(|| Point::new(0.0, 0.0, 0.0)));
+            }
+            indices.push(id);
+        }
+        let mut bond_position = vec![0; self.bonds.len()];
+        for (id, bond) in self.bonds.iter().enumerate() {
+            let molecule = &mut components[component[bond.a]].0;
+            bond_position[id] = molecule.bonds.len();
+            let mut bond = *bond;
+            bond.a = atom_position[bond.a];
+            bond.b = atom_position[bond.b];
+            molecule.bonds.push(bond);
+        }
+        for (molecule, _) in components.iter_mut() {
+            for atom in molecule.atoms.iter_mut() {
+                atom.bonds.iter_mut().for_each(|b| *b = bond_position[*b]);
+            }
+        }
+        components
+    }
+
     fn get_empirical_formula_optimize(&self, empirical: &mut BTreeMap<Element, usize>) {
-        for a in self.atoms.iter() {
+        for a in self.atoms.iter().filter(|a| !a.wildcard) {
             *empirical.entry(*a.atom.get_element()).or_insert(0) += 1;
         }
     }
@@ -124,17 +427,40 @@ impl Molecule {
 impl BasicMolecule for Molecule {
     fn get_molecular_weight(&self) -> f32 {
         let mut weight = 0.0;
-        for atom in self.atoms.iter() {
-            weight += atom.atom.get_element().get_atomic_mass();
+        for atom in self.atoms.iter().filter(|a| !a.wildcard) {
+            weight += match atom.atom.get_mass_number() {
+                Some(_) => atom.atom.get_exact_mass() as f32,
+                None => atom.atom.get_element().get_atomic_mass()
+            };
         }
         weight
     }
+
+    fn get_monoisotopic_mass(&self) -> f64 {
+        self.atoms.iter().filter(|a| !a.wildcard).map(|a| monoisotopic_mass(*a.atom.get_element())).sum()
+    }
+
+    fn get_exact_mass(&self) -> f64 {
+        self.atoms.iter().filter(|a| !a.wildcard).map(|a| a.atom.get_exact_mass()).sum()
+    }
+
+    /// The labeled atoms count with the mass of their isotope
+    fn get_average_mass(&self) -> f64 {
+        self.atoms.iter().filter(|a| !a.wildcard).map(|a| match a.atom.get_mass_number() {
+            Some(_) => a.atom.get_exact_mass(),
+            None => average_mass(*a.atom.get_element())
+        }).sum()
+    }
+
+    fn get_charge(&self) -> isize {
+        self.atoms.iter().map(|a| *a.get_ion().get_charge() as isize).sum()
+    }
 }
 
 impl AdvancedFormula for Molecule {
     fn get_empirical_formula(&self) -> EmpiricalFormula {
         let mut empirical = BTreeMap::new();
-        for a in self.atoms.iter() {
+        for a in self.atoms.iter().filter(|a| !a.wildcard) {
             *empirical.entry(a.atom).or_insert(0) += 1;
         }
         let mut res = Vec::with_capacity(empirical.len());
@@ -152,22 +478,131 @@ pub struct Compound {
 }
 
 impl Compound {
-    pub fn from_smiles(smiles: &str) -> Result<Compound, ()> {
+    pub fn from_smiles(smiles: &str) -> Result<Compound, ParserError> {
         let mut molecules = Vec::new();
+        let mut offset = 0;
         for molecule in smiles.split('.') {
-            molecules.push(Molecule::from_smiles(molecule)?);
+            // Nothing before a `.`, as in `C..C`
+            if molecule.is_empty() {
+                let rest = &smiles.as_bytes()[offset..];
+                let reason = if rest.is_empty() { ParseErrorReason::UnexpectedEnd } else { ParseErrorReason::UnexpectedCharacter };
+                return Err(ParseError::new(smiles.as_bytes(), rest, reason).into());
+            }
+            match Molecule::from_smiles(molecule) {
+                Ok(m) => molecules.push(m),
+                // Report the position on the whole string
+                Err(ParserError::Parse(e)) => return Err(ParserError::Parse(e.shifted(offset))),
+                Err(e) => return Err(e)
+            }
+            offset += molecule.len() + 1;
         }
         Ok(Compound { molecules })
     }
 
+    pub fn to_smiles(&self) -> String {
+        let mut smiles = Vec::with_capacity(self.molecules.len());
+        for molecule in self.molecules.iter() {
+            smiles.push(molecule.to_smiles());
+        }
+        smiles.join(".")
+    }
+
+    pub fn to_canonical_smiles(&self) -> String {
+        let mut smiles = Vec::with_capacity(self.molecules.len());
+        for molecule in self.molecules.iter() {
+            smiles.push(molecule.to_canonical_smiles());
+        }
+        // The molecules can also come in any order
+        smiles.sort();
+        smiles.join(".")
+    }
+
+    /// Standard InChI of all the molecules, without the stereo and isotopic layers
+    pub fn to_inchi(&self) -> String {
+        inchi::write_inchi(self.molecules.iter())
+    }
+
+    pub fn to_inchi_key(&self) -> String {
+        InChI::key(&self.to_inchi()).unwrap()
+    }
+
     pub fn from_cml<R: Read>(reader: R) -> Result<Compound, ParserError> {
-        cml::parse(reader)
+        Ok(CmlFile::parse(reader)?.into_compound())
+    }
+
+    /// The compound as a CML document with a single molecule
+    pub fn to_cml(&self) -> String {
+        let mut out = Vec::new();
+        CmlFile::new(String::new(), self.clone()).write(&mut out).unwrap();
+        String::from_utf8(out).unwrap()
     }
 
     pub fn from_mol<R: Read>(reader: R) -> Result<Compound, ParserError> {
         Ok(MolFile::parse(reader)?.into_compound())
     }
 
+    /// The molecules of a standard InChI, see `InChI` for the layers that are read
+    pub fn from_inchi(inchi: &str) -> Result<Compound, ParserError> {
+        Ok(InChI::parse(inchi)?.into_compound())
+    }
+
+    /// The molecules of the first model of a PDB file, see `PdbFile` for the residue of each atom
+    pub fn from_pdb<R: Read>(reader: R) -> Result<Compound, ParserError> {
+        Ok(PdbFile::parse(reader)?.into_compound())
+    }
+
+    /// All the molecules on a single molfile, V2000 unless they need V3000
+    pub fn to_molfile(&self) -> String {
+        let mut out = Vec::new();
+        mol::write_molfile(&mut out, "", "", self).unwrap();
+        String::from_utf8(out).unwrap()
+    }
+
+    /// An SD file with a record for every molecule
+    pub fn to_sdf(&self) -> String {
+        let mut out = Vec::new();
+        for molecule in self.molecules.iter() {
+            mol::write_molfile(&mut out, "", "", &Compound { molecules: vec![molecule.clone()] }).unwrap();
+            out.extend_from_slice(b"$$$$\n");
+        }
+        String::from_utf8(out).unwrap()
+    }
+
+    pub fn perceive_aromaticity(&mut self, model: AromaticityModel) {
+        for molecule in self.molecules.iter_mut() {
+            molecule.perceive_aromaticity(model);
+        }
+    }
+
+    /// Kekulize all the molecules, nothing is changed if one of them fails
+    pub fn kekulize(&mut self) -> Result<(), KekulizationError> {
+        let mut molecules = self.molecules.clone();
+        for molecule in molecules.iter_mut() {
+            molecule.kekulize()?;
+        }
+        self.molecules = molecules;
+        Ok(())
+    }
+
+    /// Matches of the query on every molecule, with the index of the molecule. The molecules are
+    /// searched in parallel
+    pub fn substructure_matches(&self, query: &Molecule) -> Vec<(usize, Vec<Option<usize>>)> {
+        self.molecules.par_iter().enumerate().flat_map_iter(|(i, molecule)| {
+            molecule.substructure_matches(query).into_iter().map(move |m| (i, m))
+        }).collect()
+    }
+
+    pub fn has_substructure(&self, query: &Molecule) -> bool {
+        self.molecules.par_iter().any(|molecule| molecule.has_substructure(query))
+    }
+
+    /// Matches of the SMARTS pattern on every molecule, with the index of the molecule
+    pub fn smarts_matches(&self, smarts: &Smarts) -> Vec<(usize, Vec<usize>)> {
+        self.molecules.par_iter().enumerate().flat_map_iter(|(i, molecule)| {
+            smarts.matches(molecule).into_iter().map(move |m| (i, m))
+        }).collect()
+    }
+
     pub fn iter(&self) -> CompoundIterator {
         CompoundIterator { compound: &self, pos: 0 }
     }
@@ -189,6 +624,18 @@ impl Compound {
     }
 }
 
+impl From<Molecule> for Compound {
+    fn from(molecule: Molecule) -> Compound {
+        Compound { molecules: vec![molecule] }
+    }
+}
+
+impl From<Vec<Molecule>> for Compound {
+    fn from(molecules: Vec<Molecule>) -> Compound {
+        Compound { molecules }
+    }
+}
+
 impl BasicMolecule for Compound {
     fn get_molecular_weight(&self) -> f32 {
         let mut weight = 0.0;
@@ -197,6 +644,21 @@ impl BasicMolecule for Compound {
         }
         weight
     }
+    fn get_monoisotopic_mass(&self) -> f64 {
+        self.molecules.iter().map(|m| m.get_monoisotopic_mass()).sum()
+    }
+
+    fn get_exact_mass(&self) -> f64 {
+        self.molecules.iter().map(|m| m.get_exact_mass()).sum()
+    }
+
+    fn get_average_mass(&self) -> f64 {
+        self.molecules.iter().map(|m| m.get_average_mass()).sum()
+    }
+
+    fn get_charge(&self) -> isize {
+        self.molecules.iter().map(|m| m.get_charge()).sum()
+    }
 }
 
 impl AdvancedFormula for Compound {
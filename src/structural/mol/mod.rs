@@ -1,22 +1,73 @@
-use std::io::{BufReader, BufRead, Read};
-
-use fnv::FnvHashMap;
-use ptable::Element;
+use std::io::{self, BufReader, BufRead, Read, Write};
 
 use crate::Isotope;
 use crate::ra::Point;
-use super::{AtomAndBondI, Compound, Molecule, ParserError};
+use super::{rings, AtomAndBondI, AtomList, Bond, BondStereo, Chirality, Compound, Molecule, ParserError, Radical,
+    StructuralBond};
+use super::smiles::{is_odd_permutation, symmetry_classes};
 
 mod v2000;
+mod v3000;
+mod sdf;
+pub use self::sdf::SdfReader;
+
+/// An atom as it is read from the atom block
+#[derive(Debug, Clone)]
+pub(crate) struct AtomRecord {
+    pub point: Point,
+    pub isotope: Isotope,
+    /// `A`, `Q`, `L` and `*`
+    pub wildcard: bool,
+    pub atom_list: Option<AtomList>,
+    pub radical: Option<Radical>,
+    /// Stereo parity: 1 (odd), 2 (even) or 3 (either)
+    pub parity: u8,
+    /// Valence given on the file, the hydrogens that it needs are added
+    pub valence: Option<usize>,
+}
+
+impl AtomRecord {
+    pub fn new(point: Point, isotope: Isotope) -> AtomRecord {
+        AtomRecord { point, isotope, wildcard: false, atom_list: None, radical: None, parity: 0, valence: None }
+    }
+}
+
+/// Stereo of a bond as it is drawn
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub(crate) enum Wedge {
+    None,
+    /// Toward the viewer, starting on the first atom of the bond
+    Up,
+    Down,
+    /// Unknown configuration (the wavy bonds and the crossed double bonds)
+    Either,
+}
+
+#[derive(Debug, Clone)]
+pub(crate) struct BondRecord {
+    pub bond: Bond<StructuralBond>,
+    pub wedge: Wedge,
+}
 
 #[derive(Debug)]
 pub struct MolFile {
     name: String,
     comment: String,
-    compound: Compound
+    compound: Compound,
+    atom_indices: Vec<Vec<usize>>,
 }
 
 impl MolFile {
+    pub fn new(name: String, comment: String, compound: Compound) -> MolFile {
+        // The atoms are numbered in the order in which they are written
+        let mut offset = 0;
+        let atom_indices = compound.molecules.iter().map(|m| {
+            offset += m.atoms.len();
+            (offset - m.atoms.len()..offset).collect()
+        }).collect();
+        MolFile { name, comment, compound, atom_indices }
+    }
+
     pub fn parse<R: Read>(reader: R) -> Result<MolFile, ParserError> {
         let mut reader = BufReader::new(reader).lines();
         macro_rules! get_or_eof {
@@ -36,16 +87,16 @@ impl MolFile {
         if counts.len() < 39 {
             return Err(ParserError::Syntax);
         }
-        let mut atoms_count = v2000::get_int_value(&counts[0..3])?;
-        let mut bonds_count = v2000::get_int_value(&counts[3..6])?;
+        let atoms_count = v2000::get_int_value(&counts[0..3])?;
+        let bonds_count = v2000::get_int_value(&counts[3..6])?;
         let _atom_list_count = v2000::get_int_value(&counts[6..9])?;
-        let mut chiral_flag = v2000::get_int_value(&counts[9..12])?;  // 0 or 1
+        let chiral_flag = v2000::get_int_value(&counts[9..12])?;  // 0 or 1
         let _stext_entries_count = v2000::get_int_value(&counts[12..15])?;
         if chiral_flag > 1 {
             return Err(ParserError::Syntax);
         }
-        let table_format = &counts[33..39];
-        if table_format != "V2000" && table_format != "V2000" {
+        let table_format = counts[33..39].trim();
+        if table_format != "V2000" && table_format != "V3000" {
             return Err(ParserError::Syntax);
         }
         let mut atoms = Vec::new();
@@ -55,175 +106,32 @@ impl MolFile {
             bonds.reserve(bonds_count as usize);
             v2000::parse_atom_list(&mut reader, &mut atoms, atoms_count)?;
             v2000::parse_bond_list(&mut reader, &mut bonds, atoms_count as usize, bonds_count)?;
+            v2000::parse_properties(&mut reader, &mut atoms)?;
         }
         else {
-            #[derive(Debug)]
-            enum Block {
-                Global,
-                CTab,
-                Atom,
-                Bond,
-            }
-            let mut current_block = Block::Global;
-            loop {
-                let line = get_or_eof!();
-                if line.len() < 6 {
-                    return Err(ParserError::Syntax);
-                }
-                if !line.starts_with("M  ") {
-                    return Err(ParserError::Syntax);
-                }
-                if &line[3..6] == "END" {
-                    break;
-                }
-                if line.len() < 7 {
-                    return Err(ParserError::Syntax);
-                }
-                if &line[3..7] != "V30 " {
-                    return Err(ParserError::Syntax);
-                }
-                let mut line = (&line[7..]).split(' ');
-                macro_rules! get_value {
-                    () => {{
-                        match line.next() {
-                            Some(v) => v,
-                            None => {
-                                return Err(ParserError::Syntax);
-                            }
-                        }
-                    }};
-                }
-                let head = get_value!();
-                if head == "BEGIN" {
-                    let key = get_value!();
-                    match current_block {
-                        Block::Global => {
-                            if key == "CTAB" {
-                                current_block = Block::CTab;
-                            }
-                        },
-                        Block::CTab => {
-                            if key == "ATOM" {
-                                if atoms.len() != atoms_count as usize {
-                                    return Err(ParserError::Syntax);
-                                }
-                                current_block = Block::Atom;
-                            }
-                            else if key == "BOND" {
-                                current_block = Block::Bond;
-                            }
-                        }
-                        _ => {}
-                    }
-                }
-                else if head == "END" {
-                    let key = get_value!();
-                    match current_block {
-                        Block::CTab => {
-                            if key == "CTAB" {
-                                current_block = Block::Global;
-                            }
-                        },
-                        Block::Atom => {
-                            if key == "ATOM" {
-                                current_block = Block::CTab;
-                            }
-                        },
-                        Block::Bond => {
-                            if key == "BOND" {
-                                current_block = Block::CTab;
-                            }
-                        },
-                        _ => {}
-                    }
-                }
-                if let Block::CTab = current_block {
-                    if head == "COUNTS" {
-                        atoms_count = get_value!().parse()?;
-                        atoms.resize(atoms_count as usize, (Point::new(0.0, 0.0, 0.0), Element::Hydrogen));
-                        bonds_count = get_value!().parse()?;
-                        let _sgroups_count:u32 = get_value!().parse()?;
-                        let _3d_constrants_count:u32 = get_value!().parse()?;
-                        chiral_flag = get_value!().parse()?;
-                        if chiral_flag > 1 {
-                            return Err(ParserError::Syntax);
-                        }
-                    }
-                }
-                else if let Block::Atom = current_block {
-                    let mut index:usize = get_value!().parse()?;
-                    if index == 0 {
-                        return Err(ParserError::Syntax);
-                    }
-                    index -= 1;
-                    if index > atoms_count as usize {
-                        return Err(ParserError::Syntax);
-                    }
-                    let el = match Element::from_symbol(get_value!()) {
-                        Some(e) => e,
-                        None => {
-                            return Err(ParserError::Syntax);
-                        }
-                    };
-                    let x:f64 = get_value!().parse()?;
-                    let y:f64 = get_value!().parse()?;
-                    let z:f64 = get_value!().parse()?;
-                    atoms[index] = (Point::new(x, y, z), el);
-                }
-            }
+            v3000::parse_ctab(&mut reader, &mut atoms, &mut bonds)?;
         }
-        let mut processed = FnvHashMap::with_capacity_and_hasher(atoms_count as usize, Default::default());
-        let mut molecules:Vec<Molecule> = Vec::new();
-        for bond in bonds.iter() {
-            if let Some(i) = processed.get(&bond.a) {
-                let (mol_id, atom_id):(usize, usize) = *i;
-                if let None = processed.get(&bond.b) {
-                    let bond_id = molecules[mol_id].bonds.len();
-                    // Insert this bond on the molecule
-                    molecules[mol_id].bonds.push(*bond);
-                    // Update a atom bond list
-                    molecules[mol_id].atoms[atom_id].bonds.push(bond_id);
-                    // Get atom b id
-                    let b_atom_id = molecules[mol_id].atoms.len();
-                    // Register atom b
-                    molecules[mol_id].atoms.push(AtomAndBondI { atom: Isotope::from(atoms[bond.b].1), bonds: vec![bond_id] });
-                    // Register the atom b coords
-                    molecules[mol_id].coords.as_mut().unwrap().push(atoms[bond.b].0);
-                    processed.insert(bond.b, (mol_id, b_atom_id));
-                }
-            }
-            else if let Some(i) = processed.get(&bond.b) {
-                let (mol_id, atom_id):(usize, usize) = *i;
-                let bond_id = molecules[mol_id].bonds.len();
-                // Insert this bond on the molecule
-                molecules[mol_id].bonds.push(*bond);
-                // Update atom b bond list
-                molecules[mol_id].atoms[atom_id].bonds.push(bond_id);
-                // Get a atom id
-                let a_atom_id = molecules[mol_id].atoms.len();
-                // Register atom b
-                molecules[mol_id].atoms.push(AtomAndBondI { atom: Isotope::from(atoms[bond.a].1), bonds: vec![bond_id] });
-                // Register the atom a coords
-                molecules[mol_id].coords.as_mut().unwrap().push(atoms[bond.a].0);
-                processed.insert(bond.a, (mol_id, a_atom_id));
-            }
-            else {
-                let mol_id = molecules.len();
-                molecules.push(Molecule { atoms: vec![AtomAndBondI { 
-                    atom: Isotope::from(atoms[bond.a].1), bonds: vec![0] },
-                    AtomAndBondI { atom: Isotope::from(atoms[bond.b].1), bonds: vec![0] }], 
-                    bonds: vec![*bond], coords: Some(vec![atoms[bond.a].0, atoms[bond.b].0]) });
-                processed.insert(bond.a, (mol_id, 0));
-                processed.insert(bond.b, (mol_id, 1));
-            };
-        }
-        Ok(MolFile { comment, compound: Compound { molecules }, name })
+        let (molecules, atom_indices) = assemble(&atoms, &bonds);
+        Ok(MolFile { comment, compound: Compound { molecules }, name, atom_indices })
+    }
+
+    /// Write the molfile in the V2000 format, or in the V3000 one if the compound doesn't fit on
+    /// V2000 (more than 999 atoms or bonds, or atom lists of more than 16 elements). The coordinates are taken from the molecules;
+    /// the ones without them are laid out if they have cis or trans bonds, and written with zeros if not
+    pub fn write<W: Write>(&self, mut writer: W) -> io::Result<()> {
+        write_molfile(&mut writer, &self.name, &self.comment, &self.compound)
     }
 
     pub fn comment(&self) -> &str {
         &self.comment
     }
 
+    /// Index on the file (from zero) of every atom of each molecule, the hydrogens that were added
+    /// for the valence of an atom go after the atoms of the file
+    pub fn atom_indices(&self) -> &[Vec<usize>] {
+        &self.atom_indices
+    }
+
     pub fn compound(&self) -> &Compound {
         &self.compound
     }
@@ -248,3 +156,313 @@ impl MolFile {
         self.compound
     }
 }
+
+/// Header lines and connection table of a molfile
+pub(crate) fn write_molfile<W: Write>(writer: &mut W, name: &str, comment: &str, compound: &Compound) -> io::Result<()> {
+    let three_dimensional = compound.molecules.iter().any(|m| m.coords.as_ref().is_some_and(|c| c.iter().any(|p| p.z != 0.0)));
+    writeln!(writer, "{}", name)?;
+    // Initials, program, date (left empty) and dimensions
+    writeln!(writer, "  {:<8}{:10}{}", "chembas", "", if three_dimensional { "3D" } else { "2D" })?;
+    writeln!(writer, "{}", comment)?;
+    if v2000::can_write(compound) {
+        v2000::write_ctab(writer, compound)
+    }
+    else {
+        v3000::write_ctab(writer, compound)
+    }
+}
+
+/// Copies of the molecules as they are written. The aromatic bonds are kekulized, the molecules where it
+/// is not possible keep them. A molfile only gives the configuration of the double bonds through the
+/// coordinates, so the molecules without coordinates (as the ones from SMILES) that have cis or trans
+/// bonds are laid out; the coordinates of the others are kept. Each molecule comes with the bonds that
+/// must be written as either cis or trans, because their coordinates don't give their configuration
+pub(crate) fn prepared(compound: &Compound) -> Vec<(Molecule, Vec<bool>)> {
+    compound.molecules.iter().map(|molecule| {
+        let mut molecule = molecule.clone();
+        let _ = molecule.kekulize();
+        let in_ring = rings::ring_bonds(&molecule);
+        let drawn = |molecule: &Molecule| {
+            let mut copy = molecule.clone();
+            copy.bonds.iter_mut().for_each(|b| b.stereo = None);
+            read_double_bonds(&mut copy, &[]);
+            copy.bonds.into_iter().map(|b| b.stereo).collect::<Vec<_>>()
+        };
+        let stereo = molecule.bonds.iter().zip(in_ring.iter()).any(|(bond, ring)| bond.stereo.is_some() && !ring);
+        if molecule.coords.is_none() && stereo {
+            molecule.coords = Some(stereo_layout(&molecule));
+        }
+        let read = drawn(&molecule);
+        let either = molecule.bonds.iter().zip(read.iter()).zip(in_ring.iter())
+            .map(|((bond, read), ring)| bond.stereo != *read && (bond.stereo.is_none() || !ring)).collect();
+        (molecule, either)
+    }).collect()
+}
+
+/// Parity of a stereo centre as the molfiles give it: 1 (odd), 2 (even) or 0 if the atom has no
+/// chirality or it is not a centre of three or four neighbours. It is the opposite of the reading on `assemble`
+pub(crate) fn parity(molecule: &Molecule, atom: usize) -> u8 {
+    let bonds = &molecule.atoms[atom].bonds;
+    let chirality = match molecule.atoms[atom].chirality {
+        Some(chirality) if bonds.len() == 3 || bonds.len() == 4 => chirality,
+        _ => return 0
+    };
+    let mut sorted = bonds.clone();
+    sorted.sort_by_key(|b| {
+        let bond = molecule.bonds[*b];
+        if bond.a == atom { bond.b } else { bond.a }
+    });
+    let chirality = if is_odd_permutation(&sorted, bonds) { chirality.inverted() } else { chirality };
+    match chirality {
+        Chirality::Clockwise => 1,
+        Chirality::Anticlockwise => 2,
+    }
+}
+
+/// Length of the bonds of `stereo_layout`
+const LAYOUT_BOND_LENGTH: f64 = 1.5;
+
+/// Flat coordinates where the double bonds out of the rings have their configuration. Every atom has a
+/// column of its own, in depth first order, so no two atoms overlap, and the chains zigzag. The double
+/// bonds with stereo are followed after the other bonds of an atom, so the first neighbour of the
+/// second end is placed when the rest of the bond is, on the side that the stereo asks for
+fn stereo_layout(molecule: &Molecule) -> Vec<Point> {
+    let other = |bond: usize, atom: usize| {
+        let bond = molecule.bonds[bond];
+        if bond.a == atom { bond.b } else { bond.a }
+    };
+    // First neighbour of an end of a double bond that is not the other end
+    let reference = |atom: usize, double: usize| molecule.atoms[atom].bonds.iter().find(|b| **b != double).map(|b| other(*b, atom));
+    let stereo_bond = |bond: usize| molecule.bonds[bond].k == StructuralBond::Double && molecule.bonds[bond].stereo.is_some();
+    let (step, rise) = (LAYOUT_BOND_LENGTH * 0.75f64.sqrt(), LAYOUT_BOND_LENGTH / 2.0);
+    let mut points = vec![Point::new(0.0, 0.0, 0.0); molecule.atoms.len()];
+    let mut placed = vec![false; molecule.atoms.len()];
+    let mut column = 0.0;
+    for root in 0..molecule.atoms.len() {
+        if placed[root] {
+            continue;
+        }
+        // Atom, the bond from its parent and the direction of its zigzag
+        let mut stack = vec![(root, None, 1.0)];
+        while let Some((atom, from, sign)) = stack.pop() {
+            if placed[atom] {
+                continue;
+            }
+            let x = column * step;
+            column += 1.0;
+            let y = match from {
+                None => 0.0,
+                Some(from) => {
+                    let parent = other(from, atom);
+                    let mut y = points[parent].y + sign * rise;
+                    // The atom is the first neighbour of the second end of a double bond
+                    for double in molecule.atoms[parent].bonds.iter().copied().filter(|b| *b != from && stereo_bond(*b)) {
+                        let first = other(double, parent);
+                        let a = match reference(first, double) {
+                            Some(a) if placed[first] && placed[a] && reference(parent, double) == Some(atom) => a,
+                            _ => continue
+                        };
+                        let (start, end, point) = (points[first], points[parent], points[a]);
+                        let side = (end.x - start.x) * (point.y - start.y) - (end.y - start.y) * (point.x - start.x);
+                        if side == 0.0 {
+                            continue;
+                        }
+                        // Left of the bond, going from the first end to the second one, is above it if the bond goes right
+                        let left = (side > 0.0) == (molecule.bonds[double].stereo == Some(BondStereo::Cis));
+                        let line = start.y + (end.y - start.y) * (x - start.x) / (end.x - start.x);
+                        y = line + if left == (end.x > start.x) { rise } else { -rise };
+                        break;
+                    }
+                    y
+                }
+            };
+            points[atom] = Point::new(x, y, 0.0);
+            placed[atom] = true;
+            // The children are taken from the stack in the order of the bonds, the stereo double bonds last
+            let mut children: Vec<usize> = molecule.atoms[atom].bonds.iter().copied().filter(|b| !placed[other(*b, atom)]).collect();
+            children.sort_by_key(|b| stereo_bond(*b));
+            for bond in children.into_iter().rev() {
+                stack.push((other(bond, atom), Some(bond), -sign));
+            }
+        }
+        column += 1.0;
+    }
+    points
+}
+
+/// Element of an atom symbol, the query atoms `A`, `Q`, `L` (atom list) and `*` are wildcards
+pub(crate) fn parse_symbol(symbol: &str) -> Option<(ptable::Element, bool)> {
+    match symbol {
+        "A" | "Q" | "L" | "*" => Some((ptable::Element::Hydrogen, true)),
+        _ => ptable::Element::from_symbol(symbol).map(|e| (e, false))
+    }
+}
+
+/// Build the molecules of the file, the atoms keep their order and the ones without bonds are kept
+/// too. The hydrogens that the atoms with a valence need are added and the stereochemistry is read.
+/// It gives the index on the file of every atom of each molecule, the added hydrogens go after the
+/// atoms of the file
+fn assemble(atoms: &[AtomRecord], bonds: &[BondRecord]) -> (Vec<Molecule>, Vec<Vec<usize>>) {
+    let mut molecule = Molecule { atoms: Vec::with_capacity(atoms.len()), bonds: Vec::with_capacity(bonds.len()),
+        coords: Some(atoms.iter().map(|a| a.point).collect()) };
+    for record in atoms.iter() {
+        let mut atom = AtomAndBondI::new(record.isotope, Vec::new());
+        atom.wildcard = record.wildcard;
+        atom.atom_list = record.atom_list.clone();
+        atom.radical = record.radical;
+        molecule.atoms.push(atom);
+    }
+    for (id, record) in bonds.iter().enumerate() {
+        molecule.atoms[record.bond.a].bonds.push(id);
+        molecule.atoms[record.bond.b].bonds.push(id);
+        molecule.bonds.push(record.bond);
+    }
+    for (atom, record) in atoms.iter().enumerate() {
+        if let Some(valence) = record.valence {
+            let used: usize = molecule.atoms[atom].bonds.iter().map(|b| match molecule.bonds[*b].k {
+                StructuralBond::Aromatic => 1,
+                k => k as usize
+            }).sum();
+            for _ in used..valence {
+                let (h, bond_id) = (molecule.atoms.len(), molecule.bonds.len());
+                molecule.atoms.push(AtomAndBondI::new(Isotope::from(ptable::Element::Hydrogen), vec![bond_id]));
+                molecule.coords.as_mut().unwrap().push(record.point);
+                molecule.atoms[atom].bonds.push(bond_id);
+                molecule.bonds.push(Bond::new(atom, h, StructuralBond::Single));
+            }
+        }
+    }
+    for (atom, record) in atoms.iter().enumerate() {
+        if record.parity == 1 || record.parity == 2 {
+            // The parity counts the neighbours from the lowest index, looking with the highest one
+            // (or the hydrogen) away; an odd parity is clockwise
+            let mut sorted = molecule.atoms[atom].bonds.clone();
+            sorted.sort_by_key(|b| {
+                let bond = molecule.bonds[*b];
+                if bond.a == atom { bond.b } else { bond.a }
+            });
+            let chirality = if record.parity == 1 { Chirality::Clockwise } else { Chirality::Anticlockwise };
+            let odd = is_odd_permutation(&sorted, &molecule.atoms[atom].bonds);
+            molecule.atoms[atom].chirality = Some(if odd { chirality.inverted() } else { chirality });
+        }
+    }
+    let wedges: Vec<(usize, Wedge)> = bonds.iter().enumerate().map(|(id, record)| (id, record.wedge)).collect();
+    read_wedges(&mut molecule, &wedges);
+    read_double_bonds(&mut molecule, &wedges);
+    molecule.components().into_iter().unzip()
+}
+
+type Vector3 = [f64; 3];
+
+fn difference(a: &Point, b: &Point) -> Vector3 {
+    [a.x - b.x, a.y - b.y, a.z - b.z]
+}
+
+fn dot(a: &Vector3, b: &Vector3) -> f64 {
+    a[0] * b[0] + a[1] * b[1] + a[2] * b[2]
+}
+
+fn cross(a: &Vector3, b: &Vector3) -> Vector3 {
+    [a[1] * b[2] - a[2] * b[1], a[2] * b[0] - a[0] * b[2], a[0] * b[1] - a[1] * b[0]]
+}
+
+/// Chirality of the atoms where a wedge starts and that have no parity, the wedges lift their
+/// neighbour over the plane of the drawing (or put it below)
+fn read_wedges(molecule: &mut Molecule, wedges: &[(usize, Wedge)]) {
+    let coords = match molecule.coords {
+        Some(ref coords) => coords.clone(),
+        None => return
+    };
+    for id in 0..molecule.atoms.len() {
+        let atom = &molecule.atoms[id];
+        let starts_wedge = wedges.iter().any(|(b, w)| (*w == Wedge::Up || *w == Wedge::Down) && molecule.bonds[*b].a == id);
+        if atom.chirality.is_some() || !starts_wedge || atom.bonds.len() < 3 || atom.bonds.len() > 4 {
+            continue;
+        }
+        let mut vectors: Vec<Vector3> = atom.bonds.iter().map(|b| {
+            let bond = molecule.bonds[*b];
+            let pair = if bond.a == id { bond.b } else { bond.a };
+            let mut v = difference(&coords[pair], &coords[id]);
+            let length = (v[0] * v[0] + v[1] * v[1]).sqrt();
+            match wedges.iter().find(|(w, _)| *w == *b) {
+                Some((_, Wedge::Up)) if bond.a == id => v[2] += length,
+                Some((_, Wedge::Down)) if bond.a == id => v[2] -= length,
+                _ => {}
+            }
+            v
+        }).collect();
+        // The missing neighbour (a lone pair or a hydrogen without coordinates) is opposite to the others
+        let placed: Vec<usize> = (0..vectors.len()).filter(|i| dot(&vectors[*i], &vectors[*i]) > 1e-12).collect();
+        if placed.len() == 3 {
+            let mut opposite = [0.0; 3];
+            for i in placed.iter() {
+                for (o, v) in opposite.iter_mut().zip(vectors[*i].iter()) {
+                    *o -= v;
+                }
+            }
+            match (0..vectors.len()).find(|i| !placed.contains(i)) {
+                Some(missing) => vectors[missing] = opposite,
+                None => vectors.push(opposite)
+            }
+        }
+        else if placed.len() != vectors.len() {
+            continue;
+        }
+        let relative: Vec<Vector3> = vectors[1..].iter().map(|v| [v[0] - vectors[0][0], v[1] - vectors[0][1], v[2] - vectors[0][2]]).collect();
+        let volume = dot(&relative[0], &cross(&relative[1], &relative[2]));
+        if volume.abs() < 1e-6 {
+            continue;
+        }
+        // Seen from the first neighbour, the others turn clockwise if the volume is positive
+        molecule.atoms[id].chirality = Some(if volume > 0.0 { Chirality::Clockwise } else { Chirality::Anticlockwise });
+    }
+}
+
+/// Cis or trans configuration of the double bonds out of rings, from the coordinates. The ends with
+/// two neighbours of the same symmetry class are left out
+fn read_double_bonds(molecule: &mut Molecule, wedges: &[(usize, Wedge)]) {
+    let coords = match molecule.coords {
+        Some(ref coords) => coords.clone(),
+        None => return
+    };
+    let in_ring = rings::ring_bonds(molecule);
+    let classes = symmetry_classes(molecule);
+    for (id, ring_bond) in in_ring.into_iter().enumerate() {
+        let bond = molecule.bonds[id];
+        if bond.k != StructuralBond::Double || ring_bond || wedges.iter().any(|(b, w)| *b == id && *w == Wedge::Either) {
+            continue;
+        }
+        let mut sides = Vec::with_capacity(2);
+        for end in [bond.a, bond.b].iter() {
+            let others: Vec<usize> = molecule.atoms[*end].bonds.iter().filter(|b| **b != id).map(|b| {
+                let other = molecule.bonds[*b];
+                if other.a == *end { other.b } else { other.a }
+            }).collect();
+            // An end with two symmetric neighbours (as two hydrogens or two methyls) has no configuration
+            if others.is_empty() || others.len() > 2 || (others.len() == 2 && classes[others[0]] == classes[others[1]]) {
+                break;
+            }
+            sides.push((*end, others[0]));
+        }
+        if sides.len() != 2 {
+            continue;
+        }
+        // Take out of the vectors to the neighbours their part along the double bond
+        let axis = difference(&coords[bond.b], &coords[bond.a]);
+        let axis_length = dot(&axis, &axis);
+        if axis_length < 1e-12 {
+            continue;
+        }
+        let perpendicular = |end: usize, other: usize| {
+            let v = difference(&coords[other], &coords[end]);
+            let along = dot(&v, &axis) / axis_length;
+            [v[0] - along * axis[0], v[1] - along * axis[1], v[2] - along * axis[2]]
+        };
+        let (u, w) = (perpendicular(sides[0].0, sides[0].1), perpendicular(sides[1].0, sides[1].1));
+        let product = dot(&u, &w);
+        if product.abs() < 1e-6 * (dot(&u, &u) * dot(&w, &w)).sqrt() || dot(&u, &u) < 1e-12 || dot(&w, &w) < 1e-12 {
+            continue;
+        }
+        molecule.bonds[id].stereo = Some(if product > 0.0 { BondStereo::Cis } else { BondStereo::Trans });
+    }
+}
@@ -0,0 +1,82 @@
+use std::collections::BTreeMap;
+use std::io::{BufRead, BufReader, Lines, Read};
+
+use crate::structural::*;
+
+/// Reader of the records of an SD file, one at a time. Every record gives the compound and its
+/// data items, as `> <NAME>` followed by the lines of the value
+pub struct SdfReader<R: Read> {
+    lines: Lines<BufReader<R>>,
+    ended: bool,
+}
+
+impl<R: Read> SdfReader<R> {
+    pub fn new(reader: R) -> SdfReader<R> {
+        SdfReader { lines: BufReader::new(reader).lines(), ended: false }
+    }
+}
+
+impl<R: Read> Iterator for SdfReader<R> {
+    type Item = Result<(Compound, BTreeMap<String, String>), ParserError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.ended {
+            return None;
+        }
+        let mut record = Vec::new();
+        loop {
+            match self.lines.next() {
+                Some(Ok(line)) => {
+                    if line.starts_with("$$$$") {
+                        break;
+                    }
+                    record.push(line);
+                },
+                Some(Err(e)) => {
+                    self.ended = true;
+                    return Some(Err(e.into()));
+                },
+                None => {
+                    // The last record may have no `$$$$`
+                    self.ended = true;
+                    if record.iter().all(|l| l.trim().is_empty()) {
+                        return None;
+                    }
+                    break;
+                }
+            }
+        }
+        Some(parse_record(&record))
+    }
+}
+
+fn parse_record(record: &[String]) -> Result<(Compound, BTreeMap<String, String>), ParserError> {
+    let end = match record.iter().position(|l| l.starts_with("M  END")) {
+        Some(end) => end,
+        None => {
+            return Err(ParserError::UnexpectedEof);
+        }
+    };
+    let compound = Compound::from_mol(record[..=end].join("\n").as_bytes())?;
+    let mut fields = BTreeMap::new();
+    let mut lines = record[end + 1..].iter();
+    while let Some(line) = lines.next() {
+        if !line.starts_with('>') {
+            continue;
+        }
+        // The name goes between angle brackets, the header can have other things as the
+        // registry number
+        let name = match line.find('<') {
+            Some(start) => match line[start + 1..].find('>') {
+                Some(length) => line[start + 1..start + 1 + length].to_owned(),
+                None => {
+                    return Err(ParserError::Syntax);
+                }
+            },
+            None => String::new()
+        };
+        let value: Vec<&str> = lines.by_ref().take_while(|l| !l.trim().is_empty()).map(|l| l.as_str()).collect();
+        fields.insert(name, value.join("\n"));
+    }
+    Ok((compound, fields))
+}
@@ -1,9 +1,17 @@
+use std::num::NonZeroU8;
+
 use crate::ra::Point;
-use ptable::Element;
 use crate::structural::*;
-use std::io::{BufReader, Lines, Read};
+use super::{parity, prepared, parse_symbol, AtomRecord, BondRecord, Wedge};
+use std::io::{self, BufReader, Lines, Read, Write};
+
+/// Largest number of atoms or bonds that fits on the counts line
+const MAX_COUNT: usize = 999;
+
+/// Largest number of elements of an `M  ALS` line
+const MAX_ATOM_LIST_LENGTH: usize = 16;
 
-pub fn parse_atom_list<R: Read>(lines: &mut Lines<BufReader<R>>, atoms_buffer: &mut Vec<(Point, Element)>, 
+pub fn parse_atom_list<R: Read>(lines: &mut Lines<BufReader<R>>, atoms_buffer: &mut Vec<AtomRecord>, 
     atoms_count: u32) -> Result<(), ParserError> {
     for _ in 0..atoms_count {
         let line = match lines.next() {
@@ -18,18 +26,52 @@ pub fn parse_atom_list<R: Read>(lines: &mut Lines<BufReader<R>>, atoms_buffer: &
         let x = get_float_value(&line[0..10])?;
         let y = get_float_value(&line[10..20])?;
         let z = get_float_value(&line[20..30])?;
-        let el = match Element::from_symbol((&line[31..34]).trim()) {
+        let (el, wildcard) = match parse_symbol(line[31..34].trim()) {
             Some(e) => e,
             None => {
                 return Err(ParserError::Syntax);
             }
         };
-        atoms_buffer.push((Point::new(x, y, z), el));
+        let mut atom = AtomRecord::new(Point::new(x, y, z), Isotope::from(el));
+        atom.wildcard = wildcard;
+        // Difference with the mass of the periodic table, from -3 to 4
+        let mass_difference: i32 = match line[34..36].trim() {
+            "" => 0,
+            d => d.parse()?
+        };
+        if mass_difference != 0 {
+            if !(-3..=4).contains(&mass_difference) {
+                return Err(ParserError::Syntax);
+            }
+            set_mass(&mut atom, el.get_atomic_mass().round() as i32 + mass_difference)?;
+        }
+        match get_int_value(&line[36..39])? {
+            0 => {},
+            4 => atom.radical = Some(Radical::Doublet),
+            code @ 1..=7 => atom.isotope.get_ion_mut().set_charge(4 - code as i8),
+            _ => {
+                return Err(ParserError::Syntax);
+            }
+        }
+        atom.parity = get_int_value(&line[39..42])? as u8;
+        if atom.parity > 3 {
+            return Err(ParserError::Syntax);
+        }
+        // 15 is a valence of zero
+        atom.valence = match get_int_value(&line[48..51])? {
+            0 => None,
+            15 => Some(0),
+            v if v < 15 => Some(v as usize),
+            _ => {
+                return Err(ParserError::Syntax);
+            }
+        };
+        atoms_buffer.push(atom);
     }
     Ok(())
 }
 
-pub fn parse_bond_list<R: Read>(lines: &mut Lines<BufReader<R>>, bonds_buffer: &mut Vec<Bond<StructuralBond>>, 
+pub fn parse_bond_list<R: Read>(lines: &mut Lines<BufReader<R>>, bonds_buffer: &mut Vec<BondRecord>, 
     atoms_count: usize, bonds_count: u32) -> Result<(), ParserError> {
     for _ in 0..bonds_count {
         let line = match lines.next() {
@@ -38,18 +80,16 @@ pub fn parse_bond_list<R: Read>(lines: &mut Lines<BufReader<R>>, bonds_buffer: &
                 return Err(ParserError::UnexpectedEof);
             }
         };
-        let mut line = line.split(' ');
+        if line.len() < 9 {
+            return Err(ParserError::Syntax);
+        }
         macro_rules! get_value {
-            () => {{
-                match line.next() {
-                    Some(v) => v,
-                    None => {
-                        return Err(ParserError::Syntax);
-                    }
-                }
+            ($range:expr) => {{
+                // The fields have a fixed width and are padded with spaces
+                line[$range].trim()
             }};
         }
-        let mut a:usize = get_value!().parse()?;
+        let mut a:usize = get_value!(0..3).parse()?;
         if a == 0 {
             return Err(ParserError::Syntax);
         }
@@ -57,7 +97,7 @@ pub fn parse_bond_list<R: Read>(lines: &mut Lines<BufReader<R>>, bonds_buffer: &
         if a >= atoms_count {
             return Err(ParserError::Syntax);
         }
-        let mut b:usize = get_value!().parse()?;
+        let mut b:usize = get_value!(3..6).parse()?;
         if b == 0 {
             return Err(ParserError::Syntax);
         }
@@ -65,13 +105,161 @@ pub fn parse_bond_list<R: Read>(lines: &mut Lines<BufReader<R>>, bonds_buffer: &
         if b >= atoms_count {
             return Err(ParserError::Syntax);
         }
-        let k:usize = get_value!().parse()?;
-        if k == 0 || k > 3 {
+        let k = match get_value!(6..9) {
+            "1" => StructuralBond::Single,
+            "2" => StructuralBond::Double,
+            "3" => StructuralBond::Triple,
+            "4" => StructuralBond::Aromatic,
+            _ => {
+                return Err(ParserError::Syntax);
+            }
+        };
+        // The stereo of the single bonds are wedges, 3 is a double bond that can be cis or trans
+        let wedge = match line.get(9..12).map(|s| s.trim()) {
+            None | Some("") | Some("0") => Wedge::None,
+            Some("1") => Wedge::Up,
+            Some("6") => Wedge::Down,
+            Some("3") | Some("4") => Wedge::Either,
+            _ => {
+                return Err(ParserError::Syntax);
+            }
+        };
+        bonds_buffer.push(BondRecord { bond: Bond::new(a, b, k), wedge });
+    }
+    Ok(())
+}
+
+/// Set the isotope of an atom from its mass number
+fn set_mass(atom: &mut AtomRecord, mass: i32) -> Result<(), ParserError> {
+    let neutrons = mass - atom.isotope.get_element().get_atomic_number() as i32;
+    if neutrons < 0 || neutrons > u8::MAX as i32 {
+        return Err(ParserError::Syntax);
+    }
+    *atom.isotope.get_neutrons_count_mut() = NonZeroU8::new(neutrons as u8);
+    Ok(())
+}
+
+/// Atom and value pairs of the properties as `M  CHG  2   1  -1   4   1`
+fn parse_pairs(line: &str, atoms_count: usize) -> Result<Vec<(usize, i32)>, ParserError> {
+    let values: Vec<&str> = line[6..].split_whitespace().collect();
+    if values.is_empty() {
+        return Err(ParserError::Syntax);
+    }
+    let count: usize = values[0].parse()?;
+    if count > 8 || values.len() != 1 + 2 * count {
+        return Err(ParserError::Syntax);
+    }
+    let mut pairs = Vec::with_capacity(count);
+    for pair in values[1..].chunks(2) {
+        let atom: usize = pair[0].parse()?;
+        if atom == 0 || atom > atoms_count {
             return Err(ParserError::Syntax);
         }
-        bonds_buffer.push(Bond::new(a, b, unsafe { mem::transmute::<u8, StructuralBond>(k as u8) }));
+        pairs.push((atom - 1, pair[1].parse()?));
+    }
+    Ok(pairs)
+}
+
+/// Parse the properties block until `M  END`. The charges, radicals and isotopes on the atom block
+/// are left out if there is any `M  CHG`, `M  RAD` or `M  ISO` line
+pub fn parse_properties<R: Read>(lines: &mut Lines<BufReader<R>>, atoms: &mut [AtomRecord]) -> Result<(), ParserError> {
+    let mut charges_reset = false;
+    let mut isotopes_reset = false;
+    loop {
+        let line = match lines.next() {
+            Some(line) => line?,
+            None => {
+                return Err(ParserError::UnexpectedEof);
+            }
+        };
+        if line.starts_with("M  END") {
+            return Ok(());
+        }
+        // The atom aliases and the group abbreviations take a second line
+        if line.starts_with("A  ") || line.starts_with("G  ") {
+            lines.next().transpose()?;
+            continue;
+        }
+        if let Some(count) = line.strip_prefix("S  SKP") {
+            let count: usize = count.trim().parse()?;
+            for _ in 0..count {
+                lines.next().transpose()?;
+            }
+            continue;
+        }
+        match line.get(..6) {
+            Some("M  CHG") | Some("M  RAD") => {
+                if !charges_reset {
+                    for atom in atoms.iter_mut() {
+                        atom.isotope.get_ion_mut().set_charge(0);
+                        atom.radical = None;
+                    }
+                    charges_reset = true;
+                }
+                for (atom, value) in parse_pairs(&line, atoms.len())? {
+                    if line.starts_with("M  CHG") {
+                        if !(-15..=15).contains(&value) {
+                            return Err(ParserError::Syntax);
+                        }
+                        atoms[atom].isotope.get_ion_mut().set_charge(value as i8);
+                    }
+                    else {
+                        atoms[atom].radical = match value {
+                            0 => None,
+                            1 => Some(Radical::Singlet),
+                            2 => Some(Radical::Doublet),
+                            3 => Some(Radical::Triplet),
+                            _ => {
+                                return Err(ParserError::Syntax);
+                            }
+                        };
+                    }
+                }
+            },
+            Some("M  ISO") => {
+                if !isotopes_reset {
+                    for atom in atoms.iter_mut() {
+                        *atom.isotope.get_neutrons_count_mut() = None;
+                    }
+                    isotopes_reset = true;
+                }
+                for (atom, mass) in parse_pairs(&line, atoms.len())? {
+                    set_mass(&mut atoms[atom], mass)?;
+                }
+            },
+            Some("M  ALS") => {
+                // Fixed columns: atom, number of entries, exclusion flag and symbols of four characters
+                if line.len() < 16 {
+                    return Err(ParserError::Syntax);
+                }
+                let atom = get_int_value(&line[7..10])? as usize;
+                let count = get_int_value(&line[10..13])? as usize;
+                if atom == 0 || atom > atoms.len() || count == 0 || line.len() < 13 + 4 * count {
+                    return Err(ParserError::Syntax);
+                }
+                let excluded = match &line[14..15] {
+                    "T" => true,
+                    "F" => false,
+                    _ => {
+                        return Err(ParserError::Syntax);
+                    }
+                };
+                let mut elements = Vec::with_capacity(count);
+                for i in 0..count {
+                    let symbol = line[16 + 4 * i..line.len().min(20 + 4 * i)].trim();
+                    match Element::from_symbol(symbol) {
+                        Some(e) => elements.push(e),
+                        None => {
+                            return Err(ParserError::Syntax);
+                        }
+                    }
+                }
+                atoms[atom - 1].wildcard = true;
+                atoms[atom - 1].atom_list = Some(AtomList { elements, excluded });
+            },
+            _ => {}
+        }
     }
-    Ok(())
 }
 
 pub fn get_float_value(text: &str) -> Result<f64, ParserError> {
@@ -86,4 +274,106 @@ pub fn get_int_value(text: &str) -> Result<u32, ParserError> {
         return Ok(0);
     }
     Ok(text.trim().parse()?)
-}
\ No newline at end of file
+}
+
+/// Code of the charge on the atom block, the charges out of its range only go on `M  CHG`
+fn charge_code(charge: i8) -> u8 {
+    match charge {
+        3 => 1,
+        2 => 2,
+        1 => 3,
+        -1 => 5,
+        -2 => 6,
+        -3 => 7,
+        _ => 0
+    }
+}
+
+/// Write a property that has a value for some atoms, as `M  CHG` and `M  ISO`, with at most eight
+/// atoms per line
+fn write_property<W: Write>(writer: &mut W, name: &str, values: &[(usize, isize)]) -> io::Result<()> {
+    for chunk in values.chunks(8) {
+        write!(writer, "M  {}{:3}", name, chunk.len())?;
+        for (atom, value) in chunk {
+            write!(writer, " {:3} {:3}", atom + 1, value)?;
+        }
+        writeln!(writer)?;
+    }
+    Ok(())
+}
+
+/// Check that the compound fits on a V2000 connection table: at most 999 atoms and bonds, charges
+/// that `M  CHG` can hold and atom lists that `M  ALS` can hold
+pub fn can_write(compound: &Compound) -> bool {
+    compound.atoms_count() <= MAX_COUNT && compound.bonds_count() <= MAX_COUNT &&
+        compound.molecules.iter().all(|m| m.atoms.iter().all(|a| a.get_ion().get_charge().abs() <= 15 &&
+            a.atom_list.as_ref().is_none_or(|l| !a.wildcard || l.elements.len() <= MAX_ATOM_LIST_LENGTH)))
+}
+
+/// Write the counts line, the atom and bond blocks and the properties of all the molecules,
+/// ending with `M  END`. The aromatic bonds are kekulized, if it is not possible they are written
+/// with the bond type 4. The chirality goes on the parities of the atoms and the configuration of
+/// the double bonds on the coordinates, see `prepared`
+pub fn write_ctab<W: Write>(writer: &mut W, compound: &Compound) -> io::Result<()> {
+    let molecules = prepared(compound);
+    let atoms_count: usize = molecules.iter().map(|(m, _)| m.atoms.len()).sum();
+    let bonds_count: usize = molecules.iter().map(|(m, _)| m.bonds.len()).sum();
+    if atoms_count > MAX_COUNT || bonds_count > MAX_COUNT {
+        return Err(io::Error::new(io::ErrorKind::InvalidInput, "too many atoms or bonds for a V2000 molfile"));
+    }
+    let chiral = molecules.iter().any(|(m, _)| (0..m.atoms.len()).any(|a| parity(m, a) != 0));
+    writeln!(writer, "{:3}{:3}  0  0{:3}  0  0  0  0  0999 V2000", atoms_count, bonds_count, chiral as u8)?;
+    let mut charges = Vec::new();
+    let mut radicals = Vec::new();
+    let mut isotopes = Vec::new();
+    let mut atom_lists = Vec::new();
+    let mut offset = 0;
+    for (molecule, _) in molecules.iter() {
+        for (id, atom) in molecule.atoms.iter().enumerate() {
+            let point = molecule.coords.as_ref().and_then(|c| c.get(id)).copied().unwrap_or_else(|| Point::new(0.0, 0.0, 0.0));
+            let symbol = match (atom.wildcard, &atom.atom_list) {
+                (true, Some(_)) => "L",
+                (true, None) => "*",
+                _ => atom.get_element().get_symbol()
+            };
+            let charge = *atom.get_ion().get_charge();
+            writeln!(writer, "{:10.4}{:10.4}{:10.4} {:<3} 0{:3}{:3}  0  0  0  0  0  0  0  0  0",
+                point.x, point.y, point.z, symbol, charge_code(charge), parity(molecule, id))?;
+            if charge != 0 {
+                charges.push((offset + id, charge as isize));
+            }
+            if let Some(radical) = atom.radical {
+                radicals.push((offset + id, radical as isize + 1));
+            }
+            if let (true, Some(list)) = (atom.wildcard, &atom.atom_list) {
+                atom_lists.push((offset + id, list));
+            }
+            if let Some(neutrons) = atom.get_neutrons_count() {
+                isotopes.push((offset + id, (neutrons.get() as usize + atom.get_element().get_atomic_number()) as isize));
+            }
+        }
+        offset += molecule.atoms.len();
+    }
+    offset = 0;
+    for (molecule, either) in molecules.iter() {
+        for (bond, either) in molecule.bonds.iter().zip(either.iter()) {
+            let k = match bond.k {
+                StructuralBond::Aromatic => 4,
+                k => k as u8
+            };
+            writeln!(writer, "{:3}{:3}{:3}{:3}", offset + bond.a + 1, offset + bond.b + 1, k, if *either { 3 } else { 0 })?;
+        }
+        offset += molecule.atoms.len();
+    }
+    write_property(writer, "CHG", &charges)?;
+    write_property(writer, "RAD", &radicals)?;
+    write_property(writer, "ISO", &isotopes)?;
+    for (atom, list) in atom_lists {
+        write!(writer, "M  ALS {:3}{:3} {} ", atom + 1, list.elements.len(), if list.excluded { "T" } else { "F" })?;
+        for element in list.elements.iter() {
+            write!(writer, "{:<4}", element.get_symbol())?;
+        }
+        writeln!(writer)?;
+    }
+    writeln!(writer, "M  END")
+}
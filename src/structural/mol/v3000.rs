@@ -1,8 +1,344 @@
-use crate::structural::ParserError;
+use std::io::{self, BufReader, Lines, Read, Write};
+use std::num::NonZeroU8;
 
-pub fn parse_entry(line: String) -> Result<(), ParserError> {
-    if !line.starts_with("M") {
+use fnv::FnvHashMap;
+
+use crate::ra::Point;
+use crate::structural::*;
+use super::{parity, prepared, parse_symbol, AtomRecord, BondRecord, Wedge};
+
+/// Longest line of a molfile, the entries that don't fit go on several lines
+const MAX_LINE_LENGTH: usize = 80;
+
+/// Read the next line of the V3000 block without the `M  V30 ` prefix, the lines that end with `-`
+/// go on the next one. It gives `None` on `M  END`
+fn next_line<R: Read>(lines: &mut Lines<BufReader<R>>) -> Result<Option<String>, ParserError> {
+    let mut entry = String::new();
+    loop {
+        let line = match lines.next() {
+            Some(line) => line?,
+            None => {
+                return Err(ParserError::UnexpectedEof);
+            }
+        };
+        let line = line.trim_end();
+        if line.starts_with("M  END") {
+            return Ok(None);
+        }
+        if !line.starts_with("M  V30 ") {
+            return Err(ParserError::Syntax);
+        }
+        match line[7..].strip_suffix('-') {
+            Some(part) => entry.push_str(part),
+            None => {
+                entry.push_str(&line[7..]);
+                return Ok(Some(entry));
+            }
+        }
+    }
+}
+
+/// Split an entry by the spaces that are out of quotes and parentheses, as in `ATOMS=(2 1 3)`
+fn tokens(entry: &str) -> Vec<&str> {
+    let mut tokens = Vec::new();
+    let mut start = None;
+    let mut depth = 0;
+    let mut quoted = false;
+    for (i, c) in entry.char_indices() {
+        match c {
+            '"' => quoted = !quoted,
+            '(' if !quoted => depth += 1,
+            ')' if !quoted => depth -= 1,
+            ' ' if !quoted && depth == 0 => {
+                if let Some(s) = start.take() {
+                    tokens.push(&entry[s..i]);
+                }
+                continue;
+            },
+            _ => {}
+        }
+        if start.is_none() {
+            start = Some(i);
+        }
+    }
+    if let Some(s) = start {
+        tokens.push(&entry[s..]);
+    }
+    tokens
+}
+
+fn parse_atom(tokens: &[&str]) -> Result<(usize, AtomRecord), ParserError> {
+    if tokens.len() < 6 {
+        return Err(ParserError::Syntax);
+    }
+    let index = tokens[0].parse()?;
+    // The atom lists are written as `[C,N,O]` or `NOT[C,N,O]`
+    let symbol = tokens[1].trim_matches('"');
+    let excluded = symbol.starts_with("NOT[");
+    let list = symbol.trim_start_matches("NOT").strip_prefix('[').and_then(|l| l.strip_suffix(']'));
+    let (element, wildcard) = match list {
+        Some(_) => (Element::Hydrogen, true),
+        None => match parse_symbol(symbol) {
+            Some(e) => e,
+            None => {
+                return Err(ParserError::Syntax);
+            }
+        }
+    };
+    let point = Point::new(tokens[2].parse()?, tokens[3].parse()?, tokens[4].parse()?);
+    let mut atom = AtomRecord::new(point, Isotope::from(element));
+    atom.wildcard = wildcard;
+    if let Some(list) = list {
+        let mut elements = Vec::new();
+        for symbol in list.split(',') {
+            match Element::from_symbol(symbol) {
+                Some(e) => elements.push(e),
+                None => {
+                    return Err(ParserError::Syntax);
+                }
+            }
+        }
+        atom.atom_list = Some(AtomList { elements, excluded });
+    }
+    // tokens[5] is the atom-atom mapping
+    for property in tokens[6..].iter() {
+        let (key, value) = match property.find('=') {
+            Some(p) => (&property[..p], &property[p + 1..]),
+            None => {
+                return Err(ParserError::Syntax);
+            }
+        };
+        match key {
+            "CHG" => atom.isotope.get_ion_mut().set_charge(value.parse()?),
+            "MASS" => {
+                let mass: usize = value.parse()?;
+                let number = element.get_atomic_number();
+                if mass < number || mass - number > u8::MAX as usize {
+                    return Err(ParserError::Syntax);
+                }
+                *atom.isotope.get_neutrons_count_mut() = NonZeroU8::new((mass - number) as u8);
+            },
+            "RAD" => {
+                atom.radical = match value {
+                    "0" => None,
+                    "1" => Some(Radical::Singlet),
+                    "2" => Some(Radical::Doublet),
+                    "3" => Some(Radical::Triplet),
+                    _ => {
+                        return Err(ParserError::Syntax);
+                    }
+                };
+            },
+            "CFG" => {
+                atom.parity = value.parse()?;
+                if atom.parity > 3 {
+                    return Err(ParserError::Syntax);
+                }
+            },
+            "VAL" => {
+                let valence: isize = value.parse()?;
+                // -1 is a valence of zero, 0 is the default valence
+                atom.valence = match valence {
+                    -1 => Some(0),
+                    0 => None,
+                    v if v > 0 => Some(v as usize),
+                    _ => {
+                        return Err(ParserError::Syntax);
+                    }
+                };
+            },
+            _ => {}
+        }
+    }
+    Ok((index, atom))
+}
+
+fn parse_bond(tokens: &[&str], atoms: &FnvHashMap<usize, usize>) -> Result<BondRecord, ParserError> {
+    if tokens.len() < 4 {
         return Err(ParserError::Syntax);
     }
-    Ok(())
-}
\ No newline at end of file
+    let k = match tokens[1] {
+        "1" | "9" => StructuralBond::Single, // 9 is a coordination bond
+        "2" => StructuralBond::Double,
+        "3" => StructuralBond::Triple,
+        "4" => StructuralBond::Aromatic,
+        _ => {
+            return Err(ParserError::Syntax);
+        }
+    };
+    let mut ends = [0; 2];
+    for (end, token) in ends.iter_mut().zip(tokens[2..4].iter()) {
+        let index: usize = token.parse()?;
+        *end = match atoms.get(&index) {
+            Some(position) => *position,
+            None => {
+                return Err(ParserError::Syntax);
+            }
+        };
+    }
+    let mut wedge = Wedge::None;
+    for property in tokens[4..].iter() {
+        if let Some(value) = property.strip_prefix("CFG=") {
+            wedge = match value {
+                "0" => Wedge::None,
+                "1" => Wedge::Up,
+                "2" => Wedge::Either,
+                "3" => Wedge::Down,
+                _ => {
+                    return Err(ParserError::Syntax);
+                }
+            };
+        }
+    }
+    Ok(BondRecord { bond: Bond::new(ends[0], ends[1], k), wedge })
+}
+
+/// Parse the connection table of a V3000 molfile, until `M  END`
+pub fn parse_ctab<R: Read>(lines: &mut Lines<BufReader<R>>, atoms: &mut Vec<AtomRecord>,
+    bonds: &mut Vec<BondRecord>) -> Result<(), ParserError> {
+    #[derive(Debug, PartialEq)]
+    enum Block {
+        Global,
+        CTab,
+        Atom,
+        Bond,
+        Other, // Blocks that are skipped, as the Sgroups and the collections
+    }
+    let mut current_block = Block::Global;
+    let mut counts = None;
+    let mut indexes = FnvHashMap::default(); // Index of the atoms on the file and position on `atoms`
+    while let Some(entry) = next_line(lines)? {
+        let tokens = tokens(&entry);
+        if tokens.is_empty() {
+            continue;
+        }
+        match (tokens[0], tokens.get(1).copied()) {
+            ("BEGIN", Some(key)) => {
+                current_block = match (&current_block, key) {
+                    (Block::Global, "CTAB") => Block::CTab,
+                    (Block::CTab, "ATOM") => Block::Atom,
+                    (Block::CTab, "BOND") => Block::Bond,
+                    _ => Block::Other
+                };
+                continue;
+            },
+            ("END", Some(key)) => {
+                current_block = if key == "CTAB" { Block::Global } else { Block::CTab };
+                continue;
+            },
+            _ => {}
+        }
+        match current_block {
+            Block::CTab if tokens[0] == "COUNTS" => {
+                if tokens.len() < 6 {
+                    return Err(ParserError::Syntax);
+                }
+                let atoms_count: usize = tokens[1].parse()?;
+                let bonds_count: usize = tokens[2].parse()?;
+                let chiral_flag: u32 = tokens[5].parse()?;
+                if chiral_flag > 1 {
+                    return Err(ParserError::Syntax);
+                }
+                atoms.reserve(atoms_count);
+                bonds.reserve(bonds_count);
+                counts = Some((atoms_count, bonds_count));
+            },
+            Block::Atom => {
+                let (index, atom) = parse_atom(&tokens)?;
+                if indexes.insert(index, atoms.len()).is_some() {
+                    return Err(ParserError::Syntax);
+                }
+                atoms.push(atom);
+            },
+            Block::Bond => {
+                bonds.push(parse_bond(&tokens, &indexes)?);
+            },
+            _ => {}
+        }
+    }
+    match counts {
+        Some((atoms_count, bonds_count)) if atoms_count == atoms.len() && bonds_count == bonds.len() => Ok(()),
+        _ => Err(ParserError::Syntax)
+    }
+}
+
+/// Write an entry of the V3000 block, split on lines that end with `-` if it is too long
+fn write_entry<W: Write>(writer: &mut W, entry: &str) -> io::Result<()> {
+    // Room for the prefix and the `-`
+    let width = MAX_LINE_LENGTH - 8;
+    let mut rest = entry;
+    while rest.len() > width + 1 {
+        writeln!(writer, "M  V30 {}-", &rest[..width])?;
+        rest = &rest[width..];
+    }
+    writeln!(writer, "M  V30 {}", rest)
+}
+
+/// Write the counts line and the connection table of all the molecules, ending with `M  END`.
+/// The aromatic bonds are kekulized, if it is not possible they are written with the bond type 4.
+/// The chirality goes on the `CFG` of the atoms and the configuration of the double bonds on the
+/// coordinates, see `prepared`
+pub fn write_ctab<W: Write>(writer: &mut W, compound: &Compound) -> io::Result<()> {
+    let molecules = prepared(compound);
+    let atoms_count: usize = molecules.iter().map(|(m, _)| m.atoms.len()).sum();
+    let bonds_count: usize = molecules.iter().map(|(m, _)| m.bonds.len()).sum();
+    let chiral = molecules.iter().any(|(m, _)| (0..m.atoms.len()).any(|a| parity(m, a) != 0));
+    writeln!(writer, "  0  0  0     0  0            999 V3000")?;
+    write_entry(writer, "BEGIN CTAB")?;
+    write_entry(writer, &format!("COUNTS {} {} 0 0 {}", atoms_count, bonds_count, chiral as u8))?;
+    write_entry(writer, "BEGIN ATOM")?;
+    let mut offset = 0;
+    for (molecule, _) in molecules.iter() {
+        for (id, atom) in molecule.atoms.iter().enumerate() {
+            let point = molecule.coords.as_ref().and_then(|c| c.get(id)).copied().unwrap_or_else(|| Point::new(0.0, 0.0, 0.0));
+            let symbol = match (atom.wildcard, &atom.atom_list) {
+                (true, Some(list)) => {
+                    let symbols: Vec<&str> = list.elements.iter().map(|e| e.get_symbol()).collect();
+                    format!("{}[{}]", if list.excluded { "NOT" } else { "" }, symbols.join(","))
+                },
+                (true, None) => "*".to_owned(),
+                _ => atom.get_element().get_symbol().to_owned()
+            };
+            let mut entry = format!("{} {} {:.4} {:.4} {:.4} 0", offset + id + 1, symbol, point.x, point.y, point.z);
+            let charge = *atom.get_ion().get_charge();
+            if charge != 0 {
+                entry.push_str(&format!(" CHG={}", charge));
+            }
+            if let Some(radical) = atom.radical {
+                entry.push_str(&format!(" RAD={}", radical as u8 + 1));
+            }
+            let parity = parity(molecule, id);
+            if parity != 0 {
+                entry.push_str(&format!(" CFG={}", parity));
+            }
+            if let Some(neutrons) = atom.get_neutrons_count() {
+                entry.push_str(&format!(" MASS={}", neutrons.get() as usize + atom.get_element().get_atomic_number()));
+            }
+            write_entry(writer, &entry)?;
+        }
+        offset += molecule.atoms.len();
+    }
+    write_entry(writer, "END ATOM")?;
+    if bonds_count > 0 {
+        write_entry(writer, "BEGIN BOND")?;
+        let (mut offset, mut bond_id) = (0, 0);
+        for (molecule, either) in molecules.iter() {
+            for (bond, either) in molecule.bonds.iter().zip(either.iter()) {
+                let k = match bond.k {
+                    StructuralBond::Aromatic => 4,
+                    k => k as u8
+                };
+                bond_id += 1;
+                let mut entry = format!("{} {} {} {}", bond_id, k, offset + bond.a + 1, offset + bond.b + 1);
+                if *either {
+                    entry.push_str(" CFG=2");
+                }
+                write_entry(writer, &entry)?;
+            }
+            offset += molecule.atoms.len();
+        }
+        write_entry(writer, "END BOND")?;
+    }
+    write_entry(writer, "END CTAB")?;
+    writeln!(writer, "M  END")
+}
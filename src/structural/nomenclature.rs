@@ -96,6 +96,10 @@ impl super::Molecule {
     }
 
     pub fn get_name(&self) -> Option<String> {
+        if !self.ring_systems().is_empty() {
+            // Only the chains can be named
+            return None;
+        }
         let mut name = String::new();
         for (id, atom) in self.atoms.iter().enumerate() {
             if *atom.get_element() == Element::Carbon {
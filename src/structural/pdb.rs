@@ -0,0 +1,374 @@
+use std::io::{BufRead, BufReader, Read};
+use std::ops::Range;
+
+use fnv::FnvHashMap;
+use ptable::{Element, GroupBlock};
+use super::{connectivity, AtomAndBondI, Compound, Molecule, ParserError};
+use crate::*;
+
+/// Residue names of the water molecules, that are not taken as ligands
+const WATER_NAMES: [&str; 4] = ["HOH", "WAT", "DOD", "H2O"];
+
+/// Where an atom comes from in a PDB or mmCIF file
+#[derive(Debug, Clone, Eq, PartialEq, Hash)]
+pub struct AtomSite {
+    /// Serial number of the atom on the file
+    pub serial: usize,
+    /// Atom name, as `CA`
+    pub name: String,
+    pub residue_name: String,
+    pub chain: String,
+    pub residue_number: i32,
+    pub insertion_code: Option<char>,
+    /// `HETATM` record
+    pub hetero: bool,
+}
+
+/// An atom as it is read from the file
+struct AtomRecord {
+    site: AtomSite,
+    element: Element,
+    charge: i8,
+    point: Point,
+}
+
+/// The first model of a PDB (`ATOM`, `HETATM` and `CONECT` records) or mmCIF (`_atom_site`) file.
+/// The bonds come from the `CONECT` records, the atoms without them are bonded from their distances
+#[derive(Debug)]
+pub struct PdbFile {
+    compound: Compound,
+    sites: Vec<Vec<AtomSite>>,
+}
+
+/// Text of a fixed width field without the spaces around, empty if the line is shorter
+fn field(line: &str, range: Range<usize>) -> &str {
+    let end = range.end.min(line.len());
+    line.get(range.start.min(end)..end).unwrap_or("").trim()
+}
+
+/// Element of an atom without the element field, from the columns of its name: the one letter
+/// elements go on the second column, as ` CA ` (alpha carbon) against `CA  ` (calcium)
+fn element_from_name(name: &str) -> Option<Element> {
+    let mut chars = name.chars();
+    let first = chars.next()?;
+    let second = chars.next().filter(|c| c.is_ascii_alphabetic());
+    match second {
+        Some(second) if first == ' ' || first.is_ascii_digit() => parse_element(&second.to_string()),
+        Some(second) => parse_element(&format!("{}{}", first, second)).or_else(|| parse_element(&first.to_string())),
+        None => parse_element(&first.to_string())
+    }
+}
+
+/// Element from a symbol in capital letters, as `FE`
+fn parse_element(symbol: &str) -> Option<Element> {
+    let mut chars = symbol.chars();
+    let first = chars.next()?;
+    let symbol: String = Some(first.to_ascii_uppercase()).into_iter().chain(chars.map(|c| c.to_ascii_lowercase())).collect();
+    Element::from_symbol(&symbol)
+}
+
+/// Number of a hybrid-36 field, as the serials of the files with more than 99999 atoms: decimal
+/// while it fits in the width, then `A0000` to `ZZZZZ` and `a0000` to `zzzzz` in base 36
+fn parse_hybrid36(text: &str, width: u32) -> Result<usize, ParserError> {
+    let upper = match text.chars().next() {
+        Some(c) if c.is_ascii_alphabetic() => c.is_ascii_uppercase(),
+        _ => return Ok(text.parse()?)
+    };
+    if text.len() != width as usize || !text.chars().all(|c| c.is_ascii_digit() || (c.is_ascii_alphabetic() && c.is_ascii_uppercase() == upper)) {
+        return Err(ParserError::Syntax);
+    }
+    let block = 36usize.pow(width - 1);
+    let value = usize::from_str_radix(text, 36)? - 10 * block + 10usize.pow(width);
+    Ok(if upper { value } else { value + 26 * block })
+}
+
+/// Metals that are bonded to the protein in the files, but are ions of their own
+fn is_metal(element: Element) -> bool {
+    matches!(element.get_group(), GroupBlock::AlkaliMetal | GroupBlock::AlkalineEarthMetal | GroupBlock::Lanthanide |
+        GroupBlock::Actinide | GroupBlock::TransitionMetal | GroupBlock::PostTransitionMetal)
+}
+
+/// Charge as `2+` or `1-`
+fn parse_charge(text: &str) -> Result<i8, ParserError> {
+    if text.is_empty() {
+        return Ok(0);
+    }
+    let (digits, sign) = if text.ends_with('+') || text.ends_with('-') {
+        (&text[..text.len() - 1], if text.ends_with('-') { -1 } else { 1 })
+    }
+    else {
+        (text, 1)
+    };
+    let value: i8 = if digits.is_empty() { 1 } else { digits.parse()? };
+    Ok(sign * value)
+}
+
+impl PdbFile {
+    pub fn parse<R: Read>(reader: R) -> Result<PdbFile, ParserError> {
+        let mut records = Vec::new();
+        let mut connections = Vec::new();
+        for line in BufReader::new(reader).lines() {
+            let line = line?;
+            let record = field(&line, 0..6);
+            match record {
+                "ATOM" | "HETATM" => {
+                    // Only the first alternate location
+                    let alternate = field(&line, 16..17);
+                    if !alternate.is_empty() && alternate != "A" && alternate != "1" {
+                        continue;
+                    }
+                    let name = field(&line, 12..16).to_owned();
+                    let element = match field(&line, 76..78) {
+                        "" => element_from_name(line.get(12..16).unwrap_or("")),
+                        symbol => parse_element(symbol)
+                    };
+                    let element = match element {
+                        Some(e) => e,
+                        None => {
+                            return Err(ParserError::Syntax);
+                        }
+                    };
+                    let site = AtomSite {
+                        serial: parse_hybrid36(field(&line, 6..11), 5)?,
+                        name,
+                        residue_name: field(&line, 17..20).to_owned(),
+                        chain: field(&line, 21..22).to_owned(),
+                        residue_number: match field(&line, 22..26) {
+                            "" => 0,
+                            number if number.starts_with('-') => number.parse()?,
+                            number => parse_hybrid36(number, 4)? as i32
+                        },
+                        insertion_code: field(&line, 26..27).chars().next(),
+                        hetero: record == "HETATM",
+                    };
+                    let point = Point::new(field(&line, 30..38).parse()?, field(&line, 38..46).parse()?,
+                        field(&line, 46..54).parse()?);
+                    records.push(AtomRecord { site, element, charge: parse_charge(field(&line, 78..80))?, point });
+                },
+                "CONECT" => {
+                    let atom = parse_hybrid36(field(&line, 6..11), 5)?;
+                    for start in (11..31).step_by(5) {
+                        match field(&line, start..start + 5) {
+                            "" => {},
+                            other => connections.push((atom, parse_hybrid36(other, 5)?))
+                        }
+                    }
+                },
+                "ENDMDL" | "END" => break,
+                _ => {}
+            }
+        }
+        assemble(records, &connections)
+    }
+
+    /// Read the `_atom_site` loop of an mmCIF file, the `auth_` fields are preferred to the
+    /// `label_` ones
+    pub fn parse_mmcif<R: Read>(reader: R) -> Result<PdbFile, ParserError> {
+        #[derive(PartialEq)]
+        enum State {
+            Tags,
+            Values,
+            None
+        }
+        let mut tags: Vec<String> = Vec::new();
+        let mut values: Vec<String> = Vec::new();
+        let mut state = State::None;
+        for line in BufReader::new(reader).lines() {
+            let line = line?;
+            let trimmed = line.trim();
+            if state == State::Values {
+                if trimmed.starts_with('_') || trimmed.starts_with('#') || trimmed.starts_with("data_") || trimmed == "loop_" {
+                    break;
+                }
+                values.extend(cif_tokens(trimmed));
+            }
+            else if trimmed == "loop_" {
+                state = State::Tags;
+                tags.clear();
+            }
+            else if state == State::Tags {
+                if trimmed.starts_with('_') {
+                    tags.push(trimmed.split_whitespace().next().unwrap_or("").to_owned());
+                }
+                else if tags.first().is_some_and(|t| t.starts_with("_atom_site.")) {
+                    state = State::Values;
+                    values.extend(cif_tokens(trimmed));
+                }
+                else {
+                    state = State::None;
+                }
+            }
+        }
+        if !tags.first().is_some_and(|t| t.starts_with("_atom_site.")) || !values.len().is_multiple_of(tags.len()) {
+            return Err(ParserError::Syntax);
+        }
+        let column = |names: &[&str]| names.iter().find_map(|n| tags.iter().position(|t| t == &format!("_atom_site.{}", n)));
+        let x = column(&["Cartn_x"]);
+        let y = column(&["Cartn_y"]);
+        let z = column(&["Cartn_z"]);
+        let symbol = column(&["type_symbol"]);
+        let (x, y, z, symbol) = match (x, y, z, symbol) {
+            (Some(x), Some(y), Some(z), Some(symbol)) => (x, y, z, symbol),
+            _ => {
+                return Err(ParserError::Syntax);
+            }
+        };
+        let group = column(&["group_PDB"]);
+        let serial = column(&["id"]);
+        let name = column(&["auth_atom_id", "label_atom_id"]);
+        let alternate = column(&["label_alt_id"]);
+        let residue_name = column(&["auth_comp_id", "label_comp_id"]);
+        let chain = column(&["auth_asym_id", "label_asym_id"]);
+        let residue_number = column(&["auth_seq_id", "label_seq_id"]);
+        let insertion_code = column(&["pdbx_PDB_ins_code"]);
+        let charge = column(&["pdbx_formal_charge"]);
+        let model = column(&["pdbx_PDB_model_num"]);
+        let mut records = Vec::new();
+        let mut first_model = None;
+        for row in values.chunks(tags.len()) {
+            // `?` and `.` are values that are missing
+            let get = |c: Option<usize>| c.map(|c| row[c].as_str()).filter(|v| *v != "?" && *v != ".");
+            if let Some(model) = get(model) {
+                if *first_model.get_or_insert_with(|| model.to_owned()) != model {
+                    break;
+                }
+            }
+            if get(alternate).is_some_and(|a| a != "A" && a != "1") {
+                continue;
+            }
+            let element = match get(Some(symbol)).and_then(parse_element) {
+                Some(e) => e,
+                None => {
+                    return Err(ParserError::Syntax);
+                }
+            };
+            let site = AtomSite {
+                serial: match get(serial) {
+                    Some(serial) => serial.parse()?,
+                    None => records.len() + 1
+                },
+                name: get(name).unwrap_or("").trim_matches('"').to_owned(),
+                residue_name: get(residue_name).unwrap_or("").to_owned(),
+                chain: get(chain).unwrap_or("").to_owned(),
+                residue_number: match get(residue_number) {
+                    Some(number) => number.parse()?,
+                    None => 0
+                },
+                insertion_code: get(insertion_code).and_then(|c| c.chars().next()),
+                hetero: get(group) == Some("HETATM"),
+            };
+            let point = Point::new(row[x].parse()?, row[y].parse()?, row[z].parse()?);
+            let charge = match get(charge) {
+                Some(charge) => charge.parse()?,
+                None => 0
+            };
+            records.push(AtomRecord { site, element, charge, point });
+        }
+        assemble(records, &[])
+    }
+
+    pub fn compound(&self) -> &Compound {
+        &self.compound
+    }
+
+    pub fn into_compound(self) -> Compound {
+        self.compound
+    }
+
+    /// Residue information of every atom of each molecule
+    pub fn sites(&self) -> &[Vec<AtomSite>] {
+        &self.sites
+    }
+
+    /// Only the molecules made of `HETATM` records that are not water, as the ligands and the ions
+    pub fn ligands(&self) -> PdbFile {
+        let mut molecules = Vec::new();
+        let mut sites = Vec::new();
+        for (molecule, molecule_sites) in self.compound.molecules.iter().zip(self.sites.iter()) {
+            if molecule_sites.iter().all(|s| s.hetero && !WATER_NAMES.contains(&s.residue_name.as_str())) {
+                molecules.push(molecule.clone());
+                sites.push(molecule_sites.clone());
+            }
+        }
+        PdbFile { compound: Compound { molecules }, sites }
+    }
+}
+
+/// Split a line of a CIF file into its values, that can be quoted
+fn cif_tokens(line: &str) -> Vec<String> {
+    let mut tokens = Vec::new();
+    let mut chars = line.chars().peekable();
+    while let Some(c) = chars.next() {
+        if c.is_whitespace() {
+            continue;
+        }
+        let mut token = String::new();
+        if c == '\'' || c == '"' {
+            // The quote only closes if a space or the end of the line follows
+            while let Some(n) = chars.next() {
+                if n == c && chars.peek().is_none_or(|p| p.is_whitespace()) {
+                    break;
+                }
+                token.push(n);
+            }
+        }
+        else {
+            token.push(c);
+            while let Some(n) = chars.peek() {
+                if n.is_whitespace() {
+                    break;
+                }
+                token.push(*n);
+                chars.next();
+            }
+        }
+        tokens.push(token);
+    }
+    tokens
+}
+
+fn assemble(records: Vec<AtomRecord>, connections: &[(usize, usize)]) -> Result<PdbFile, ParserError> {
+    let mut molecule = Molecule { atoms: Vec::with_capacity(records.len()), bonds: Vec::new(),
+        coords: Some(records.iter().map(|r| r.point).collect()) };
+    let mut index = FnvHashMap::default();
+    for (id, record) in records.iter().enumerate() {
+        molecule.atoms.push(AtomAndBondI::new(Isotope::from(Ion::new(record.element, record.charge)), Vec::new()));
+        index.insert(record.site.serial, id);
+    }
+    // A metal ion of a `HETATM` is not bonded to the protein that holds it, so it stays a ligand
+    let metal_ion = |atom: usize| records[atom].site.hetero && is_metal(records[atom].element);
+    let allowed = |a: usize, b: usize| (!metal_ion(a) || records[b].site.hetero) && (!metal_ion(b) || records[a].site.hetero);
+    // The bonds of `CONECT` go in both directions, every bond is added once
+    let mut connected = vec![false; records.len()];
+    for (a, b) in connections.iter() {
+        let (a, b) = match (index.get(a), index.get(b)) {
+            (Some(a), Some(b)) => (*a, *b),
+            // The atoms of other alternate locations or models
+            _ => continue
+        };
+        // The coordination of the metals, the protein atom keeps its bonds from the distances
+        if !allowed(a, b) {
+            continue;
+        }
+        connected[a] = true;
+        connected[b] = true;
+        let bonded = a == b || molecule.atoms[a].bonds.iter().any(|id| {
+            let bond = molecule.bonds[*id];
+            bond.a == b || bond.b == b
+        });
+        if !bonded {
+            let id = molecule.bonds.len();
+            molecule.atoms[a].bonds.push(id);
+            molecule.atoms[b].bonds.push(id);
+            molecule.bonds.push(Bond::new(a, b, StructuralBond::Single));
+        }
+    }
+    let unconnected: Vec<bool> = connected.iter().map(|c| !c).collect();
+    connectivity::perceive_bonds_filtered(&mut molecule, &unconnected, allowed);
+    let sites: Vec<AtomSite> = records.into_iter().map(|r| r.site).collect();
+    let (molecules, atom_sites) = molecule.components().into_iter().map(|(molecule, indices)| {
+        let molecule_sites = indices.iter().map(|i| sites[*i].clone()).collect::<Vec<AtomSite>>();
+        (molecule, molecule_sites)
+    }).unzip();
+    Ok(PdbFile { compound: Compound { molecules }, sites: atom_sites })
+}
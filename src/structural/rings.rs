@@ -0,0 +1,416 @@
+use std::collections::{BTreeSet, VecDeque};
+
+use super::Molecule;
+
+/// A cycle of the molecule, the atoms and the bonds are given in the order in which they are walked,
+/// `bonds[i]` joins `atoms[i]` and `atoms[i + 1]` (the last one joins the last atom with the first)
+#[derive(Debug, Clone, Eq, PartialEq, Hash)]
+pub struct Ring {
+    pub atoms: Vec<usize>,
+    pub bonds: Vec<usize>,
+}
+
+impl Ring {
+    /// Build the ring from a set of bonds, if they form a single simple cycle
+    pub(crate) fn from_bonds(molecule: &Molecule, bonds: &BTreeSet<usize>) -> Option<Ring> {
+        let first = *bonds.iter().next()?;
+        let start = molecule.bonds[first].a;
+        let mut atoms = vec![start];
+        let mut path = vec![first];
+        let mut current = molecule.bonds[first].b;
+        while current != start {
+            let mut next = None;
+            for bond_id in molecule.atoms[current].bonds.iter() {
+                if bonds.contains(bond_id) && Some(bond_id) != path.last() {
+                    if next.is_some() {
+                        // More than two bonds of the set on the same atom
+                        return None;
+                    }
+                    next = Some(*bond_id);
+                }
+            }
+            let next = next?;
+            atoms.push(current);
+            path.push(next);
+            let bond = molecule.bonds[next];
+            current = if bond.a == current { bond.b } else { bond.a };
+            if path.len() > bonds.len() {
+                return None;
+            }
+        }
+        if path.len() != bonds.len() {
+            // There is more than one cycle
+            return None;
+        }
+        Some(Ring { atoms, bonds: path })
+    }
+
+    pub fn len(&self) -> usize {
+        self.atoms.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.atoms.is_empty()
+    }
+
+    pub fn contains_atom(&self, atom: usize) -> bool {
+        self.atoms.contains(&atom)
+    }
+
+    pub fn contains_bond(&self, bond: usize) -> bool {
+        self.bonds.contains(&bond)
+    }
+}
+
+/// Find the bonds that are part of some cycle, these are the ones that are not bridges
+pub(crate) fn ring_bonds(molecule: &Molecule) -> Vec<bool> {
+    let atoms_count = molecule.atoms.len();
+    let mut in_ring = vec![true; molecule.bonds.len()];
+    let mut order = vec![usize::MAX; atoms_count];
+    let mut low = vec![0; atoms_count];
+    let mut counter = 0;
+    for root in 0..atoms_count {
+        if order[root] != usize::MAX {
+            continue;
+        }
+        // Iterative DFS of Tarjan, the stack keeps the atom, the bond used to reach it and the next bond to visit
+        let mut stack = vec![(root, usize::MAX, 0)];
+        order[root] = counter;
+        low[root] = counter;
+        counter += 1;
+        while let Some(&mut (id, parent_bond, ref mut next)) = stack.last_mut() {
+            if let Some(bond_id) = molecule.atoms[id].bonds.get(*next) {
+                *next += 1;
+                let bond_id = *bond_id;
+                if bond_id == parent_bond {
+                    continue;
+                }
+                let bond = molecule.bonds[bond_id];
+                let pair = if bond.a == id { bond.b } else { bond.a };
+                if order[pair] == usize::MAX {
+                    order[pair] = counter;
+                    low[pair] = counter;
+                    counter += 1;
+                    stack.push((pair, bond_id, 0));
+                }
+                else {
+                    low[id] = low[id].min(order[pair]);
+                }
+            }
+            else {
+                stack.pop();
+                if let Some(&(parent, _, _)) = stack.last() {
+                    low[parent] = low[parent].min(low[id]);
+                    if low[id] > order[parent] {
+                        in_ring[parent_bond] = false;
+                    }
+                }
+            }
+        }
+    }
+    in_ring
+}
+
+/// Number of independent cycles of the molecule, that is the size of any cycle basis
+fn cyclomatic_number(molecule: &Molecule, in_ring: &[bool], ring_atoms: &[usize]) -> usize {
+    let ring_bonds_count = in_ring.iter().filter(|r| **r).count();
+    let mut systems = 0;
+    let mut seen = vec![false; molecule.atoms.len()];
+    for root in ring_atoms.iter() {
+        if seen[*root] {
+            continue;
+        }
+        systems += 1;
+        let mut stack = vec![*root];
+        seen[*root] = true;
+        while let Some(id) = stack.pop() {
+            for bond_id in molecule.atoms[id].bonds.iter().filter(|b| in_ring[**b]) {
+                let bond = molecule.bonds[*bond_id];
+                let pair = if bond.a == id { bond.b } else { bond.a };
+                if !seen[pair] {
+                    seen[pair] = true;
+                    stack.push(pair);
+                }
+            }
+        }
+    }
+    ring_bonds_count + systems - ring_atoms.len()
+}
+
+/// Rows of a matrix over GF(2) kept in reduced form, every row is a set of bonds
+struct CycleSpace {
+    words: usize,
+    rows: Vec<(usize, Vec<u64>)>, // Pivot and reduced vector
+}
+
+impl CycleSpace {
+    fn new(molecule: &Molecule) -> CycleSpace {
+        CycleSpace { words: molecule.bonds.len().div_ceil(64), rows: Vec::new() }
+    }
+
+    fn len(&self) -> usize {
+        self.rows.len()
+    }
+
+    /// Reduce the cycle with the rows, it gives `None` if the cycle is a sum of them
+    fn reduce(&self, bonds: &[usize]) -> Option<(usize, Vec<u64>)> {
+        let mut vector = vec![0u64; self.words];
+        for bond_id in bonds.iter() {
+            vector[bond_id / 64] |= 1 << (bond_id % 64);
+        }
+        for (pivot, row) in self.rows.iter() {
+            if vector[pivot / 64] & (1 << (pivot % 64)) != 0 {
+                for (v, r) in vector.iter_mut().zip(row.iter()) {
+                    *v ^= *r;
+                }
+            }
+        }
+        let word = vector.iter().position(|v| *v != 0)?;
+        Some((word * 64 + vector[word].trailing_zeros() as usize, vector))
+    }
+
+    fn insert(&mut self, (pivot, vector): (usize, Vec<u64>)) {
+        // Keep the rows reduced so that every pivot only appears in its own row
+        for (_, row) in self.rows.iter_mut() {
+            if row[pivot / 64] & (1 << (pivot % 64)) != 0 {
+                for (r, v) in row.iter_mut().zip(vector.iter()) {
+                    *r ^= *v;
+                }
+            }
+        }
+        self.rows.push((pivot, vector));
+    }
+}
+
+/// Atoms that have some ring bond
+fn ring_atoms(molecule: &Molecule, in_ring: &[bool]) -> Vec<usize> {
+    (0..molecule.atoms.len()).filter(|id| {
+        molecule.atoms[*id].bonds.iter().any(|b| in_ring[*b])
+    }).collect()
+}
+
+/// Smallest set of smallest rings, it is computed as a minimum cycle basis with the algorithm of
+/// Horton: the candidates are the cycles made of an edge and the shortest paths from its ends to a root
+pub(crate) fn sssr(molecule: &Molecule) -> Vec<Ring> {
+    let in_ring = ring_bonds(molecule);
+    let ring_atoms = ring_atoms(molecule, &in_ring);
+    if ring_atoms.is_empty() {
+        return Vec::new();
+    }
+    let basis_size = cyclomatic_number(molecule, &in_ring, &ring_atoms);
+    let mut candidates = BTreeSet::new();
+    for root in ring_atoms.iter() {
+        let root = *root;
+        // Shortest paths tree using only ring bonds
+        let mut parent = vec![None; molecule.atoms.len()];
+        let mut distance = vec![usize::MAX; molecule.atoms.len()];
+        distance[root] = 0;
+        let mut queue = VecDeque::new();
+        queue.push_back(root);
+        while let Some(id) = queue.pop_front() {
+            for bond_id in molecule.atoms[id].bonds.iter().filter(|b| in_ring[**b]) {
+                let bond = molecule.bonds[*bond_id];
+                let pair = if bond.a == id { bond.b } else { bond.a };
+                if distance[pair] == usize::MAX {
+                    distance[pair] = distance[id] + 1;
+                    parent[pair] = Some(*bond_id);
+                    queue.push_back(pair);
+                }
+            }
+        }
+        let path = |mut id: usize| {
+            let mut atoms = vec![id];
+            let mut bonds = Vec::new();
+            while let Some(bond_id) = parent[id] {
+                let bond = molecule.bonds[bond_id];
+                id = if bond.a == id { bond.b } else { bond.a };
+                atoms.push(id);
+                bonds.push(bond_id);
+            }
+            (atoms, bonds)
+        };
+        for (bond_id, bond) in molecule.bonds.iter().enumerate() {
+            if !in_ring[bond_id] || distance[bond.a] == usize::MAX || parent[bond.a] == Some(bond_id) ||
+                parent[bond.b] == Some(bond_id) {
+                continue;
+            }
+            let (atoms_a, bonds_a) = path(bond.a);
+            let (atoms_b, bonds_b) = path(bond.b);
+            // Both paths can only share the root
+            let shared = atoms_a.iter().filter(|a| atoms_b.contains(a)).count();
+            if shared != 1 {
+                continue;
+            }
+            let mut bonds: Vec<usize> = bonds_a.into_iter().chain(bonds_b).collect();
+            bonds.push(bond_id);
+            bonds.sort_unstable();
+            candidates.insert((bonds.len(), bonds));
+        }
+    }
+    // Take the shortest candidates that are independent of the ones already taken (Gaussian elimination on GF(2))
+    let mut space = CycleSpace::new(molecule);
+    let mut rings = Vec::new();
+    for (_, bonds) in candidates {
+        if space.len() == basis_size {
+            break;
+        }
+        if let Some(row) = space.reduce(&bonds) {
+            space.insert(row);
+            if let Some(ring) = Ring::from_bonds(molecule, &bonds.into_iter().collect()) {
+                rings.push(ring);
+            }
+        }
+    }
+    rings
+}
+
+/// All the shortest paths from the root to an atom, as lists of bonds
+fn shortest_paths(molecule: &Molecule, predecessors: &[Vec<usize>], id: usize, paths: &mut Vec<Vec<usize>>) {
+    let mut stack = vec![(id, Vec::new())];
+    while let Some((id, path)) = stack.pop() {
+        if predecessors[id].is_empty() {
+            paths.push(path);
+            continue;
+        }
+        for bond_id in predecessors[id].iter() {
+            let bond = molecule.bonds[*bond_id];
+            let pair = if bond.a == id { bond.b } else { bond.a };
+            let mut path = path.clone();
+            path.push(*bond_id);
+            stack.push((pair, path));
+        }
+    }
+}
+
+fn path_atoms(molecule: &Molecule, path: &[usize]) -> BTreeSet<usize> {
+    path.iter().flat_map(|b| vec![molecule.bonds[*b].a, molecule.bonds[*b].b]).collect()
+}
+
+/// Relevant cycles, the union of all the minimum cycle bases. Unlike the SSSR it does not depend
+/// on the order of the atoms, so in the cubane all the six faces are given. The candidates are
+/// the cycles made of an edge, or two edges that meet on an atom, and every pair of shortest paths
+/// from their ends to a root (the algorithm of Vismara); a candidate is relevant if it is not a
+/// sum of shorter cycles
+pub(crate) fn relevant_cycles(molecule: &Molecule) -> Vec<Ring> {
+    let in_ring = ring_bonds(molecule);
+    let ring_atoms = ring_atoms(molecule, &in_ring);
+    if ring_atoms.is_empty() {
+        return Vec::new();
+    }
+    let mut candidates = BTreeSet::new();
+    for root in ring_atoms.iter() {
+        let root = *root;
+        let mut distance = vec![usize::MAX; molecule.atoms.len()];
+        let mut predecessors = vec![Vec::new(); molecule.atoms.len()];
+        distance[root] = 0;
+        let mut queue = VecDeque::new();
+        queue.push_back(root);
+        while let Some(id) = queue.pop_front() {
+            for bond_id in molecule.atoms[id].bonds.iter().filter(|b| in_ring[**b]) {
+                let bond = molecule.bonds[*bond_id];
+                let pair = if bond.a == id { bond.b } else { bond.a };
+                if distance[pair] == usize::MAX {
+                    distance[pair] = distance[id] + 1;
+                    queue.push_back(pair);
+                }
+                if distance[pair] == distance[id] + 1 {
+                    predecessors[pair].push(*bond_id);
+                }
+            }
+        }
+        // Pairs of paths that close a cycle: through a bond between atoms at the same distance
+        // (odd cycles) or through an atom reached by two bonds (even cycles)
+        let mut closures = Vec::new();
+        for (bond_id, bond) in molecule.bonds.iter().enumerate() {
+            if in_ring[bond_id] && distance[bond.a] != usize::MAX && distance[bond.a] == distance[bond.b] {
+                closures.push((bond.a, bond.b, vec![bond_id]));
+            }
+        }
+        for id in ring_atoms.iter() {
+            let incoming = &predecessors[*id];
+            for i in 0..incoming.len() {
+                for j in i + 1..incoming.len() {
+                    let (a, b) = (molecule.bonds[incoming[i]], molecule.bonds[incoming[j]]);
+                    let a = if a.a == *id { a.b } else { a.a };
+                    let b = if b.a == *id { b.b } else { b.a };
+                    closures.push((a, b, vec![incoming[i], incoming[j]]));
+                }
+            }
+        }
+        for (a, b, closing) in closures {
+            let mut paths_a = Vec::new();
+            let mut paths_b = Vec::new();
+            shortest_paths(molecule, &predecessors, a, &mut paths_a);
+            shortest_paths(molecule, &predecessors, b, &mut paths_b);
+            for path_a in paths_a.iter() {
+                let atoms_a = path_atoms(molecule, path_a);
+                for path_b in paths_b.iter() {
+                    let atoms_b = path_atoms(molecule, path_b);
+                    // Both paths can only share the root
+                    if atoms_a.intersection(&atoms_b).any(|a| *a != root) {
+                        continue;
+                    }
+                    let mut bonds: Vec<usize> = path_a.iter().chain(path_b.iter()).chain(closing.iter()).cloned().collect();
+                    bonds.sort_unstable();
+                    candidates.insert((bonds.len(), bonds));
+                }
+            }
+        }
+    }
+    let candidates: Vec<(usize, Vec<usize>)> = candidates.into_iter().collect();
+    let mut space = CycleSpace::new(molecule);
+    let mut rings = Vec::new();
+    let mut start = 0;
+    while start < candidates.len() {
+        // The cycles of the same size are compared only with the shorter ones
+        let size = candidates[start].0;
+        let end = candidates[start..].iter().position(|c| c.0 != size).map_or(candidates.len(), |p| start + p);
+        let mut rows = Vec::new();
+        for (_, bonds) in candidates[start..end].iter() {
+            if let Some(row) = space.reduce(bonds) {
+                rows.push(row);
+                if let Some(ring) = Ring::from_bonds(molecule, &bonds.iter().cloned().collect()) {
+                    rings.push(ring);
+                }
+            }
+        }
+        for row in rows {
+            // Reduce again, the rows of the same size can depend on each other
+            let bonds: Vec<usize> = (0..molecule.bonds.len()).filter(|b| row.1[b / 64] & (1 << (b % 64)) != 0).collect();
+            if let Some(row) = space.reduce(&bonds) {
+                space.insert(row);
+            }
+        }
+        start = end;
+    }
+    rings
+}
+
+/// Groups of ring atoms joined by ring bonds, the fused and the spiro rings are in the same system
+pub(crate) fn ring_systems(molecule: &Molecule) -> Vec<Vec<usize>> {
+    let in_ring = ring_bonds(molecule);
+    let mut seen = vec![false; molecule.atoms.len()];
+    let mut systems = Vec::new();
+    for root in ring_atoms(molecule, &in_ring) {
+        if seen[root] {
+            continue;
+        }
+        let mut system = Vec::new();
+        let mut stack = vec![root];
+        seen[root] = true;
+        while let Some(id) = stack.pop() {
+            system.push(id);
+            for bond_id in molecule.atoms[id].bonds.iter().filter(|b| in_ring[**b]) {
+                let bond = molecule.bonds[*bond_id];
+                let pair = if bond.a == id { bond.b } else { bond.a };
+                if !seen[pair] {
+                    seen[pair] = true;
+                    stack.push(pair);
+                }
+            }
+        }
+        system.sort_unstable();
+        systems.push(system);
+    }
+    systems
+}
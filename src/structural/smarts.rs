@@ -0,0 +1,585 @@
+use std::collections::BTreeMap;
+use std::str;
+
+use ptable::Element;
+
+use super::smiles::{SMILES_AROMATIC_BRACKET_ELEMENTS, SMILES_ORGANIC_SUBSET};
+use super::substructure::{self, Query};
+use super::{rings, kekulization, Molecule, ParseError, ParseErrorReason, ParserError, StructuralBond};
+use crate::{checked_parse_number, parse_number};
+
+/// Logical combination of primitives, as `C,N`, `!H0` or `c;R2`
+#[derive(Debug, Clone, PartialEq)]
+enum Expression<P> {
+    Primitive(P),
+    Not(Box<Expression<P>>),
+    And(Box<Expression<P>>, Box<Expression<P>>),
+    Or(Box<Expression<P>>, Box<Expression<P>>),
+}
+
+impl<P> Expression<P> {
+    fn evaluate<F: Fn(&P) -> bool + Copy>(&self, primitive: F) -> bool {
+        match self {
+            Expression::Primitive(p) => primitive(p),
+            Expression::Not(e) => !e.evaluate(primitive),
+            Expression::And(a, b) => a.evaluate(primitive) && b.evaluate(primitive),
+            Expression::Or(a, b) => a.evaluate(primitive) || b.evaluate(primitive),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum AtomPrimitive {
+    /// `*`
+    Any,
+    /// `a` (true) and `A` (false)
+    Aromatic(bool),
+    /// A symbol, which also tells if the atom is aromatic, or an atomic number (`#6`)
+    Element(Element, Option<bool>),
+    /// Mass number
+    Isotope(usize),
+    /// `D`: heavy atoms bonded to the atom
+    Degree(usize),
+    /// `X`: all the atoms bonded to the atom, including the hydrogens
+    Connectivity(usize),
+    /// `H` and `h`
+    Hydrogens(usize),
+    /// `v`: sum of the bond orders
+    Valence(usize),
+    /// `R`: number of SSSR rings of the atom, `None` for any
+    RingMembership(Option<usize>),
+    /// `r`: size of a ring of the atom, `None` for any
+    RingSize(Option<usize>),
+    /// `x`: ring bonds of the atom, `None` for any
+    RingConnectivity(Option<usize>),
+    Charge(i8),
+    /// `@` and `@@`, they are accepted but the chirality is not checked
+    Chirality,
+    /// `$(...)`
+    Recursive(Box<Smarts>),
+}
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+enum BondPrimitive {
+    Single,
+    Double,
+    Triple,
+    Aromatic,
+    /// `~`
+    Any,
+    /// `@`
+    Ring,
+}
+
+/// A SMARTS pattern, a query that can be searched in molecules
+#[derive(Debug, Clone, PartialEq)]
+pub struct Smarts {
+    atoms: Vec<Expression<AtomPrimitive>>,
+    /// Ends of the bond and its expression, `None` if no bond was written (single or aromatic)
+    bonds: Vec<(usize, usize, Option<Expression<BondPrimitive>>)>,
+}
+
+/// Atom where a ring label was opened, the bond written with it and where it is
+type RingOpening<'a> = (usize, Option<Expression<BondPrimitive>>, &'a [u8]);
+
+/// Position of the pattern on the whole string, for the errors of the recursive patterns
+struct Context {
+    length: usize,
+    offset: usize,
+}
+
+impl Context {
+    fn error(&self, at: &[u8], reason: ParseErrorReason) -> ParserError {
+        ParseError { position: self.offset + self.length - at.len(), character: at.first().map(|c| *c as char), reason }.into()
+    }
+}
+
+const BOND_CHARACTERS: &[u8] = b"-=#:~@/\\!";
+
+fn parse_bond_primitive<'a>(string: &'a [u8], context: &Context) -> Result<(BondPrimitive, &'a [u8]), ParserError> {
+    let primitive = match string.first() {
+        // The directional bonds are single bonds for the matching
+        Some(b'-') | Some(b'/') | Some(b'\\') => BondPrimitive::Single,
+        Some(b'=') => BondPrimitive::Double,
+        Some(b'#') => BondPrimitive::Triple,
+        Some(b':') => BondPrimitive::Aromatic,
+        Some(b'~') => BondPrimitive::Any,
+        Some(b'@') => BondPrimitive::Ring,
+        Some(_) => return Err(context.error(string, ParseErrorReason::UnexpectedCharacter)),
+        None => return Err(context.error(string, ParseErrorReason::UnexpectedEnd))
+    };
+    Ok((primitive, &string[1..]))
+}
+
+fn parse_atom_primitive<'a>(string: &'a [u8], first: bool, context: &Context) -> Result<(AtomPrimitive, &'a [u8]), ParserError> {
+    let c = match string.first() {
+        Some(c) => *c,
+        None => return Err(context.error(string, ParseErrorReason::UnexpectedEnd))
+    };
+    // Optional number after a primitive, the error is for the numbers that don't fit
+    let count = |rest: &'a [u8], reason| -> Result<(Option<usize>, &'a [u8]), ParserError> {
+        if !rest.is_empty() && rest[0].is_ascii_digit() {
+            match checked_parse_number(rest) {
+                (Some(n), r) => Ok((Some(n), r)),
+                (None, _) => Err(context.error(rest, reason))
+            }
+        }
+        else {
+            Ok((None, rest))
+        }
+    };
+    let rest = &string[1..];
+    if c.is_ascii_uppercase() && rest.first().is_some_and(|c| c.is_ascii_lowercase()) {
+        // The two letter symbols go first, so `[Cl]` is chlorine and `[As]` arsenic
+        if let Some(e) = Element::from_symbol(unsafe { str::from_utf8_unchecked(&string[..2]) }) {
+            return Ok((AtomPrimitive::Element(e, Some(false)), &string[2..]));
+        }
+    }
+    let primitive = match c {
+        b'0'..=b'9' => {
+            return match checked_parse_number(string) {
+                (Some(n), rest) => Ok((AtomPrimitive::Isotope(n), rest)),
+                (None, _) => Err(context.error(string, ParseErrorReason::BadIsotope))
+            };
+        },
+        b'*' => AtomPrimitive::Any,
+        b'a' if !rest.starts_with(b"s") => AtomPrimitive::Aromatic(true),
+        b'A' => AtomPrimitive::Aromatic(false),
+        b'#' => {
+            let (n, rest) = count(rest, ParseErrorReason::UnknownElement)?;
+            return match n.and_then(Element::from_atomic_number) {
+                Some(e) => Ok((AtomPrimitive::Element(e, None), rest)),
+                None => Err(context.error(string, ParseErrorReason::UnknownElement))
+            };
+        },
+        b'+' | b'-' => {
+            let sign = if c == b'+' { 1 } else { -1 };
+            let mut rest = rest;
+            let mut charge = 1;
+            if !rest.is_empty() && rest[0].is_ascii_digit() {
+                let (n, r) = checked_parse_number(rest);
+                charge = match n.filter(|n| *n <= 127) {
+                    Some(n) => n as i8,
+                    None => return Err(context.error(rest, ParseErrorReason::BadCharge))
+                };
+                rest = r;
+            }
+            else {
+                while rest.first() == Some(&c) {
+                    charge = match charge.checked_add(1) {
+                        Some(charge) => charge,
+                        None => return Err(context.error(rest, ParseErrorReason::BadCharge))
+                    };
+                    rest = &rest[1..];
+                }
+            }
+            return Ok((AtomPrimitive::Charge(sign * charge), rest));
+        },
+        b'@' => {
+            let rest = if rest.first() == Some(&b'@') { &rest[1..] } else { rest };
+            return Ok((AtomPrimitive::Chirality, rest));
+        },
+        b'$' => {
+            if rest.first() != Some(&b'(') {
+                return Err(context.error(rest, ParseErrorReason::UnexpectedCharacter));
+            }
+            // Look for the parenthesis that closes the pattern
+            let mut depth = 0;
+            let mut end = None;
+            for (i, c) in rest.iter().enumerate() {
+                match c {
+                    b'(' => depth += 1,
+                    b')' => {
+                        depth -= 1;
+                        if depth == 0 {
+                            end = Some(i);
+                            break;
+                        }
+                    },
+                    _ => {}
+                }
+            }
+            let end = match end {
+                Some(end) => end,
+                None => return Err(context.error(rest, ParseErrorReason::UnmatchedParenthesis))
+            };
+            let inner = Context { length: end - 1, offset: context.offset + context.length - rest.len() + 1 };
+            let smarts = parse(&rest[1..end], &inner)?;
+            return Ok((AtomPrimitive::Recursive(Box::new(smarts)), &rest[end + 1..]));
+        },
+        b'H' if first && !rest.first().is_some_and(|c| c.is_ascii_digit()) => {
+            // `[H]` is a hydrogen atom, not an atom with one hydrogen
+            AtomPrimitive::Element(Element::Hydrogen, Some(false))
+        },
+        b'H' | b'h' => {
+            let (n, rest) = count(rest, ParseErrorReason::BadHydrogenCount)?;
+            return Ok((AtomPrimitive::Hydrogens(n.unwrap_or(1)), rest));
+        },
+        b'D' | b'X' | b'v' => {
+            let (n, rest) = count(rest, ParseErrorReason::UnexpectedCharacter)?;
+            let n = n.unwrap_or(1);
+            return Ok((match c {
+                b'D' => AtomPrimitive::Degree(n),
+                b'X' => AtomPrimitive::Connectivity(n),
+                _ => AtomPrimitive::Valence(n)
+            }, rest));
+        },
+        b'R' | b'r' | b'x' => {
+            let (n, rest) = count(rest, ParseErrorReason::UnexpectedCharacter)?;
+            return Ok((match (c, n) {
+                // `R0`, `r0` and `x0` are the atoms without rings
+                (_, Some(0)) => AtomPrimitive::RingMembership(Some(0)),
+                (b'R', n) => AtomPrimitive::RingMembership(n),
+                (b'r', n) => AtomPrimitive::RingSize(n),
+                (_, n) => AtomPrimitive::RingConnectivity(n)
+            }, rest));
+        },
+        _ => {
+            if let Some((symbol, element)) = SMILES_AROMATIC_BRACKET_ELEMENTS.iter().find(|(s, _)| string.starts_with(s)) {
+                return Ok((AtomPrimitive::Element(*element, Some(true)), &string[symbol.len()..]));
+            }
+            if !c.is_ascii_uppercase() {
+                return Err(context.error(string, ParseErrorReason::UnexpectedCharacter));
+            }
+            return match Element::from_symbol(unsafe { str::from_utf8_unchecked(&string[..1]) }) {
+                Some(e) => Ok((AtomPrimitive::Element(e, Some(false)), rest)),
+                None => Err(context.error(string, ParseErrorReason::UnknownElement))
+            };
+        }
+    };
+    Ok((primitive, rest))
+}
+
+/// Parse an expression with the precedence of SMARTS: `!`, then `&` (or nothing), then `,` and at
+/// last `;`. The first primitive of the brackets (or the one after the mass number) can be `H`, the
+/// hydrogen atom
+fn parse_atom_expression<'a>(string: &'a [u8], level: usize, first: bool, context: &Context)
+    -> Result<(Expression<AtomPrimitive>, &'a [u8]), ParserError> {
+    if level == 3 {
+        if string.first() == Some(&b'!') {
+            let (e, rest) = parse_atom_expression(&string[1..], 3, false, context)?;
+            return Ok((Expression::Not(Box::new(e)), rest));
+        }
+        let (p, rest) = parse_atom_primitive(string, first, context)?;
+        return Ok((Expression::Primitive(p), rest));
+    }
+    let (mut expression, mut string) = parse_atom_expression(string, level + 1, first, context)?;
+    let mut first = first && matches!(expression, Expression::Primitive(AtomPrimitive::Isotope(_)));
+    loop {
+        let next = match (level, string.first()) {
+            (0, Some(b';')) | (1, Some(b',')) | (2, Some(b'&')) => &string[1..],
+            // Two primitives together, as in `CH3`
+            (2, Some(c)) if !b"],;".contains(c) => string,
+            _ => break
+        };
+        let (e, rest) = parse_atom_expression(next, level + 1, first, context)?;
+        first = false;
+        expression = match level {
+            1 => Expression::Or(Box::new(expression), Box::new(e)),
+            _ => Expression::And(Box::new(expression), Box::new(e))
+        };
+        string = rest;
+    }
+    Ok((expression, string))
+}
+
+fn parse_bond_expression<'a>(string: &'a [u8], level: usize, context: &Context)
+    -> Result<(Expression<BondPrimitive>, &'a [u8]), ParserError> {
+    if level == 3 {
+        if string.first() == Some(&b'!') {
+            let (e, rest) = parse_bond_expression(&string[1..], 3, context)?;
+            return Ok((Expression::Not(Box::new(e)), rest));
+        }
+        let (p, rest) = parse_bond_primitive(string, context)?;
+        return Ok((Expression::Primitive(p), rest));
+    }
+    let (mut expression, mut string) = parse_bond_expression(string, level + 1, context)?;
+    loop {
+        let next = match (level, string.first()) {
+            (0, Some(b';')) | (1, Some(b',')) | (2, Some(b'&')) => &string[1..],
+            (2, Some(c)) if BOND_CHARACTERS.contains(c) => string,
+            _ => break
+        };
+        let (e, rest) = parse_bond_expression(next, level + 1, context)?;
+        expression = match level {
+            1 => Expression::Or(Box::new(expression), Box::new(e)),
+            _ => Expression::And(Box::new(expression), Box::new(e))
+        };
+        string = rest;
+    }
+    Ok((expression, string))
+}
+
+fn parse(string: &[u8], context: &Context) -> Result<Smarts, ParserError> {
+    let whole = string;
+    let mut string = string;
+    let mut smarts = Smarts { atoms: Vec::new(), bonds: Vec::new() };
+    let mut previous: Option<usize> = None;
+    let mut branches = Vec::new();
+    let mut waiting_bond: Option<(Expression<BondPrimitive>, &[u8])> = None;
+    let mut labels: BTreeMap<usize, RingOpening> = BTreeMap::new();
+    while let Some(c) = string.first() {
+        match c {
+            b'(' => {
+                if previous.is_none() || waiting_bond.is_some() {
+                    return Err(context.error(string, ParseErrorReason::UnexpectedCharacter));
+                }
+                branches.push((previous, string));
+                string = &string[1..];
+            },
+            b')' => {
+                if let Some((_, at)) = waiting_bond {
+                    return Err(context.error(at, ParseErrorReason::DanglingBond));
+                }
+                previous = match branches.pop() {
+                    Some((atom, _)) => atom,
+                    None => return Err(context.error(string, ParseErrorReason::UnmatchedParenthesis))
+                };
+                string = &string[1..];
+            },
+            b'.' => {
+                if waiting_bond.is_some() || !branches.is_empty() {
+                    return Err(context.error(string, ParseErrorReason::UnexpectedCharacter));
+                }
+                previous = None;
+                string = &string[1..];
+            },
+            b'0'..=b'9' | b'%' => {
+                let atom = match previous {
+                    Some(atom) => atom,
+                    None => return Err(context.error(string, ParseErrorReason::UnexpectedCharacter))
+                };
+                let at = string;
+                let label = if *c == b'%' {
+                    if string.len() < 3 || !string[1].is_ascii_digit() || !string[2].is_ascii_digit() {
+                        return Err(context.error(string, ParseErrorReason::BadRingLabel));
+                    }
+                    let label = parse_number(&string[1..3]).0;
+                    string = &string[3..];
+                    label
+                }
+                else {
+                    string = &string[1..];
+                    (c - b'0') as usize
+                };
+                let bond = waiting_bond.take().map(|b| b.0);
+                match labels.remove(&label) {
+                    Some((opening, opening_bond, _)) => {
+                        if opening == atom {
+                            return Err(context.error(at, ParseErrorReason::BadRingLabel));
+                        }
+                        smarts.bonds.push((opening, atom, bond.or(opening_bond)));
+                    },
+                    None => {
+                        labels.insert(label, (atom, bond, at));
+                    }
+                }
+            },
+            c if BOND_CHARACTERS.contains(c) => {
+                if previous.is_none() || waiting_bond.is_some() {
+                    return Err(context.error(string, ParseErrorReason::UnexpectedCharacter));
+                }
+                let (bond, rest) = parse_bond_expression(string, 0, context)?;
+                waiting_bond = Some((bond, string));
+                string = rest;
+            },
+            _ => {
+                let (atom, rest) = if *c == b'[' {
+                    let (atom, rest) = parse_atom_expression(&string[1..], 0, true, context)?;
+                    match rest.first() {
+                        Some(b']') => (atom, &rest[1..]),
+                        Some(_) => return Err(context.error(rest, ParseErrorReason::UnexpectedCharacter)),
+                        None => return Err(context.error(rest, ParseErrorReason::UnexpectedEnd))
+                    }
+                }
+                else {
+                    parse_organic_atom(string, context)?
+                };
+                let id = smarts.atoms.len();
+                smarts.atoms.push(atom);
+                if let Some(previous) = previous {
+                    smarts.bonds.push((previous, id, waiting_bond.take().map(|b| b.0)));
+                }
+                previous = Some(id);
+                string = rest;
+            }
+        }
+    }
+    if let Some((_, at)) = waiting_bond {
+        return Err(context.error(at, ParseErrorReason::DanglingBond));
+    }
+    if let Some((_, at)) = branches.first() {
+        return Err(context.error(at, ParseErrorReason::UnmatchedParenthesis));
+    }
+    if let Some((_, _, at)) = labels.values().min_by_key(|l| whole.len() - l.2.len()) {
+        return Err(context.error(at, ParseErrorReason::UnclosedRing));
+    }
+    if smarts.atoms.is_empty() {
+        return Err(context.error(string, ParseErrorReason::UnexpectedEnd));
+    }
+    Ok(smarts)
+}
+
+/// Atoms written without brackets: the organic subset, `*`, `a` and `A`
+fn parse_organic_atom<'a>(string: &'a [u8], context: &Context) -> Result<(Expression<AtomPrimitive>, &'a [u8]), ParserError> {
+    let primitive = match string[0] {
+        b'*' => Some((AtomPrimitive::Any, 1)),
+        b'a' => Some((AtomPrimitive::Aromatic(true), 1)),
+        b'A' => Some((AtomPrimitive::Aromatic(false), 1)),
+        b'b' | b'c' | b'n' | b'o' | b'p' | b's' => SMILES_AROMATIC_BRACKET_ELEMENTS.iter()
+            .find(|(s, _)| s.len() == 1 && s[0] == string[0]).map(|(_, e)| (AtomPrimitive::Element(*e, Some(true)), 1)),
+        _ => {
+            let two = string.len() > 1 && (string.starts_with(b"Cl") || string.starts_with(b"Br"));
+            let end = if two { 2 } else { 1 };
+            Element::from_symbol(unsafe { str::from_utf8_unchecked(&string[..end]) })
+                .filter(|e| SMILES_ORGANIC_SUBSET.contains(e)).map(|e| (AtomPrimitive::Element(e, Some(false)), end))
+        }
+    };
+    match primitive {
+        Some((p, length)) => Ok((Expression::Primitive(p), &string[length..])),
+        None if string[0].is_ascii_uppercase() => Err(context.error(string, ParseErrorReason::UnknownElement)),
+        None => Err(context.error(string, ParseErrorReason::UnexpectedCharacter))
+    }
+}
+
+/// Properties of the atoms of a molecule that the primitives need
+struct Target<'a> {
+    molecule: &'a Molecule,
+    aromatic: Vec<bool>,
+    heavy_degree: Vec<usize>,
+    hydrogens: Vec<usize>,
+    valence: Vec<usize>,
+    ring_bond: Vec<bool>,
+    ring_membership: Vec<usize>,
+    ring_sizes: Vec<Vec<usize>>,
+}
+
+impl<'a> Target<'a> {
+    fn new(molecule: &'a Molecule) -> Target<'a> {
+        let count = molecule.atoms.len();
+        let neighbour = |id: usize, b: &usize| {
+            let bond = molecule.bonds[*b];
+            if bond.a == id { bond.b } else { bond.a }
+        };
+        let aromatic = (0..count).map(|id| {
+            molecule.atoms[id].bonds.iter().any(|b| molecule.bonds[*b].k == StructuralBond::Aromatic)
+        }).collect();
+        let hydrogens: Vec<usize> = (0..count).map(|id| molecule.atoms[id].bonds.iter().filter(|b| {
+            *molecule.atoms[neighbour(id, b)].get_element() == Element::Hydrogen && !molecule.atoms[neighbour(id, b)].wildcard
+        }).count()).collect();
+        let heavy_degree = (0..count).map(|id| molecule.atoms[id].bonds.len() - hydrogens[id]).collect();
+        // The aromatic bonds count as single or double as in the Kekulé structure
+        let mut kekule = molecule.clone();
+        let orders = if kekulization::kekulize(&mut kekule).is_ok() { &kekule } else { molecule };
+        let valence = (0..count).map(|id| orders.atoms[id].bonds.iter().map(|b| match orders.bonds[*b].k {
+            StructuralBond::Aromatic => 1,
+            k => k as usize
+        }).sum()).collect();
+        let ring_bond = rings::ring_bonds(molecule);
+        let mut ring_membership = vec![0; count];
+        for ring in rings::sssr(molecule) {
+            for id in ring.atoms {
+                ring_membership[id] += 1;
+            }
+        }
+        let mut ring_sizes = vec![Vec::new(); count];
+        for ring in rings::relevant_cycles(molecule) {
+            for id in ring.atoms.iter() {
+                ring_sizes[*id].push(ring.len());
+            }
+        }
+        Target { molecule, aromatic, heavy_degree, hydrogens, valence, ring_bond, ring_membership, ring_sizes }
+    }
+
+    fn atom_matches(&self, primitive: &AtomPrimitive, id: usize) -> bool {
+        let atom = &self.molecule.atoms[id];
+        if atom.wildcard {
+            return *primitive == AtomPrimitive::Any;
+        }
+        let ring_bonds = || atom.bonds.iter().filter(|b| self.ring_bond[**b]).count();
+        match primitive {
+            AtomPrimitive::Any | AtomPrimitive::Chirality => true,
+            AtomPrimitive::Aromatic(aromatic) => self.aromatic[id] == *aromatic,
+            AtomPrimitive::Element(element, aromatic) => atom.get_element() == element &&
+                aromatic.is_none_or(|a| a == self.aromatic[id]),
+            AtomPrimitive::Isotope(mass) => atom.get_neutrons_count()
+                .is_some_and(|n| n.get() as usize + atom.get_element().get_atomic_number() == *mass),
+            AtomPrimitive::Degree(n) => self.heavy_degree[id] == *n,
+            AtomPrimitive::Connectivity(n) => atom.bonds.len() == *n,
+            AtomPrimitive::Hydrogens(n) => self.hydrogens[id] == *n,
+            AtomPrimitive::Valence(n) => self.valence[id] == *n,
+            AtomPrimitive::RingMembership(Some(n)) => self.ring_membership[id] == *n,
+            AtomPrimitive::RingMembership(None) | AtomPrimitive::RingSize(None) | AtomPrimitive::RingConnectivity(None) =>
+                self.ring_membership[id] > 0,
+            AtomPrimitive::RingSize(Some(n)) => self.ring_sizes[id].contains(n),
+            AtomPrimitive::RingConnectivity(Some(n)) => ring_bonds() == *n,
+            AtomPrimitive::Charge(charge) => atom.get_ion().get_charge() == charge,
+            AtomPrimitive::Recursive(smarts) => {
+                substructure::matches_at(&Bound { smarts, target: self }, 0, self.molecule, id)
+            }
+        }
+    }
+
+    fn bond_matches(&self, expression: &Option<Expression<BondPrimitive>>, id: usize) -> bool {
+        let k = self.molecule.bonds[id].k;
+        match expression {
+            Some(expression) => expression.evaluate(|p| match p {
+                BondPrimitive::Single => k == StructuralBond::Single,
+                BondPrimitive::Double => k == StructuralBond::Double,
+                BondPrimitive::Triple => k == StructuralBond::Triple,
+                BondPrimitive::Aromatic => k == StructuralBond::Aromatic,
+                BondPrimitive::Any => true,
+                BondPrimitive::Ring => self.ring_bond[id]
+            }),
+            None => k == StructuralBond::Single || k == StructuralBond::Aromatic
+        }
+    }
+}
+
+/// A pattern together with the molecule where it is searched
+struct Bound<'a, 'b> {
+    smarts: &'a Smarts,
+    target: &'a Target<'b>,
+}
+
+impl<'a, 'b> Query for Bound<'a, 'b> {
+    fn atoms_count(&self) -> usize {
+        self.smarts.atoms.len()
+    }
+
+    fn neighbours(&self, atom: usize) -> Vec<(usize, usize)> {
+        self.smarts.bonds.iter().enumerate().filter_map(|(i, (a, b, _))| {
+            if *a == atom { Some((i, *b)) } else if *b == atom { Some((i, *a)) } else { None }
+        }).collect()
+    }
+
+    fn atom_matches(&self, atom: usize, _target: &Molecule, target_atom: usize) -> bool {
+        self.smarts.atoms[atom].evaluate(|p| self.target.atom_matches(p, target_atom))
+    }
+
+    fn bond_matches(&self, bond: usize, _target: &Molecule, target_bond: usize) -> bool {
+        self.target.bond_matches(&self.smarts.bonds[bond].2, target_bond)
+    }
+}
+
+impl Smarts {
+    /// Parse a SMARTS pattern. Atom primitives: symbols, `*`, `a`, `A`, `#n`, the mass number, `D`
+    /// (heavy atoms bonded), `X`, `H`, `h`, `v`, `R`, `r`, `x`, the charge, `@` (not checked) and
+    /// `$(...)`; bond primitives: `-`, `=`, `#`, `:`, `~`, `@`, `/` and `\`; operators: `!`, `&`, `,`
+    /// and `;`. The hydrogens of the molecules are explicit atoms, so `H` and `h` are the same
+    pub fn from_string(string: &str) -> Result<Smarts, ParserError> {
+        parse(string.as_bytes(), &Context { length: string.len(), offset: 0 })
+    }
+
+    /// All the matches of the pattern, every match gives the atom of the molecule for each atom of the pattern
+    pub fn matches(&self, molecule: &Molecule) -> Vec<Vec<usize>> {
+        let target = Target::new(molecule);
+        substructure::find(&Bound { smarts: self, target: &target }, molecule, false).into_iter()
+            .map(|m| m.into_iter().map(|a| a.unwrap()).collect()).collect()
+    }
+
+    pub fn is_match(&self, molecule: &Molecule) -> bool {
+        let target = Target::new(molecule);
+        !substructure::find(&Bound { smarts: self, target: &target }, molecule, true).is_empty()
+    }
+}
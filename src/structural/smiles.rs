@@ -1,68 +1,103 @@
 use std::hint::unreachable_unchecked;
 use std::collections::{BTreeMap, BTreeSet};
 use std::num::NonZeroU8;
+use std::str;
 
 use ptable::Element;
 
 use super::*;
-use crate::parse_number;
+use crate::checked_parse_number;
+
+/// Element given to the wildcard atoms (`*`), it has no meaning as they are marked as wildcards
+const WILDCARD_ELEMENT: Element = Element::Hydrogen;
+
+/// Aromatic symbols that can be written inside brackets
+pub(crate) const SMILES_AROMATIC_BRACKET_ELEMENTS: [(&[u8], Element); 9] = [(b"se", Element::Selenium), (b"as", Element::Arsenic), 
+    (b"te", Element::Tellurium), (b"b", Element::Boron), (b"c", Element::Carbon), (b"n", Element::Nitrogen), 
+    (b"o", Element::Oxygen), (b"p", Element::Phosphorus), (b"s", Element::Sulfur)];
 
 #[derive(Debug)]
 pub struct SMILESMisc {
     pub automatic_hydrogens_targets: Vec<usize>,
-    pub labels: BTreeMap<u32, usize>,
+    pub labels: BTreeMap<u32, RingOpening>,
     pub aromatic_ions: BTreeSet<usize>,
+    pub marks: BTreeMap<usize, bool>, // Directional bonds, true if it is `/` when going from a to b
+    pub ring_positions: BTreeMap<(usize, usize), usize>, // Position of the label of a ring bond on an atom
+    pub bracket_hydrogens: Vec<(usize, usize)>, // Hydrogens written inside the brackets of an atom
+    pub length: usize, // Length of the whole SMILES, to know the position of an error
+}
+
+impl SMILESMisc {
+    fn error(&self, at: &[u8], reason: ParseErrorReason) -> ParseError {
+        ParseError { position: self.length - at.len(), character: at.first().map(|c| *c as char), reason }
+    }
 }
 
-#[derive(Debug, Clone)]
-pub struct AromaticDetectionData {
-    pub init: bool,
-    pub last_one_was_double: bool,
+#[derive(Debug)]
+pub struct RingOpening {
+    pub atom: usize,
+    pub bond: Option<StructuralBond>,
+    pub mark: Option<bool>,
+    pub position: usize,
+    pub offset: usize, // Where the label is written
 }
 
-// aromatic_detection_data = Small structure composed of information to the formation of an aromatic ring,
-// bonds_in_the_probably_aromatic_ring = The atoms that possibly make up the aromatic ring that is being checked, this is restored by the function called
 #[allow(unused_assignments)]  // Rust compiler is buggy
 fn parse_smiles_group<'a>(mut string: &'a [u8], sf: &mut Molecule, misc: &mut SMILESMisc, 
-    mut adj_atom: Option<usize>, aromatic_detection_data: &mut AromaticDetectionData,
-    bonds_in_the_probably_aromatic_ring: &mut Vec<usize>) -> Result<&'a [u8], ()> {
+    mut adj_atom: Option<usize>) -> Result<&'a [u8], ParserError> {
     const SMILES_SPECIAL_UNICHR_ELEMENTS: [(u8, Element); 6] = [(b'F', Element::Fluorine), (b'I', Element::Iodine), 
         (b'N', Element::Nitrogen), (b'O', Element::Oxygen), (b'P', Element::Phosphorus), (b'S', Element::Sulfur)];
     let mut waiting_bond = None; // The bond is parse first and store here for the next ion, which takes it
-    let bond_count_in_the_probably_aromatic_ring = bonds_in_the_probably_aromatic_ring.len();
+    let mut waiting_mark = None; // The direction of the waiting bond, if it was written with `/` or `\`
+    let first_atom = sf.atoms.len(); // The atoms of this chain start here
+    macro_rules! fail {
+        ($at:expr, $reason:ident) => {
+            return Err(misc.error($at, ParseErrorReason::$reason).into())
+        };
+    }
     macro_rules! insert_ion {
         ($atom:expr, $is_aromatic:expr, $charge:expr, $isotopic_spec:expr) => {{
             let mut labels = Vec::new();
             while !string.is_empty() {
+                let offset = misc.length - string.len();
                 let mut bond = None;
+                let mut mark = None;
                 let mut pos = 0;
-                if string[0] == b':' || string[0] == b'-' || string[0] == b'=' || string[0] == b'#' {
+                if string[0] == b':' || string[0] == b'-' || string[0] == b'=' || string[0] == b'#' || 
+                    string[0] == b'/' || string[0] == b'\\' {
                     bond = Some(match string[0] {
                         b':' => StructuralBond::Aromatic,
-                        b'-' => StructuralBond::Single,
+                        b'-' | b'/' | b'\\' => StructuralBond::Single,
                         b'=' => StructuralBond::Double,
                         b'#' => StructuralBond::Triple,
                         _ => unsafe { unreachable_unchecked() }
                     });
+                    if string[0] == b'/' || string[0] == b'\\' {
+                        mark = Some(string[0] == b'/');
+                    }
                     pos += 1;
                     if string.len() == 1 {
-                        return Err(());
+                        fail!(&[], UnexpectedEnd);
                     }
                 }
                 if string[pos] >= b'0' && string[pos] <= b'9' {
                     // One-digit label
-                    labels.push(((string[pos] - b'0') as u32, bond));
+                    labels.push(((string[pos] - b'0') as u32, bond, mark, offset));
                     string = &string[pos+1..];
                 }
                 else if string[pos] == b'%' {
                     // Multi-digit label
                     string = &string[pos+1..];
                     if string.is_empty() || string[0] < b'0' || string[0] > b'9' {
-                        return Err(());
+                        fail!(string, BadRingLabel);
                     }
-                    let (n, s) = parse_number(string);
+                    let (n, s) = checked_parse_number(string);
+                    let n = match n.filter(|n| *n <= u32::MAX as usize) {
+                        Some(n) => n as u32,
+                        None => fail!(string, BadRingLabel)
+                    };
                     string = s;
-                    labels.push((n as u32, bond));
+                    labels.push((n, bond, mark, offset));
                 }
                 else {
                     break;
@@ -85,36 +120,17 @@ fn parse_smiles_group<'a>(mut string: &'a [u8], sf: &mut Molecule, misc: &mut SM
                 }
                 current_bounds.push(sf.bonds.len());
                 sf.atoms[adj_atom].bonds.push(sf.bonds.len());
-                if aromatic_detection_data.init {
-                    // Default believe there is no aromaticity
-                    aromatic_detection_data.init = false;
-                    if k == StructuralBond::Single || k == StructuralBond::Double {
-                        if ((k == StructuralBond::Single) == aromatic_detection_data.last_one_was_double) {
-                            // =X-C or -X=C bond
-                            // There is aromaticity, so far
-                            aromatic_detection_data.last_one_was_double = !aromatic_detection_data.last_one_was_double;
-                            aromatic_detection_data.init = true;
-                            bonds_in_the_probably_aromatic_ring.push(sf.bonds.len());
-                        }
-                    }
-                    if !aromatic_detection_data.init {
-                        bonds_in_the_probably_aromatic_ring.truncate(bond_count_in_the_probably_aromatic_ring);
-                    }
-                }
-                else {
-                    if k == StructuralBond::Single || k == StructuralBond::Double {
-                        aromatic_detection_data.init = true;
-                        aromatic_detection_data.last_one_was_double = k == StructuralBond::Double;
-                        bonds_in_the_probably_aromatic_ring.push(sf.bonds.len());
-                    }
+                if let Some(mark) = waiting_mark {
+                    misc.marks.insert(sf.bonds.len(), mark);
+                    waiting_mark = None;
                 }
-                sf.bonds.push(Bond { a: adj_atom, b: sf.atoms.len(), k });
+                sf.bonds.push(Bond::new(adj_atom, sf.atoms.len(), k));
                 waiting_bond = None;
             }
-            for label in labels {
-                if let Some(b) = misc.labels.get(&label.0) {
-                    let b = *b;
-                    let mut k = match label.1 {
+            for (position, label) in labels.into_iter().enumerate() {
+                if let Some(opening) = misc.labels.remove(&label.0) {
+                    let b = opening.atom;
+                    let k = match label.1.or(opening.bond) {
                         Some(k) => k,
                         None => {
                             if $is_aromatic && misc.aromatic_ions.contains(&b) {
@@ -125,42 +141,26 @@ fn parse_smiles_group<'a>(mut string: &'a [u8], sf: &mut Molecule, misc: &mut SM
                             }
                         }
                     };
-                    misc.labels.remove(&label.0);
-                    if aromatic_detection_data.init && bonds_in_the_probably_aromatic_ring.len() >= 4*1+2-1 {
-                        // Seek b atom in the possible ring
-                        let mut other_end = None;
-                        for (p, id) in bonds_in_the_probably_aromatic_ring.iter().enumerate() {
-                            if *id == b {
-                                other_end = Some(p);
-                                break;
-                            }
-                        }
-                        if let Some(other_end) = other_end {
-                            let length = bonds_in_the_probably_aromatic_ring.len() - other_end + 1;
-                            if (length - 2) % 4 == 0 { // Test if it complies the Hueckel's rule
-                                for i in bonds_in_the_probably_aromatic_ring.iter() {
-                                    let i = *i;
-                                    sf.bonds[i].k = StructuralBond::Aromatic;
-                                }
-                                k = StructuralBond::Aromatic;
-                                bonds_in_the_probably_aromatic_ring.clear();
-                                aromatic_detection_data.init = false;
-                            }
-                        }
+                    // The mark of the opening goes from b to this atom, the reverse of the bond
+                    if let Some(mark) = label.2.or(opening.mark.map(|m| !m)) {
+                        misc.marks.insert(sf.bonds.len(), mark);
                     }
+                    misc.ring_positions.insert((sf.atoms.len(), sf.bonds.len()), position);
+                    misc.ring_positions.insert((b, sf.bonds.len()), opening.position);
                     current_bounds.push(sf.bonds.len());
                     sf.atoms[b].bonds.push(sf.bonds.len());
-                    sf.bonds.push(Bond { a: sf.atoms.len(), b, k });
+                    sf.bonds.push(Bond::new(sf.atoms.len(), b, k));
                 }
                 else {
-                    misc.labels.insert(label.0, sf.atoms.len());
+                    misc.labels.insert(label.0, RingOpening { atom: sf.atoms.len(), bond: label.1, mark: label.2, position, 
+                        offset: label.3 });
                 }
             }
             if $is_aromatic {
                 misc.aromatic_ions.insert(sf.atoms.len());
             }
             adj_atom = Some(sf.atoms.len());
-            sf.atoms.push(AtomAndBondI { atom: Isotope::new(Ion::new($atom, $charge), $isotopic_spec), bonds: current_bounds });
+            sf.atoms.push(AtomAndBondI::new(Isotope::new(Ion::new($atom, $charge), $isotopic_spec), current_bounds));
         }};
     }
     if let Some(_) = adj_atom {
@@ -178,6 +178,11 @@ fn parse_smiles_group<'a>(mut string: &'a [u8], sf: &mut Molecule, misc: &mut SM
                     waiting_bond = Some(StructuralBond::Triple);
                     string = &string[1..];
                 },
+                b'/' | b'\\' => {
+                    waiting_bond = Some(StructuralBond::Single);
+                    waiting_mark = Some(string[0] == b'/');
+                    string = &string[1..];
+                },
                 _ => {}
             }
         }
@@ -187,6 +192,7 @@ fn parse_smiles_group<'a>(mut string: &'a [u8], sf: &mut Molecule, misc: &mut SM
         if chr == b')' {
             break;
         }
+        let current = string;
         string = &string[1..];
         if chr == b'B' {
             if string.is_empty() {
@@ -224,99 +230,177 @@ fn parse_smiles_group<'a>(mut string: &'a [u8], sf: &mut Molecule, misc: &mut SM
         }
         else if chr == b'[' {
             if string.is_empty() {
-                return Err(());
+                fail!(&[], UnexpectedEnd);
             }
-            let mut isotopic_spec = None;
+            let bracket = string;
+            let mut mass_number = None;
             if string[0] >= b'0' && string[0] <= b'9' {
                 // Isotopic specification
-                let (r, s) = parse_number(string);
-                string = s;
-                if r >= 256 {
-                    return Err(());
+                let (r, s) = checked_parse_number(string);
+                if r.is_none() {
+                    fail!(bracket, BadIsotope);
                 }
-                isotopic_spec = NonZeroU8::new(r as u8);
+                string = s;
+                mass_number = r;
                 if string.is_empty() {
-                    return Err(());
+                    fail!(&[], UnexpectedEnd);
                 }
             }
-            while string[0] != b']' {
-                if string.len() < 2 { // 1: X, 2: ]  "X]"
-                    return Err(());
+            if string.len() < 2 { // 1: X, 2: ]  "X]"
+                fail!(&[], UnexpectedEnd);
+            }
+            let mut e = WILDCARD_ELEMENT;
+            let mut aromatic = false;
+            let mut wildcard = false;
+            if string[0] == b'*' {
+                if mass_number.is_some() {
+                    fail!(bracket, BadIsotope);
                 }
-                let e;
-                let mut aromatic = false;
-                if string[0] == b'c' || string[0] == b'n' || string[0] == b'o' || string[0] == b's' {
-                    e = match string[0] {
-                        b'c' => Element::Carbon,
-                        b'n' => Element::Nitrogen,
-                        b'o' => Element::Phosphorus,
-                        b's' => Element::Sulfur,
-                        _ => unsafe { unreachable_unchecked() }
-                    };
-                    aromatic = true;
-                    string = &string[1..];
+                wildcard = true;
+                string = &string[1..];
+            }
+            else if let Some((_, element)) = SMILES_AROMATIC_BRACKET_ELEMENTS.iter().find(|(symbol, _)| string.starts_with(symbol)) {
+                // The two letter symbols go first on the list, so `se` is not taken as `s`
+                e = *element;
+                aromatic = true;
+                string = &string[e.get_symbol().len()..];
+            }
+            else {
+                if !string[0].is_ascii_uppercase() {
+                    fail!(string, UnknownElement);
                 }
-                else if string[1] >= b'a' && string[1] <= b'z' {
-                    e = match Element::from_symbol(unsafe { mem::transmute(&string[..2]) }) {
-                        Some(e) => e,
-                        None => return Err(())
-                    };
-                    string = &string[2..];
+                let mut end = 1;
+                if string[1].is_ascii_lowercase() && Element::from_symbol(unsafe { str::from_utf8_unchecked(&string[..2]) }).is_some() {
+                    end = 2;
+                }
+                e = match Element::from_symbol(unsafe { str::from_utf8_unchecked(&string[..end]) }) {
+                    Some(e) => e,
+                    None => fail!(string, UnknownElement)
+                };
+                string = &string[end..];
+            }
+            if string.is_empty() {
+                fail!(&[], UnexpectedEnd);
+            }
+            let mut chirality = None;
+            if string[0] == b'@' {
+                string = &string[1..];
+                if string.is_empty() {
+                    fail!(&[], UnexpectedEnd);
+                }
+                if string[0] == b'@' {
+                    string = &string[1..];
+                    chirality = Some(Chirality::Clockwise);
                     if string.is_empty() {
-                        return Err(());
+                        fail!(&[], UnexpectedEnd);
                     }
                 }
                 else {
-                    e = match Element::from_symbol(unsafe { mem::transmute(&string[..1]) }) {
-                        Some(e) => e,
-                        None => return Err(())
+                    chirality = Some(Chirality::Anticlockwise);
+                }
+            }
+            let mut hydrogens = 0;
+            if string[0] == b'H' {
+                string = &string[1..];
+                hydrogens = 1;
+                if !string.is_empty() && string[0].is_ascii_digit() {
+                    let count = string;
+                    let (n, s) = checked_parse_number(string);
+                    string = s;
+                    hydrogens = match n.filter(|n| *n <= 255) {
+                        Some(n) => n,
+                        None => fail!(count, BadHydrogenCount)
                     };
-                    string = &string[1..];
                 }
-                let mut charge = 0;
-                if string[0] == b'+' || string[0] == b'-' {
-                    let negative = string[0] == b'-';
-                    string = &string[1..];
+                if string.is_empty() {
+                    fail!(&[], UnexpectedEnd);
+                }
+            }
+            let mut charge = 0;
+            if string[0] == b'+' || string[0] == b'-' {
+                let negative = string[0] == b'-';
+                string = &string[1..];
+                if string.is_empty() {
+                    fail!(&[], UnexpectedEnd);
+                }
+                if string[0] >= b'0' && string[0] <= b'9' {
+                    // Specific with number
+                    let count = string;
+                    let (n, s) = checked_parse_number(string);
+                    string = s;
+                    charge = match n.filter(|n| *n <= 127) {
+                        Some(n) => n as i8,
+                        None => fail!(count, BadCharge)
+                    };
                     if string.is_empty() {
-                        return Err(());
+                        fail!(&[], UnexpectedEnd);
                     }
-                    if string[0] >= b'0' && string[0] <= b'9' {
-                        // Specific with number
-                        let (n, s) = parse_number(string);
-                        string = s;
-                        if string.is_empty() {
-                            return Err(());
-                        }
-                        if n > 127 {
-                            return Err(());
-                        }
-                        charge = n as i8;
-                    }
-                    else {
-                        // Specific with symbol count
-                        charge += 1;
-                        while !string.is_empty() {
-                            if string[0] != b'+' && string[0] != b'-' {
-                                break;
-                            }
-                            if (string[0] == b'-') != negative {
-                                // Weird charge specification, yield an error
-                                return Err(());
-                            }
-                            charge += 1;
+                }
+                else {
+                    // Specific with symbol count
+                    charge += 1;
+                    while !string.is_empty() {
+                        if string[0] != b'+' && string[0] != b'-' {
+                            break;
                         }
-                        if string.is_empty() {
-                            return Err(());
+                        if (string[0] == b'-') != negative {
+                            // Weird charge specification, yield an error
+                            fail!(string, BadCharge);
                         }
+                        charge = match charge.checked_add(1) {
+                            Some(charge) => charge,
+                            None => fail!(string, BadCharge)
+                        };
+                        string = &string[1..];
                     }
-                    if negative {
-                        charge = -charge;
+                    if string.is_empty() {
+                        fail!(&[], UnexpectedEnd);
                     }
                 }
-                insert_ion!(e, aromatic, charge, isotopic_spec);
-                isotopic_spec = None;
+                if negative {
+                    charge = -charge;
+                }
+            }
+            let mut class = None;
+            if string[0] == b':' {
+                string = &string[1..];
+                if string.is_empty() || !string[0].is_ascii_digit() {
+                    fail!(string, BadAtomClass);
+                }
+                let (n, s) = checked_parse_number(string);
+                class = match n.filter(|n| *n <= u32::MAX as usize) {
+                    Some(n) => Some(n as u32),
+                    None => fail!(string, BadAtomClass)
+                };
+                string = s;
+                if string.is_empty() {
+                    fail!(&[], UnexpectedEnd);
+                }
+            }
+            // The isotope stores the neutrons, not the mass number written in the SMILES
+            let isotopic_spec = match mass_number {
+                Some(m) => {
+                    if m < e.get_atomic_number() || m - e.get_atomic_number() > 255 {
+                        fail!(bracket, BadIsotope);
+                    }
+                    NonZeroU8::new((m - e.get_atomic_number()) as u8)
+                },
+                None => None
+            };
+            // The ring labels go after the bracket
+            if string[0] != b']' {
+                fail!(string, UnexpectedCharacter);
             }
             string = &string[1..];
+            if hydrogens > 0 {
+                misc.bracket_hydrogens.push((sf.atoms.len(), hydrogens));
+            }
+            insert_ion!(e, aromatic, charge, isotopic_spec);
+            let atom = sf.atoms.last_mut().unwrap();
+            atom.chirality = chirality;
+            atom.class = class;
+            atom.wildcard = wildcard;
+            atom.explicit_hydrogens = Some(hydrogens as u8);
         }
         else if chr >= b'A' && chr <= b'Z' {
             let mut l = 0;
@@ -335,6 +419,7 @@ fn parse_smiles_group<'a>(mut string: &'a [u8], sf: &mut Molecule, misc: &mut SM
                     r = m - 1;
                 }
             }
+            fail!(current, UnknownElement);
         }
         else if chr == b'c' {
             misc.automatic_hydrogens_targets.push(sf.atoms.len());
@@ -352,17 +437,33 @@ fn parse_smiles_group<'a>(mut string: &'a [u8], sf: &mut Molecule, misc: &mut SM
             misc.automatic_hydrogens_targets.push(sf.atoms.len());
             insert_ion!(Element::Nitrogen, true, 0, None);
         }
+        else if chr == b'b' {
+            misc.automatic_hydrogens_targets.push(sf.atoms.len());
+            insert_ion!(Element::Boron, true, 0, None);
+        }
+        else if chr == b'p' {
+            misc.automatic_hydrogens_targets.push(sf.atoms.len());
+            insert_ion!(Element::Phosphorus, true, 0, None);
+        }
+        else if chr == b'*' {
+            // The wildcard never takes hydrogens
+            insert_ion!(WILDCARD_ELEMENT, false, 0, None);
+            sf.atoms.last_mut().unwrap().wildcard = true;
+        }
         else if chr == b'(' {
-            // Branch
-            let mut new_data = aromatic_detection_data.clone();
-            string = parse_smiles_group(string, sf, misc, adj_atom, &mut new_data, bonds_in_the_probably_aromatic_ring)?;
-            if bonds_in_the_probably_aromatic_ring.is_empty() {
-                // Aromatic ring close or not started
-                aromatic_detection_data.init = false;
+            // Branch, only after an atom of this chain
+            if sf.atoms.len() == first_atom {
+                fail!(current, UnexpectedCharacter);
             }
+            let atoms = sf.atoms.len();
+            string = parse_smiles_group(string, sf, misc, adj_atom)?;
             // SMILES ends without close branch
             if string.is_empty() {
-                return Err(());
+                fail!(current, UnmatchedParenthesis);
+            }
+            // An empty branch, as `C()C`
+            if sf.atoms.len() == atoms {
+                fail!(string, UnexpectedCharacter);
             }
             string = &string[1..]
         }
@@ -385,73 +486,649 @@ fn parse_smiles_group<'a>(mut string: &'a [u8], sf: &mut Molecule, misc: &mut SM
                         waiting_bond = Some(StructuralBond::Triple);
                         continue;
                     },
+                    b'/' | b'\\' => {
+                        waiting_bond = Some(StructuralBond::Single);
+                        waiting_mark = Some(chr == b'/');
+                        continue;
+                    },
                     _ => {}
                 }
             }
-            return Err(());
+            fail!(current, UnexpectedCharacter);
         }
     }
-    bonds_in_the_probably_aromatic_ring.truncate(bond_count_in_the_probably_aromatic_ring);
+    if waiting_bond.is_some() {
+        fail!(string, DanglingBond);
+    }
     Ok(string)
 }
 
-pub fn parse(string: &str) -> Result<Molecule, ()> {
+/// Count the hydrogens that the parser adds to an atom written without brackets, given the kinds
+/// of the bonds that it already has
+fn implicit_hydrogens<I: Iterator<Item = StructuralBond>>(element: Element, bonds: I) -> isize {
+    let mut actual_valence = 0;
+    let mut aromatic = false;
+    for k in bonds {
+        if k == StructuralBond::Aromatic {
+            // An aromatic bond counts as single, the atom also takes one double bond of its ring
+            aromatic = true;
+            actual_valence += 1;
+        }
+        else {
+            actual_valence += k as isize;
+        }
+    }
+    // Only if its lowest valence leaves room for it, so `s`, `o` and the `n` with three neighbours
+    // (as on N-methylpyrrole) give their electrons to the ring instead
+    let lowest_valence = match element {
+        Element::Boron | Element::Nitrogen | Element::Phosphorus => 3,
+        Element::Carbon => 4,
+        Element::Oxygen | Element::Sulfur => 2,
+        _ => 1
+    };
+    if aromatic && actual_valence < lowest_valence {
+        actual_valence += 1;
+    }
+    match element {
+        Element::Boron => 3 - actual_valence,
+        Element::Carbon => 4 - actual_valence,
+        Element::Nitrogen | Element::Phosphorus => {
+            if actual_valence <= 3 {
+                3 - actual_valence
+            }
+            else if actual_valence <= 5 {
+                5 - actual_valence
+            }
+            else {
+                0
+            }
+        },
+        Element::Oxygen => 2 - actual_valence,
+        Element::Sulfur => {
+            if actual_valence <= 2 {
+                2 - actual_valence
+            }
+            else if actual_valence <= 4 {
+                4 - actual_valence
+            }
+            else if actual_valence <= 6 {
+                6 - actual_valence
+            }
+            else {
+                0
+            }
+        },
+        _ => 1 - actual_valence
+    }
+}
+
+pub fn parse(string: &str) -> Result<Molecule, ParserError> {
     let mut sf = Molecule { atoms: Vec::new(), bonds: Vec::new(), coords: None };
     let string = string.as_bytes();
     let mut misc = smiles::SMILESMisc { automatic_hydrogens_targets: Vec::new(), 
-        labels: BTreeMap::new(), aromatic_ions: BTreeSet::new() };
-    let _ = smiles::parse_smiles_group(string, &mut sf, &mut misc, None, &mut smiles::AromaticDetectionData { init: false, 
-        last_one_was_double: false }, &mut Vec::new())?;
+        labels: BTreeMap::new(), aromatic_ions: BTreeSet::new(), marks: BTreeMap::new(), ring_positions: BTreeMap::new(), 
+        bracket_hydrogens: Vec::new(), length: string.len() };
+    let rest = smiles::parse_smiles_group(string, &mut sf, &mut misc, None)?;
+    if !rest.is_empty() {
+        // A `)` without its `(`
+        return Err(misc.error(rest, ParseErrorReason::UnmatchedParenthesis).into());
+    }
+    if sf.atoms.is_empty() {
+        return Err(misc.error(rest, ParseErrorReason::UnexpectedEnd).into());
+    }
+    if let Some(opening) = misc.labels.values().map(|l| l.offset).min() {
+        return Err(misc.error(&string[opening..], ParseErrorReason::UnclosedRing).into());
+    }
     for atom in misc.automatic_hydrogens_targets {
-        let mut actual_valence = 0;
-        let mut aromatic_flip_flop = true;
-        for bond in sf.atoms[atom].bonds.iter() {
-            if sf.bonds[*bond].k == StructuralBond::Aromatic {
-                actual_valence += 1;
-                if aromatic_flip_flop {
-                    actual_valence += 1;
-                }
-                aromatic_flip_flop = !aromatic_flip_flop;
+        let lack = implicit_hydrogens(*sf.atoms[atom].get_element(), 
+            sf.atoms[atom].bonds.iter().map(|b| sf.bonds[*b].k));
+        for _ in 0..lack {
+            let b = vec![sf.bonds.len()];
+            sf.atoms[atom].bonds.push(sf.bonds.len());
+            sf.bonds.push(Bond::new(atom, sf.atoms.len(), StructuralBond::Single));
+            sf.atoms.push(AtomAndBondI::new(Isotope::from(Element::Hydrogen), b));
+        }
+    }
+    let mut hydrogen_bonds = BTreeSet::new();
+    for (atom, count) in misc.bracket_hydrogens {
+        for _ in 0..count {
+            hydrogen_bonds.insert(sf.bonds.len());
+            let b = vec![sf.bonds.len()];
+            sf.atoms[atom].bonds.push(sf.bonds.len());
+            sf.bonds.push(Bond::new(atom, sf.atoms.len(), StructuralBond::Single));
+            sf.atoms.push(AtomAndBondI::new(Isotope::from(Element::Hydrogen), b));
+        }
+    }
+    let (ring_positions, marks) = (misc.ring_positions, misc.marks);
+    for id in 0..sf.atoms.len() {
+        if let Some(chirality) = sf.atoms[id].chirality {
+            // Neighbours as they were written: the previous atom, the hydrogens of the brackets, the ring
+            // bonds and then the rest
+            let mut written = sf.atoms[id].bonds.clone();
+            written.sort_by_key(|b| match ring_positions.get(&(id, *b)) {
+                Some(position) => (2, *position),
+                None if hydrogen_bonds.contains(b) => (1, *b),
+                None if sf.bonds[*b].b == id => (0, 0),
+                None => (3, *b)
+            });
+            if is_odd_permutation(&written, &sf.atoms[id].bonds) {
+                sf.atoms[id].chirality = Some(chirality.inverted());
             }
-            actual_valence += sf.bonds[*bond].k as isize;
         }
-        let lack = match sf.atoms[atom].get_element() {
-            Element::Boron => 3 - actual_valence,
-            Element::Carbon => 4 - actual_valence,
-            Element::Nitrogen | Element::Phosphorus => {
-                if actual_valence <= 3 {
-                    3 - actual_valence
-                }
-                else if actual_valence <= 5 {
-                    5 - actual_valence
+    }
+    for id in 0..sf.bonds.len() {
+        if sf.bonds[id].k != StructuralBond::Double {
+            continue;
+        }
+        // Side of each end: the marked neighbour bond, its direction seen from the end and the first neighbour bond
+        let mut sides = Vec::with_capacity(2);
+        for end in [sf.bonds[id].a, sf.bonds[id].b].iter() {
+            let others: Vec<usize> = sf.atoms[*end].bonds.iter().cloned().filter(|b| *b != id).collect();
+            let marked = others.iter().find_map(|b| marks.get(b).map(|m| (*b, if sf.bonds[*b].a == *end { *m } else { !*m })));
+            if let (Some((bond, up)), Some(first)) = (marked, others.first()) {
+                sides.push((bond, up, *first));
+            }
+        }
+        if sides.len() == 2 {
+            let cis = (sides[0].1 == sides[1].1) ^ (sides[0].0 != sides[0].2) ^ (sides[1].0 != sides[1].2);
+            sf.bonds[id].stereo = Some(if cis { BondStereo::Cis } else { BondStereo::Trans });
+        }
+    }
+    // The hydrogens are already counted, so the rings written with single and double bonds can be aromatic now
+    sf.perceive_aromaticity(AromaticityModel::Daylight);
+    Ok(sf)
+}
+
+/// Check if going from `reference` to `order` (both with the same items) needs an odd number of swaps
+pub(crate) fn is_odd_permutation(order: &[usize], reference: &[usize]) -> bool {
+    let positions: Vec<usize> = order.iter().map(|i| reference.iter().position(|r| r == i).unwrap()).collect();
+    let mut odd = false;
+    for i in 0..positions.len() {
+        for j in i + 1..positions.len() {
+            if positions[i] > positions[j] {
+                odd = !odd;
+            }
+        }
+    }
+    odd
+}
+pub(crate) const SMILES_ORGANIC_SUBSET: [Element; 10] = [Element::Boron, Element::Carbon, Element::Nitrogen, Element::Oxygen, 
+    Element::Phosphorus, Element::Sulfur, Element::Fluorine, Element::Chlorine, Element::Bromine, Element::Iodine];
+
+struct SMILESWriter<'a> {
+    molecule: &'a Molecule,
+    hidden: Vec<bool>, // Hydrogens that are implied by their neighbour
+    hydrogens: Vec<usize>, // Hidden hydrogens of each atom
+    bracket: Vec<bool>,
+    aromatic: Vec<bool>, // Atoms written in lowercase
+    visited: Vec<bool>,
+    children: Vec<Vec<(usize, usize)>>, // (bond, atom) pairs of the spanning tree
+    ring_bonds: Vec<Vec<usize>>,
+    is_ring_bond: Vec<bool>,
+    labels: BTreeMap<usize, u32>, // Open ring bonds and their labels
+    ranks: Option<Vec<usize>>, // Canonical order of the atoms, if any
+    parent: Vec<Option<usize>>, // Bond with the previous atom in the spanning tree
+    marks: Vec<Option<bool>>, // Directional bonds, true if it is written as `/`
+}
+
+impl<'a> SMILESWriter<'a> {
+    fn new(molecule: &'a Molecule) -> SMILESWriter<'a> {
+        let atoms_count = molecule.atoms.len();
+        let mut writer = SMILESWriter { molecule, hidden: vec![false; atoms_count], hydrogens: vec![0; atoms_count], 
+            bracket: vec![false; atoms_count], 
+            aromatic: vec![false; atoms_count], visited: vec![false; atoms_count], children: vec![Vec::new(); atoms_count], 
+            ring_bonds: vec![Vec::new(); atoms_count], is_ring_bond: vec![false; molecule.bonds.len()], labels: BTreeMap::new(), 
+            ranks: None, parent: vec![None; atoms_count], marks: vec![None; molecule.bonds.len()] };
+        for (id, atom) in molecule.atoms.iter().enumerate() {
+            if *atom.get_element() != Element::Hydrogen || *atom.get_ion().get_charge() != 0 || 
+                atom.get_neutrons_count().is_some() || atom.bonds.len() != 1 || atom.wildcard || 
+                atom.class.is_some() || atom.chirality.is_some() {
+                continue;
+            }
+            let bond = molecule.bonds[atom.bonds[0]];
+            let pair = if bond.a == id { bond.b } else { bond.a };
+            if bond.k == StructuralBond::Single && *molecule.atoms[pair].get_element() != Element::Hydrogen && 
+                !molecule.atoms[pair].wildcard {
+                writer.hidden[id] = true;
+            }
+        }
+        for (id, atom) in molecule.atoms.iter().enumerate() {
+            if writer.hidden[id] {
+                continue;
+            }
+            let mut kinds = Vec::new();
+            for bond in atom.bonds.iter() {
+                let bond = molecule.bonds[*bond];
+                let pair = if bond.a == id { bond.b } else { bond.a };
+                if writer.hidden[pair] {
+                    writer.hydrogens[id] += 1;
                 }
                 else {
-                    0
+                    kinds.push(bond.k);
+                }
+            }
+            let element = *atom.get_element();
+            writer.aromatic[id] = !atom.wildcard && kinds.contains(&StructuralBond::Aromatic) && 
+                SMILES_AROMATIC_BRACKET_ELEMENTS.iter().any(|(_, e)| *e == element);
+            let plain = *atom.get_ion().get_charge() == 0 && atom.get_neutrons_count().is_none() && 
+                atom.chirality.is_none() && atom.class.is_none();
+            writer.bracket[id] = if atom.wildcard {
+                // The parser never adds hydrogens to the wildcard
+                !plain || writer.hydrogens[id] != 0
+            }
+            else {
+                // The parser would not add these hydrogens by itself, so they are written inside the brackets
+                !plain || !SMILES_ORGANIC_SUBSET.contains(&element) || 
+                    implicit_hydrogens(element, kinds.into_iter()).max(0) as usize != writer.hydrogens[id]
+            };
+        }
+        writer
+    }
+
+    fn build_tree(&mut self, id: usize, parent_bond: Option<usize>) {
+        self.visited[id] = true;
+        self.parent[id] = parent_bond;
+        let mut bonds = self.molecule.atoms[id].bonds.clone();
+        if let Some(ref ranks) = self.ranks {
+            let molecule = self.molecule;
+            bonds.sort_by_key(|b| {
+                let bond = molecule.bonds[*b];
+                ranks[if bond.a == id { bond.b } else { bond.a }]
+            });
+        }
+        for bond_id in bonds {
+            if Some(bond_id) == parent_bond || self.is_ring_bond[bond_id] {
+                continue;
+            }
+            let bond = self.molecule.bonds[bond_id];
+            let pair = if bond.a == id { bond.b } else { bond.a };
+            if self.hidden[pair] {
+                continue;
+            }
+            if self.visited[pair] {
+                // Back edge, it closes a ring
+                self.is_ring_bond[bond_id] = true;
+                self.ring_bonds[id].push(bond_id);
+                self.ring_bonds[pair].push(bond_id);
+                continue;
+            }
+            self.children[id].push((bond_id, pair));
+            self.build_tree(pair, Some(bond_id));
+        }
+    }
+
+    /// Bonds in the order in which they are written, with the atom that is written first
+    fn written_bonds(&self, id: usize, seen: &mut Vec<bool>, order: &mut Vec<(usize, usize)>) {
+        seen[id] = true;
+        for bond_id in self.ring_bonds[id].iter() {
+            let bond = self.molecule.bonds[*bond_id];
+            if seen[if bond.a == id { bond.b } else { bond.a }] {
+                // Ring closure, it goes from this atom to the one which opened it
+                order.push((*bond_id, id));
+            }
+        }
+        for (bond_id, pair) in self.children[id].iter() {
+            order.push((*bond_id, id));
+            self.written_bonds(*pair, seen, order);
+        }
+    }
+
+    /// Choose the directional bonds (`/` and `\\`) around the double bonds with a configuration
+    fn assign_marks(&mut self, root: usize) {
+        let molecule = self.molecule;
+        let mut order = Vec::new();
+        self.written_bonds(root, &mut vec![false; molecule.atoms.len()], &mut order);
+        let mut positions = vec![None; molecule.bonds.len()];
+        let mut from = vec![0; molecule.bonds.len()];
+        for (position, (bond_id, atom)) in order.iter().enumerate() {
+            positions[*bond_id] = Some(position);
+            from[*bond_id] = *atom;
+        }
+        for (bond_id, _) in order.iter() {
+            let bond = molecule.bonds[*bond_id];
+            let stereo = match bond.stereo {
+                Some(stereo) if bond.k == StructuralBond::Double => stereo,
+                _ => continue
+            };
+            let mut sides = Vec::with_capacity(2);
+            for end in [bond.a, bond.b].iter() {
+                let others: Vec<usize> = molecule.atoms[*end].bonds.iter().cloned().filter(|b| b != bond_id).collect();
+                let mut candidates: Vec<usize> = others.iter().cloned().filter(|b| positions[*b].is_some() && 
+                    molecule.bonds[*b].k == StructuralBond::Single).collect();
+                // Reuse a bond that already has a direction, if there is one
+                candidates.sort_by_key(|b| (self.marks[*b].is_none(), positions[*b]));
+                if let (Some(chosen), Some(first)) = (candidates.first(), others.first()) {
+                    sides.push((*end, *chosen, *first));
                 }
-            },
-            Element::Oxygen => 2 - actual_valence,
-            Element::Sulfur => {
-                if actual_valence <= 2 {
-                    2 - actual_valence
+            }
+            if sides.len() != 2 {
+                continue;
+            }
+            let cis = (stereo == BondStereo::Cis) ^ (sides[0].1 != sides[0].2) ^ (sides[1].1 != sides[1].2);
+            // The direction of a bond seen from an end of the double bond
+            let outwards = |end: usize, b: usize, mark: bool| if from[b] == end { mark } else { !mark };
+            let (x, y) = (sides[0].1, sides[1].1);
+            match (self.marks[x], self.marks[y]) {
+                (None, None) => {
+                    let (first, second) = if positions[x] < positions[y] { (0, 1) } else { (1, 0) };
+                    let up = outwards(sides[first].0, sides[first].1, true);
+                    self.marks[sides[first].1] = Some(true);
+                    self.marks[sides[second].1] = Some(outwards(sides[second].0, sides[second].1, up == cis));
+                },
+                (Some(mark), None) => {
+                    let up = outwards(sides[0].0, x, mark);
+                    self.marks[y] = Some(outwards(sides[1].0, y, up == cis));
+                },
+                (None, Some(mark)) => {
+                    let up = outwards(sides[1].0, y, mark);
+                    self.marks[x] = Some(outwards(sides[0].0, x, up == cis));
+                },
+                (Some(_), Some(_)) => {}
+            }
+        }
+    }
+
+    fn write_bond(&self, out: &mut String, bond_id: usize) {
+        let bond = self.molecule.bonds[bond_id];
+        let both_aromatic = self.aromatic[bond.a] && self.aromatic[bond.b];
+        if let Some(mark) = self.marks[bond_id] {
+            out.push(if mark { '/' } else { '\\' });
+            return;
+        }
+        match bond.k {
+            StructuralBond::Aromatic if !both_aromatic => out.push(':'),
+            StructuralBond::Single if both_aromatic => out.push('-'),
+            StructuralBond::Double => out.push('='),
+            StructuralBond::Triple => out.push('#'),
+            _ => {}
+        }
+    }
+
+    fn write_atom(&mut self, out: &mut String, id: usize) {
+        let mut labels = Vec::new();
+        for bond_id in self.ring_bonds[id].clone() {
+            if let Some(label) = self.labels.remove(&bond_id) {
+                // Ring closure
+                let mut text = String::new();
+                self.write_bond(&mut text, bond_id);
+                labels.push((label, text, bond_id));
+            }
+            else {
+                // Ring opening, take the lowest free label
+                let mut label = 1;
+                while self.labels.values().any(|l| *l == label) || labels.iter().any(|l| l.0 == label) {
+                    label += 1;
                 }
-                else if actual_valence <= 4 {
-                    4 - actual_valence
+                self.labels.insert(bond_id, label);
+                labels.push((label, String::new(), bond_id));
+            }
+        }
+        // One-digit labels first, so that a digit never follows a multi-digit label
+        labels.sort();
+        let atom = &self.molecule.atoms[id];
+        let symbol = if atom.wildcard { "*" } else { atom.get_element().get_symbol() };
+        let symbol = if self.aromatic[id] { symbol.to_lowercase() } else { symbol.to_owned() };
+        if self.bracket[id] {
+            out.push('[');
+            if let Some(neutrons) = atom.get_neutrons_count() {
+                out.push_str(&(neutrons.get() as usize + atom.get_element().get_atomic_number()).to_string());
+            }
+            out.push_str(&symbol);
+            if let Some(mut chirality) = atom.chirality {
+                // Neighbours in the order in which they are written
+                let molecule = self.molecule;
+                let hidden = &self.hidden;
+                let mut written: Vec<usize> = self.parent[id].into_iter().collect();
+                written.extend(atom.bonds.iter().filter(|b| {
+                    let bond = molecule.bonds[**b];
+                    hidden[if bond.a == id { bond.b } else { bond.a }]
+                }));
+                written.extend(labels.iter().map(|l| l.2));
+                written.extend(self.children[id].iter().map(|c| c.0));
+                if is_odd_permutation(&written, &atom.bonds) {
+                    chirality = chirality.inverted();
                 }
-                else if actual_valence <= 6 {
-                    6 - actual_valence
+                out.push_str(match chirality {
+                    Chirality::Anticlockwise => "@",
+                    Chirality::Clockwise => "@@"
+                });
+            }
+            if self.hydrogens[id] > 0 {
+                out.push('H');
+                if self.hydrogens[id] > 1 {
+                    out.push_str(&self.hydrogens[id].to_string());
+                }
+            }
+            let charge = *atom.get_ion().get_charge();
+            if charge > 0 {
+                out.push('+');
+            }
+            else if charge < 0 {
+                out.push('-');
+            }
+            if charge.abs() > 1 {
+                out.push_str(&charge.abs().to_string());
+            }
+            if let Some(class) = atom.class {
+                out.push(':');
+                out.push_str(&class.to_string());
+            }
+            out.push(']');
+        }
+        else {
+            out.push_str(&symbol);
+        }
+        for (label, bond, _) in labels {
+            out.push_str(&bond);
+            if label < 10 {
+                out.push_str(&label.to_string());
+            }
+            else {
+                out.push('%');
+                out.push_str(&label.to_string());
+            }
+        }
+        let children = self.children[id].clone();
+        for (i, (bond_id, pair)) in children.iter().enumerate() {
+            let branch = i + 1 != children.len();
+            if branch {
+                out.push('(');
+            }
+            self.write_bond(out, *bond_id);
+            self.write_atom(out, *pair);
+            if branch {
+                out.push(')');
+            }
+        }
+    }
+
+    /// Rank the visible atoms by their invariants refined with the ranks of the neighbours (Morgan
+    /// algorithm) and with their configurations, the atoms with the same rank are symmetric. The hidden
+    /// hydrogens are left with `usize::MAX`. It also gives the visible atoms, their visible neighbours
+    /// and the number of ranks
+    fn symmetry_ranks(&self) -> (Vec<usize>, Vec<usize>, Neighbours, usize) {
+        let molecule = self.molecule;
+        let atoms: Vec<usize> = (0..molecule.atoms.len()).filter(|id| !self.hidden[*id]).collect();
+        let mut ranks = vec![usize::MAX; molecule.atoms.len()];
+        let mut neighbours = vec![Vec::new(); molecule.atoms.len()];
+        let mut invariants = Vec::with_capacity(atoms.len());
+        for id in atoms.iter() {
+            let id = *id;
+            let atom = &molecule.atoms[id];
+            let mut hydrogens = 0;
+            for bond_id in atom.bonds.iter() {
+                let bond = molecule.bonds[*bond_id];
+                let pair = if bond.a == id { bond.b } else { bond.a };
+                if self.hidden[pair] {
+                    hydrogens += 1;
                 }
                 else {
-                    0
+                    neighbours[id].push((pair, bond.k));
                 }
-            },
-            _ => 1 - actual_valence
+            }
+            invariants.push((id, (atom.wildcard, atom.get_element().get_atomic_number(), atom.get_neutrons_count().map_or(0, |n| n.get()), 
+                *atom.get_ion().get_charge(), neighbours[id].len(), hydrogens, self.aromatic[id], atom.class)));
+        }
+        let classes = assign_ranks(&mut ranks, invariants);
+        let classes = refine_ranks(molecule, &mut ranks, &atoms, &neighbours, classes);
+        (ranks, atoms, neighbours, classes)
+    }
+
+    /// Rank the visible atoms so that the ranks only depend on the structure and not on the order
+    /// in which the atoms were read, it refines the atom invariants with the ranks of the neighbours
+    /// (Morgan algorithm) and breaks the remaining ties as CANON does. Gives the ranks of every
+    /// way of breaking the ties that has to be tried
+    fn rank_atoms(&self) -> Vec<Vec<usize>> {
+        let (ranks, atoms, neighbours, classes) = self.symmetry_ranks();
+        let mut found = Vec::new();
+        break_ties(self.molecule, ranks, &atoms, &neighbours, classes, &mut found);
+        found
+    }
+}
+
+/// Break the lowest tie and the following ones. The tied atoms are symmetric, but while some
+/// configuration can't be told from the ranks (as in the meso compounds), taking one atom or
+/// another can give the mirror image, so every one is tried
+fn break_ties(molecule: &Molecule, ranks: Vec<usize>, atoms: &[usize], neighbours: &Neighbours, classes: usize, 
+    found: &mut Vec<Vec<usize>>) {
+    if classes == atoms.len() {
+        found.push(ranks);
+        return;
+    }
+    let mut counts = vec![0; classes];
+    for id in atoms.iter() {
+        counts[ranks[*id]] += 1;
+    }
+    let tied = counts.iter().position(|c| *c > 1).unwrap();
+    let undefined = atoms.iter().any(|id| {
+        let (centre, double_bonds) = stereo_invariants(molecule, &ranks, *id);
+        centre == Some(None) || double_bonds.contains(&None)
+    });
+    for chosen in atoms.iter().filter(|id| ranks[**id] == tied) {
+        let mut ranks = ranks.clone();
+        let invariants = atoms.iter().map(|id| (*id, (ranks[*id], id != chosen))).collect();
+        let classes = assign_ranks(&mut ranks, invariants);
+        let classes = refine_ranks(molecule, &mut ranks, atoms, neighbours, classes);
+        break_ties(molecule, ranks, atoms, neighbours, classes, found);
+        if !undefined {
+            break;
+        }
+    }
+}
+
+/// Configurations of an atom relative to the ranks of its neighbours, so that they don't depend on
+/// the order of the bonds: the parity of the centre with the neighbours sorted by rank and, for each
+/// double bond with a configuration, if the lowest ranked neighbours of the ends are cis. A
+/// configuration is `None` while the neighbours it refers to are tied
+fn stereo_invariants(molecule: &Molecule, ranks: &[usize], id: usize) -> (Option<Option<bool>>, Vec<Option<bool>>) {
+    let atom = &molecule.atoms[id];
+    let rank = |bond_id: usize, from: usize| {
+        let bond = molecule.bonds[bond_id];
+        ranks[if bond.a == from { bond.b } else { bond.a }]
+    };
+    let centre = atom.chirality.map(|chirality| {
+        let mut sorted = atom.bonds.clone();
+        sorted.sort_by_key(|b| rank(*b, id));
+        if sorted.windows(2).any(|w| rank(w[0], id) == rank(w[1], id)) {
+            return None;
+        }
+        Some((chirality == Chirality::Clockwise) ^ is_odd_permutation(&sorted, &atom.bonds))
+    });
+    let mut double_bonds = Vec::new();
+    for bond_id in atom.bonds.iter() {
+        let bond = molecule.bonds[*bond_id];
+        let stereo = match bond.stereo {
+            Some(stereo) if bond.k == StructuralBond::Double => stereo,
+            _ => continue
         };
-        for _ in 0..lack {
-            let b = vec![sf.bonds.len()];
-            sf.atoms[atom].bonds.push(sf.bonds.len());
-            sf.bonds.push(Bond { a: atom, b: sf.atoms.len(), k: StructuralBond::Single });
-            sf.atoms.push(AtomAndBondI { atom: Isotope::from(Element::Hydrogen), bonds: b });
+        // The configuration refers to the first neighbour of each end
+        let mut cis = Some(stereo == BondStereo::Cis);
+        for end in [bond.a, bond.b].iter() {
+            let others: Vec<usize> = molecule.atoms[*end].bonds.iter().cloned().filter(|b| b != bond_id).collect();
+            let lowest = others.iter().cloned().min_by_key(|b| rank(*b, *end));
+            match (others.first(), lowest) {
+                (Some(first), Some(lowest)) if others.iter().filter(|b| rank(**b, *end) == rank(lowest, *end)).count() == 1 => {
+                    cis = cis.map(|cis| cis ^ (*first != lowest));
+                },
+                _ => cis = None
+            }
         }
+        double_bonds.push(cis);
     }
-    Ok(sf)
-}
\ No newline at end of file
+    double_bonds.sort();
+    (centre, double_bonds)
+}
+
+/// Visible neighbours of every atom, with the kind of the bond to each one
+type Neighbours = Vec<Vec<(usize, StructuralBond)>>;
+
+/// Refine the ranks with the ones of the neighbours and the configurations until the number of
+/// classes does not grow anymore, returns the final number
+fn refine_ranks(molecule: &Molecule, ranks: &mut [usize], atoms: &[usize], neighbours: &Neighbours, mut classes: usize) -> usize {
+    loop {
+        let mut invariants = Vec::with_capacity(atoms.len());
+        for id in atoms.iter() {
+            let mut around: Vec<(usize, StructuralBond)> = neighbours[*id].iter().map(|(pair, k)| (ranks[*pair], *k)).collect();
+            around.sort();
+            invariants.push((*id, (ranks[*id], around, stereo_invariants(molecule, ranks, *id))));
+        }
+        let refined = assign_ranks(ranks, invariants);
+        if refined == classes {
+            return classes;
+        }
+        classes = refined;
+    }
+}
+
+/// Symmetry class of every atom, the atoms of the same class can't be told apart from the structure.
+/// The hydrogens bonded to a heavy atom all share the class `usize::MAX`
+pub(crate) fn symmetry_classes(molecule: &Molecule) -> Vec<usize> {
+    SMILESWriter::new(molecule).symmetry_ranks().0
+}
+
+/// Give to each atom the position of its invariant among the distinct ones, returns the number of
+/// distinct invariants
+fn assign_ranks<T: Ord>(ranks: &mut [usize], mut invariants: Vec<(usize, T)>) -> usize {
+    invariants.sort_by(|a, b| a.1.cmp(&b.1));
+    let mut rank = 0;
+    for i in 0..invariants.len() {
+        if i > 0 && invariants[i].1 != invariants[i - 1].1 {
+            rank += 1;
+        }
+        ranks[invariants[i].0] = rank;
+    }
+    if invariants.is_empty() { 0 } else { rank + 1 }
+}
+
+fn write_with(mut writer: SMILESWriter, order: Vec<usize>) -> String {
+    let mut out = String::new();
+    for id in order {
+        if writer.hidden[id] || writer.visited[id] {
+            continue;
+        }
+        if !out.is_empty() {
+            // Disconnected fragment
+            out.push('.');
+        }
+        writer.build_tree(id, None);
+        writer.assign_marks(id);
+        writer.write_atom(&mut out, id);
+    }
+    out
+}
+
+pub fn write(molecule: &Molecule) -> String {
+    write_with(SMILESWriter::new(molecule), (0..molecule.atoms.len()).collect())
+}
+
+/// Write a SMILES that is the same for any order of the atoms and the bonds of the molecule, when
+/// the ties can be broken in several ways the lowest of the strings is taken
+pub fn write_canonical(molecule: &Molecule) -> String {
+    SMILESWriter::new(molecule).rank_atoms().into_iter().map(|ranks| {
+        let mut writer = SMILESWriter::new(molecule);
+        let mut order: Vec<usize> = (0..molecule.atoms.len()).collect();
+        order.sort_by_key(|id| ranks[*id]);
+        writer.ranks = Some(ranks);
+        write_with(writer, order)
+    }).min().unwrap_or_default()
+}
@@ -0,0 +1,216 @@
+use ptable::Element;
+
+use super::{Molecule, StructuralBond};
+
+/// A graph that can be searched inside a molecule
+pub(crate) trait Query {
+    fn atoms_count(&self) -> usize;
+
+    /// Bonds of the atom as pairs of bond and neighbour atom
+    fn neighbours(&self, atom: usize) -> Vec<(usize, usize)>;
+
+    fn atom_matches(&self, atom: usize, target: &Molecule, target_atom: usize) -> bool;
+
+    fn bond_matches(&self, bond: usize, target: &Molecule, target_bond: usize) -> bool;
+
+    /// Atoms that are left out of the search
+    fn is_ignored(&self, _atom: usize) -> bool {
+        false
+    }
+}
+
+impl Query for Molecule {
+    fn atoms_count(&self) -> usize {
+        self.atoms.len()
+    }
+
+    fn neighbours(&self, atom: usize) -> Vec<(usize, usize)> {
+        self.atoms[atom].bonds.iter().map(|b| {
+            let bond = self.bonds[*b];
+            (*b, if bond.a == atom { bond.b } else { bond.a })
+        }).collect()
+    }
+
+    fn atom_matches(&self, atom: usize, target: &Molecule, target_atom: usize) -> bool {
+        let (query, target) = (&self.atoms[atom], &target.atoms[target_atom]);
+        if query.wildcard {
+            return match query.atom_list {
+                Some(ref list) => !target.wildcard && list.contains(*target.get_element()),
+                None => true
+            };
+        }
+        !target.wildcard && query.get_element() == target.get_element() &&
+            query.get_ion().get_charge() == target.get_ion().get_charge() &&
+            (query.get_neutrons_count().is_none() || query.get_neutrons_count() == target.get_neutrons_count())
+    }
+
+    fn bond_matches(&self, bond: usize, target: &Molecule, target_bond: usize) -> bool {
+        self.bonds[bond].k == target.bonds[target_bond].k
+    }
+
+    /// The hydrogens of a heavy atom, as the ones that are added to the atoms of a SMILES, would
+    /// only match the atoms of the target with the same number of hydrogens
+    fn is_ignored(&self, atom: usize) -> bool {
+        let atom_data = &self.atoms[atom];
+        *atom_data.get_element() == Element::Hydrogen && !atom_data.wildcard &&
+            atom_data.get_neutrons_count().is_none() && *atom_data.get_ion().get_charge() == 0 &&
+            atom_data.bonds.len() == 1 && self.neighbours(atom).iter().all(|(b, n)| {
+                self.bonds[*b].k == StructuralBond::Single && *self.atoms[*n].get_element() != Element::Hydrogen
+            })
+    }
+}
+
+struct Search<'a, Q: Query> {
+    query: &'a Q,
+    target: &'a Molecule,
+    /// Query atoms in the order in which they are mapped, with a neighbour that is mapped before
+    order: Vec<(usize, Option<usize>)>,
+    neighbours: Vec<Vec<(usize, usize)>>,
+    mapping: Vec<Option<usize>>,
+    used: Vec<bool>,
+    /// Terminal sets of VF2: the depth plus one at which an atom became a neighbour of the mapped
+    /// atoms, 0 if it isn't one
+    query_terminal: Vec<usize>,
+    target_terminal: Vec<usize>,
+    first_only: bool,
+    /// Target atom for the first atom of the order
+    root: Option<usize>,
+    matches: Vec<Vec<Option<usize>>>,
+}
+
+impl<'a, Q: Query> Search<'a, Q> {
+    /// The search starts with the given query atom, or the first one
+    fn new(query: &'a Q, target: &'a Molecule, first_only: bool, start: Option<usize>) -> Search<'a, Q> {
+        let count = query.atoms_count();
+        let neighbours: Vec<Vec<(usize, usize)>> = (0..count).map(|a| {
+            query.neighbours(a).into_iter().filter(|(_, n)| !query.is_ignored(*n)).collect()
+        }).collect();
+        // Breadth first, so every atom but the first of each fragment has a neighbour already mapped
+        let mut order = Vec::with_capacity(count);
+        let mut seen = vec![false; count];
+        for root in start.into_iter().chain(0..count) {
+            if seen[root] || query.is_ignored(root) {
+                continue;
+            }
+            seen[root] = true;
+            let start = order.len();
+            order.push((root, None));
+            let mut i = start;
+            while i < order.len() {
+                let atom = order[i].0;
+                for (_, n) in neighbours[atom].iter() {
+                    if !seen[*n] {
+                        seen[*n] = true;
+                        order.push((*n, Some(atom)));
+                    }
+                }
+                i += 1;
+            }
+        }
+        Search { query, target, order, neighbours, mapping: vec![None; count], used: vec![false; target.atoms.len()],
+            query_terminal: vec![0; count], target_terminal: vec![0; target.atoms.len()], first_only, root: None,
+            matches: Vec::new() }
+    }
+
+    fn target_neighbours(&self, target_atom: usize) -> impl Iterator<Item = usize> + 'a {
+        let target = self.target;
+        target.atoms[target_atom].bonds.iter().map(move |b| {
+            let bond = target.bonds[*b];
+            if bond.a == target_atom { bond.b } else { bond.a }
+        })
+    }
+
+    fn feasible(&self, atom: usize, target_atom: usize) -> bool {
+        if self.used[target_atom] || self.target.atoms[target_atom].bonds.len() < self.neighbours[atom].len() ||
+            !self.query.atom_matches(atom, self.target, target_atom) {
+            return false;
+        }
+        // The bonds to the atoms already mapped must be in the target too
+        let bonds = self.neighbours[atom].iter().all(|(bond, n)| match self.mapping[*n] {
+            Some(target_n) => self.target.atoms[target_atom].bonds.iter().any(|b| {
+                let target_bond = self.target.bonds[*b];
+                (target_bond.a == target_n || target_bond.b == target_n) && self.query.bond_matches(*bond, self.target, *b)
+            }),
+            None => true
+        });
+        if !bonds {
+            return false;
+        }
+        // Look-ahead: the neighbours in the terminal set of the query go to the terminal set of the
+        // target, and the target must have room for all the neighbours that are not mapped yet
+        let (mut query_terminal, mut query_rest) = (0, 0);
+        for (_, n) in self.neighbours[atom].iter().filter(|(_, n)| self.mapping[*n].is_none()) {
+            if self.query_terminal[*n] > 0 { query_terminal += 1 } else { query_rest += 1 }
+        }
+        let (mut target_terminal, mut target_rest) = (0, 0);
+        for n in self.target_neighbours(target_atom).filter(|n| !self.used[*n]) {
+            if self.target_terminal[n] > 0 { target_terminal += 1 } else { target_rest += 1 }
+        }
+        query_terminal <= target_terminal && query_terminal + query_rest <= target_terminal + target_rest
+    }
+
+    /// Map the atoms, or undo it, updating the terminal sets
+    fn map(&mut self, atom: usize, target_atom: usize, depth: usize, mapped: bool) {
+        self.mapping[atom] = if mapped { Some(target_atom) } else { None };
+        self.used[target_atom] = mapped;
+        let (from, to) = if mapped { (0, depth + 1) } else { (depth + 1, 0) };
+        for (_, n) in self.neighbours[atom].iter() {
+            if self.query_terminal[*n] == from {
+                self.query_terminal[*n] = to;
+            }
+        }
+        for n in self.target_neighbours(target_atom) {
+            if self.target_terminal[n] == from {
+                self.target_terminal[n] = to;
+            }
+        }
+    }
+
+    fn extend(&mut self, depth: usize) {
+        if depth == self.order.len() {
+            self.matches.push(self.mapping.clone());
+            return;
+        }
+        let (atom, anchor) = self.order[depth];
+        let candidates: Vec<usize> = match anchor {
+            Some(anchor) => {
+                self.target_neighbours(self.mapping[anchor].unwrap()).collect()
+            },
+            None if depth == 0 && self.root.is_some() => vec![self.root.unwrap()],
+            None => (0..self.target.atoms.len()).collect()
+        };
+        for target_atom in candidates {
+            if !self.feasible(atom, target_atom) {
+                continue;
+            }
+            self.map(atom, target_atom, depth, true);
+            self.extend(depth + 1);
+            self.map(atom, target_atom, depth, false);
+            if self.first_only && !self.matches.is_empty() {
+                return;
+            }
+        }
+    }
+}
+
+/// All the ways of finding the query in the target, every match gives the target atom of each
+/// query atom (`None` for the ignored ones)
+pub(crate) fn find<Q: Query>(query: &Q, target: &Molecule, first_only: bool) -> Vec<Vec<Option<usize>>> {
+    let mut search = Search::new(query, target, first_only, None);
+    if search.order.is_empty() || search.order.len() > target.atoms.len() {
+        return Vec::new();
+    }
+    search.extend(0);
+    search.matches
+}
+
+/// Check if the query can be found with the given query atom on the given atom of the target
+pub(crate) fn matches_at<Q: Query>(query: &Q, query_atom: usize, target: &Molecule, target_atom: usize) -> bool {
+    let mut search = Search::new(query, target, true, Some(query_atom));
+    if query.is_ignored(query_atom) || search.order.len() > target.atoms.len() {
+        return false;
+    }
+    search.root = Some(target_atom);
+    search.extend(0);
+    !search.matches.is_empty()
+}
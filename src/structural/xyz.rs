@@ -0,0 +1,117 @@
+use std::io::{self, BufRead, BufReader, Read, Write};
+
+use ptable::Element;
+use super::{AtomAndBondI, Molecule, ParserError};
+use crate::*;
+
+/// A frame of an XYZ file: the number of atoms, a comment line and a line for every atom with its
+/// element (symbol or atomic number) and its coordinates in Å. The molecule has no bonds, they can
+/// be found with `Molecule::perceive_bonds`
+#[derive(Debug)]
+pub struct XyzFile {
+    comment: String,
+    molecule: Molecule,
+}
+
+impl XyzFile {
+    pub fn new(comment: String, molecule: Molecule) -> XyzFile {
+        XyzFile { comment, molecule }
+    }
+
+    /// Parse the first frame of the file
+    pub fn parse<R: Read>(reader: R) -> Result<XyzFile, ParserError> {
+        let mut reader = BufReader::new(reader).lines();
+        macro_rules! get_or_eof {
+            () => {{
+                match reader.next() {
+                    Some(line) => line?,
+                    None => {
+                        return Err(ParserError::UnexpectedEof);
+                    }
+                }
+            }};
+        }
+        let atoms_count: usize = get_or_eof!().trim().parse()?;
+        let comment = get_or_eof!();
+        let mut molecule = Molecule { atoms: Vec::with_capacity(atoms_count), bonds: Vec::new(),
+            coords: Some(Vec::with_capacity(atoms_count)) };
+        for _ in 0..atoms_count {
+            let line = get_or_eof!();
+            let mut fields = line.split_whitespace();
+            let symbol = match fields.next() {
+                Some(symbol) => symbol,
+                None => {
+                    return Err(ParserError::Syntax);
+                }
+            };
+            let (element, wildcard) = match symbol.parse::<usize>() {
+                Ok(0) => (Element::Hydrogen, true),
+                Ok(number) => match Element::from_atomic_number(number) {
+                    Some(e) => (e, false),
+                    None => {
+                        return Err(ParserError::Syntax);
+                    }
+                },
+                // Dummy atoms are wildcards
+                Err(_) if symbol == "X" || symbol == "Xx" => (Element::Hydrogen, true),
+                Err(_) => match Element::from_symbol(symbol) {
+                    Some(e) => (e, false),
+                    None => {
+                        return Err(ParserError::Syntax);
+                    }
+                }
+            };
+            let mut coordinates = [0.0; 3];
+            for c in coordinates.iter_mut() {
+                *c = match fields.next() {
+                    Some(value) => value.parse()?,
+                    None => {
+                        return Err(ParserError::Syntax);
+                    }
+                };
+            }
+            let mut atom = AtomAndBondI::new(Isotope::from(element), Vec::new());
+            atom.wildcard = wildcard;
+            molecule.atoms.push(atom);
+            molecule.coords.as_mut().unwrap().push(Point::new(coordinates[0], coordinates[1], coordinates[2]));
+        }
+        Ok(XyzFile { comment, molecule })
+    }
+
+    /// Write the frame, the atoms without coordinates go on the origin
+    pub fn write<W: Write>(&self, mut writer: W) -> io::Result<()> {
+        write_xyz(&mut writer, &self.comment, &self.molecule)
+    }
+
+    pub fn comment(&self) -> &str {
+        &self.comment
+    }
+
+    pub fn molecule(&self) -> &Molecule {
+        &self.molecule
+    }
+
+    pub fn molecule_mut(&mut self) -> &mut Molecule {
+        &mut self.molecule
+    }
+
+    pub fn into_molecule(self) -> Molecule {
+        self.molecule
+    }
+
+    pub fn set_comment(&mut self, comment: String) {
+        self.comment = comment;
+    }
+}
+
+pub(crate) fn write_xyz<W: Write>(writer: &mut W, comment: &str, molecule: &Molecule) -> io::Result<()> {
+    writeln!(writer, "{}", molecule.atoms.len())?;
+    // The comment is a single line
+    writeln!(writer, "{}", comment.lines().next().unwrap_or(""))?;
+    for (id, atom) in molecule.atoms.iter().enumerate() {
+        let point = molecule.coords.as_ref().and_then(|c| c.get(id)).copied().unwrap_or_else(|| Point::new(0.0, 0.0, 0.0));
+        let symbol = if atom.wildcard { "X" } else { atom.get_element().get_symbol() };
+        writeln!(writer, "{:<2} {:12.6} {:12.6} {:12.6}", symbol, point.x, point.y, point.z)?;
+    }
+    Ok(())
+}